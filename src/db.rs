@@ -0,0 +1,302 @@
+use bitcoin::secp256k1::PublicKey;
+use fedimint_core::config::FederationId;
+use fedimint_core::core::OperationId;
+use fedimint_core::encoding::{Decodable, Encodable};
+use fedimint_core::{impl_db_lookup, impl_db_record, Amount};
+use fedimint_ln_client::PayType;
+use fedimint_mint_client::OOBNotes;
+
+use crate::router::handlers::cashu::check::ProofRecord;
+use crate::router::handlers::cashu::melt::quote::MeltStatus;
+use crate::router::handlers::cashu::mint::quote::MintQuoteState;
+use crate::router::handlers::cashu::{Method, Unit};
+
+/// Prefixes of the records fedimint-http keeps in the client databases. They
+/// start at 0xc0 to stay clear of the prefixes used by the fedimint client.
+#[repr(u8)]
+#[derive(Clone, Debug)]
+pub enum DbKeyPrefix {
+    MintQuote = 0xc0,
+    MintQuoteIssued = 0xc1,
+    MeltQuote = 0xc2,
+    ProofState = 0xc3,
+    IssuedOutput = 0xc4,
+    PaymentRequest = 0xc5,
+    VoucherBatch = 0xc6,
+    WithdrawLink = 0xc7,
+    MintQuoteState = 0xc8,
+    MeltStatus = 0xc9,
+}
+
+impl std::fmt::Display for DbKeyPrefix {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        write!(f, "{self:?}")
+    }
+}
+
+/// A NUT-04 mint quote, keyed by the operation id of the invoice or deposit
+/// address the wallet has to pay
+#[derive(Debug, Clone, Copy, Encodable, Decodable, Eq, PartialEq, Hash)]
+pub struct MintQuoteKey(pub OperationId);
+
+#[derive(Debug, Encodable, Decodable)]
+pub struct MintQuoteKeyPrefix;
+
+#[derive(Debug, Clone, Eq, PartialEq, Encodable, Decodable)]
+pub struct MintQuote {
+    pub method: Method,
+    pub unit: Unit,
+    pub amount: Amount,
+    /// The bolt11 invoice or bitcoin address the wallet has to pay
+    pub request: String,
+    /// Unix timestamp after which an unpaid quote can no longer be paid
+    pub expiry: u64,
+    pub federation_id: FederationId,
+}
+
+impl_db_record!(
+    key = MintQuoteKey,
+    value = MintQuote,
+    db_prefix = DbKeyPrefix::MintQuote,
+);
+
+impl_db_lookup!(key = MintQuoteKey, query_prefix = MintQuoteKeyPrefix);
+
+/// Marks a paid mint quote as issued, the value is the operation id of the
+/// spend that handed the notes to the wallet
+#[derive(Debug, Clone, Copy, Encodable, Decodable, Eq, PartialEq, Hash)]
+pub struct MintQuoteIssuedKey(pub OperationId);
+
+impl_db_record!(
+    key = MintQuoteIssuedKey,
+    value = OperationId,
+    db_prefix = DbKeyPrefix::MintQuoteIssued,
+    notify_on_modify = true,
+);
+
+/// Latest payment state of a mint quote, recorded from the lightning receive
+/// or deposit operation backing it. Quotes without a record are unpaid.
+#[derive(Debug, Clone, Copy, Encodable, Decodable, Eq, PartialEq, Hash)]
+pub struct MintQuoteStateKey(pub OperationId);
+
+impl_db_record!(
+    key = MintQuoteStateKey,
+    value = MintQuoteState,
+    db_prefix = DbKeyPrefix::MintQuoteState,
+    notify_on_modify = true,
+);
+
+/// A NUT-05 melt quote, keyed by a random id handed to the wallet
+#[derive(Debug, Clone, Copy, Encodable, Decodable, Eq, PartialEq, Hash)]
+pub struct MeltQuoteKey(pub OperationId);
+
+#[derive(Debug, Encodable, Decodable)]
+pub struct MeltQuoteKeyPrefix;
+
+#[derive(Debug, Clone, Encodable, Decodable)]
+pub struct MeltQuote {
+    pub method: Method,
+    pub unit: Unit,
+    /// The bolt11 invoice or bitcoin address to pay
+    pub request: String,
+    pub amount: Amount,
+    pub fee_reserve: Amount,
+    /// Unix timestamp after which the quote can no longer be executed
+    pub expiry: u64,
+    pub federation_id: FederationId,
+    /// Reissue of the wallet's inputs, set once the quote has been funded
+    pub funding: Option<MeltFunding>,
+    /// The latest payment attempt, a failed attempt can be retried without
+    /// funding the quote again
+    pub payment: Option<MeltPayment>,
+    /// Set while a request funds and pays the quote. Claimed in a transaction
+    /// that fails on conflict, so concurrent requests never pay twice.
+    pub executing: bool,
+    /// Fee charged by the latest payment attempt
+    pub fee_paid: Amount,
+    /// NUT-08 change handed back once the quote is paid: the inputs minus the
+    /// amount and the fee actually charged. Kept so that every later request
+    /// returns the same notes.
+    pub change: Option<OOBNotes>,
+}
+
+#[derive(Debug, Clone, Copy, Eq, PartialEq, Encodable, Decodable)]
+pub struct MeltFunding {
+    pub operation_id: OperationId,
+    pub amount: Amount,
+}
+
+#[derive(Debug, Clone, Copy, Eq, PartialEq, Encodable, Decodable)]
+pub enum MeltPayment {
+    Bolt11(PayType),
+    Onchain(OperationId),
+}
+
+impl MeltPayment {
+    pub fn operation_id(&self) -> OperationId {
+        match self {
+            MeltPayment::Bolt11(payment_type) => payment_type.operation_id(),
+            MeltPayment::Onchain(operation_id) => *operation_id,
+        }
+    }
+}
+
+impl_db_record!(
+    key = MeltQuoteKey,
+    value = MeltQuote,
+    db_prefix = DbKeyPrefix::MeltQuote,
+    notify_on_modify = true,
+);
+
+impl_db_lookup!(key = MeltQuoteKey, query_prefix = MeltQuoteKeyPrefix);
+
+/// Latest state of a melt quote's payment attempt, keyed by the operation id
+/// of the payment. Attempts without a record are still pending.
+#[derive(Debug, Clone, Copy, Encodable, Decodable, Eq, PartialEq, Hash)]
+pub struct MeltStatusKey(pub OperationId);
+
+impl_db_record!(
+    key = MeltStatusKey,
+    value = MeltStatus,
+    db_prefix = DbKeyPrefix::MeltStatus,
+    notify_on_modify = true,
+);
+
+/// A note this server handed out, checked or redeemed, keyed by the note's
+/// `Y` value so that NUT-07 checks can find its nonce
+#[derive(Debug, Clone, Copy, Encodable, Decodable, Eq, PartialEq, Hash)]
+pub struct ProofStateKey(pub PublicKey);
+
+impl_db_record!(
+    key = ProofStateKey,
+    value = ProofRecord,
+    db_prefix = DbKeyPrefix::ProofState,
+    notify_on_modify = true,
+);
+
+/// The signature of a note handed out for one of the blinded messages a wallet
+/// sent along with a mint or melt request, keyed by the blinded message `B_`.
+/// Lets wallets recover their signatures through NUT-09 restore; the note's
+/// spend key is not kept.
+#[derive(Debug, Clone, Copy, Encodable, Decodable, Eq, PartialEq, Hash)]
+pub struct IssuedOutputKey(pub PublicKey);
+
+#[derive(Debug, Clone, Encodable, Decodable)]
+pub struct IssuedOutput {
+    pub keyset_id: String,
+    /// Amount of the note in the keyset's unit
+    pub amount: u64,
+    /// Hex encoded signature of the note
+    pub signature: String,
+}
+
+impl_db_record!(
+    key = IssuedOutputKey,
+    value = IssuedOutput,
+    db_prefix = DbKeyPrefix::IssuedOutput,
+);
+
+/// A NUT-18 payment request created by this server, keyed by its random id.
+/// Kept in the database of the first federation the request accepts.
+#[derive(Debug, Clone, Copy, Encodable, Decodable, Eq, PartialEq, Hash)]
+pub struct PaymentRequestKey(pub OperationId);
+
+#[derive(Debug, Clone, Encodable, Decodable)]
+pub struct PaymentRequest {
+    /// Requested amount in `unit`, any amount is accepted when unset
+    pub amount: Option<u64>,
+    pub unit: Unit,
+    pub description: Option<String>,
+    pub single_use: bool,
+    /// Federations whose notes are accepted as payment
+    pub federation_ids: Vec<FederationId>,
+    pub received: Amount,
+    /// Reissue operations of the payments received so far
+    pub payments: Vec<OperationId>,
+    /// Payments being received, counted before their notes are reissued so
+    /// that a single use request is never paid twice
+    pub receiving: u64,
+}
+
+impl PaymentRequest {
+    pub fn is_fulfilled(&self) -> bool {
+        !self.payments.is_empty()
+    }
+}
+
+impl_db_record!(
+    key = PaymentRequestKey,
+    value = PaymentRequest,
+    db_prefix = DbKeyPrefix::PaymentRequest,
+);
+
+/// A batch of fixed-value vouchers, keyed by a random batch id
+#[derive(Debug, Clone, Copy, Encodable, Decodable, Eq, PartialEq, Hash)]
+pub struct VoucherBatchKey(pub OperationId);
+
+#[derive(Debug, Encodable, Decodable)]
+pub struct VoucherBatchKeyPrefix;
+
+#[derive(Debug, Clone, Encodable, Decodable)]
+pub struct VoucherBatch {
+    /// Value of each voucher
+    pub amount: Amount,
+    pub description: Option<String>,
+    /// Unix timestamp of the batch
+    pub created_at: u64,
+    /// Unix timestamp after which unredeemed vouchers can be reclaimed
+    pub deadline: u64,
+    /// Out-of-band spend operations of the vouchers
+    pub vouchers: Vec<OperationId>,
+}
+
+impl_db_record!(
+    key = VoucherBatchKey,
+    value = VoucherBatch,
+    db_prefix = DbKeyPrefix::VoucherBatch,
+);
+
+impl_db_lookup!(key = VoucherBatchKey, query_prefix = VoucherBatchKeyPrefix);
+
+/// An LNURL-withdraw link, keyed by its random `k1` secret. Kept in the
+/// database of the federation it is paid out from.
+#[derive(Debug, Clone, Copy, Encodable, Decodable, Eq, PartialEq, Hash)]
+pub struct WithdrawLinkKey(pub OperationId);
+
+#[derive(Debug, Encodable, Decodable)]
+pub struct WithdrawLinkKeyPrefix;
+
+#[derive(Debug, Clone, Encodable, Decodable)]
+pub struct WithdrawLink {
+    pub min_withdrawable: Amount,
+    pub max_withdrawable: Amount,
+    pub description: String,
+    /// Unix timestamp of the link
+    pub created_at: u64,
+    /// Unix timestamp after which the link can no longer be used
+    pub expiry: u64,
+    /// Withdrawals the link allows, 1 for single use links
+    pub uses: u64,
+    /// Withdrawals started so far, counted before the invoice is paid and
+    /// given back if the payment fails
+    pub used: u64,
+    /// Payment operations of the withdrawals that succeeded
+    pub payments: Vec<OperationId>,
+    /// Payments still in flight, followed until they succeed or fail
+    pub pending: Vec<PayType>,
+    pub revoked: bool,
+}
+
+impl WithdrawLink {
+    pub fn is_active(&self, now: u64) -> bool {
+        !self.revoked && self.used < self.uses && now < self.expiry
+    }
+}
+
+impl_db_record!(
+    key = WithdrawLinkKey,
+    value = WithdrawLink,
+    db_prefix = DbKeyPrefix::WithdrawLink,
+);
+
+impl_db_lookup!(key = WithdrawLinkKey, query_prefix = WithdrawLinkKeyPrefix);
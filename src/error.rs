@@ -1,9 +1,11 @@
 use std::fmt;
+use std::sync::Arc;
 
 use axum::http::StatusCode;
 use axum::response::{IntoResponse, Response};
 use serde_json::json;
 
+#[derive(Debug)]
 pub struct AppError {
     pub error: anyhow::Error,
     pub status: StatusCode,
@@ -18,10 +20,20 @@ impl AppError {
     }
 }
 
+/// The error a response was created from, kept as a response extension so
+/// middleware can render it in an API specific format
+#[derive(Clone)]
+pub struct ErrorSource(pub Arc<anyhow::Error>);
+
 // Tell axum how to convert `AppError` into a response.
 impl IntoResponse for AppError {
     fn into_response(self) -> Response {
-        (self.status, format!("Something went wrong: {}", self.error)).into_response()
+        let mut response =
+            (self.status, format!("Something went wrong: {}", self.error)).into_response();
+        response
+            .extensions_mut()
+            .insert(ErrorSource(Arc::new(self.error)));
+        response
     }
 }
 
@@ -2,6 +2,7 @@ use std::path::PathBuf;
 use std::str::FromStr;
 
 use anyhow::Result;
+use axum::extract::Request;
 use axum::http::Method;
 use fedimint_core::api::InviteCode;
 use router::ws::websocket_handler;
@@ -10,18 +11,21 @@ use tower_http::trace::TraceLayer;
 use tracing::info;
 
 mod config;
+mod db;
 mod error;
+mod notes;
 mod router;
 mod state;
 mod utils;
 
 use axum::routing::{get, post};
-use axum::Router;
+use axum::{Router, ServiceExt};
 use axum_otel_metrics::HttpMetricsLayerBuilder;
 use clap::{Parser, Subcommand, ValueEnum};
 use router::handlers::*;
 use state::AppState;
 // use tower_http::cors::{Any, CorsLayer};
+use tower::Layer;
 use tower_http::validate_request::ValidateRequestHeaderLayer;
 
 #[derive(Clone, Debug, ValueEnum)]
@@ -64,6 +68,15 @@ struct Cli {
     /// Mode of operation
     #[clap(long, default_value = "default")]
     mode: Mode,
+
+    #[clap(flatten)]
+    cashu: cashu::CashuConfig,
+
+    #[clap(flatten)]
+    lnurl: router::handlers::lnurl::LnurlConfig,
+
+    #[clap(flatten)]
+    consolidate: fedimint::mint::consolidate::ConsolidateConfig,
 }
 
 // const PID_FILE: &str = "/tmp/fedimint_http.pid";
@@ -74,7 +87,7 @@ async fn main() -> Result<()> {
     dotenv::dotenv().ok();
 
     let cli: Cli = Cli::parse();
-    let mut state = AppState::new(cli.fm_db_path).await?;
+    let mut state = AppState::new(cli.fm_db_path, cli.cashu, cli.lnurl).await?;
     match InviteCode::from_str(&cli.federation_invite_code) {
         Ok(invite_code) => {
             let federation_id = state.multimint.register_new(invite_code, true).await?;
@@ -88,22 +101,32 @@ async fn main() -> Result<()> {
         }
     }
 
+    cashu::resume_followers(&state).await;
+    lnurl::withdraw::resume_withdrawals(&state).await;
+    fedimint::mint::consolidate::spawn_consolidate_policy(state.clone(), cli.consolidate);
+
     let app = match cli.mode {
         Mode::Fedimint => Router::new()
             .nest("/fedimint/v2", fedimint_v2_rest())
-            .with_state(state)
+            .with_state(state.clone())
             .layer(ValidateRequestHeaderLayer::bearer(&cli.password)),
         Mode::Cashu => Router::new()
-            .nest("/cashu/v1", cashu_v1_rest())
-            .with_state(state)
+            .nest(cashu::CASHU_V1_PATH, cashu_v1_rest())
+            .with_state(state.clone())
             .layer(ValidateRequestHeaderLayer::bearer(&cli.password)),
         Mode::Ws => Router::new()
             .route("/fedimint/v2/ws", get(websocket_handler))
-            .with_state(state)
+            .with_state(state.clone())
             .layer(ValidateRequestHeaderLayer::bearer(&cli.password)),
-        Mode::Default => create_default_router(state, &cli.password).await?,
+        Mode::Default => create_default_router(state.clone(), &cli.password).await?,
     };
 
+    // LNURL endpoints and the transport of payment requests are called by
+    // other wallets, so they are served without authentication
+    let app = app
+        .merge(lnurl_rest().with_state(state.clone()))
+        .merge(cashu_v1_public().with_state(state.clone()));
+
     let cors = CorsLayer::new()
         // allow `GET` and `POST` when accessing the resource
         .allow_methods([Method::GET, Method::POST])
@@ -125,11 +148,16 @@ async fn main() -> Result<()> {
         .merge(metrics.routes())
         .layer(metrics);
 
+    // Federation scoped Cashu routes are rewritten before routing
+    let app = axum::middleware::from_fn_with_state(state, cashu::scope_federation).layer(app);
+
     let listener = tokio::net::TcpListener::bind(format!("{}:{}", &cli.domain, &cli.port))
         .await
         .unwrap();
     info!("fedimint-http Listening on {}", &cli.port);
-    axum::serve(listener, app).await.unwrap();
+    axum::serve(listener, ServiceExt::<Request>::into_make_service(app))
+        .await
+        .unwrap();
 
     Ok(())
 }
@@ -143,7 +171,7 @@ pub async fn create_default_router(state: AppState, password: &str) -> Result<Ro
     let app = Router::new()
         .route("/fedimint/v2/ws", get(websocket_handler))
         .nest("/fedimint/v2", fedimint_v2_rest())
-        .nest("/cashu/v1", cashu_v1_rest())
+        .nest(cashu::CASHU_V1_PATH, cashu_v1_rest())
         .with_state(state)
         // .layer(cors)
         .layer(ValidateRequestHeaderLayer::bearer(password));
@@ -167,14 +195,41 @@ pub async fn create_default_router(state: AppState, password: &str) -> Result<Ro
 /// Mint related commands:
 /// - `/fedimint/v2/mint/reissue`: Reissue notes received from a third party to
 ///   avoid double spends.
+/// - `/fedimint/v2/mint/reissue-batch`: Reissue many bundles of notes across
+///   federations, with a result per bundle.
+/// - `/fedimint/v2/mint/await-reissue`: Wait for a reissue submitted with
+///   `noWait` to complete.
 /// - `/fedimint/v2/mint/spend`: Prepare notes to send to a third party as a
 ///   payment.
+/// - `/fedimint/v2/mint/spends`: List outstanding out-of-band spends.
+/// - `/fedimint/v2/mint/reclaim`: Reclaim an out-of-band spend before its
+///   timeout if the recipient has not redeemed it.
 /// - `/fedimint/v2/mint/validate`: Verifies the signatures of e-cash notes, but
 ///   *not* if they have been spent already.
+/// - `/fedimint/v2/mint/validate-offline`: Verifies the signatures of e-cash
+///   notes of any federation, fetching the mint keys of federations we have
+///   not joined with their invite code.
 /// - `/fedimint/v2/mint/split`: Splits a string containing multiple e-cash
 ///   notes (e.g. from the `spend` command) into ones that contain exactly one.
+///   Optionally packages them into bundles of requested amounts.
 /// - `/fedimint/v2/mint/combine`: Combines two or more serialized e-cash notes
 ///   strings.
+/// - `/fedimint/v2/mint/consolidate`: Reissue surplus notes to reduce the note
+///   count, reporting the estimated denominations and fee first with `dryRun`.
+/// - `/fedimint/v2/mint/vouchers`: Create a batch of fixed-value vouchers,
+///   optionally as SVG QR codes.
+/// - `/fedimint/v2/mint/voucher-status`: Redemption status and totals of
+///   voucher batches.
+/// - `/fedimint/v2/mint/reclaim-vouchers`: Reclaim the unredeemed vouchers of
+///   a batch after its deadline.
+/// - `/fedimint/v2/mint/send-encrypted`: Spend notes and encrypt them to a
+///   recipient public key.
+/// - `/fedimint/v2/mint/receive-encrypted`: Decrypt notes encrypted to our
+///   receiving key and reissue them.
+/// - `/fedimint/v2/mint/receiving-key`: The public key encrypted notes are
+///   received with.
+/// - `/fedimint/v2/mint/convert`: Converts e-cash between fedimint `OOBNotes`
+///   and Cashu `cashuA`/`cashuB` tokens.
 ///
 /// Lightning network related commands:
 /// - `/fedimint/v2/ln/invoice`: Create a lightning invoice to receive payment
@@ -191,13 +246,67 @@ pub async fn create_default_router(state: AppState, password: &str) -> Result<Ro
 /// - `/fedimint/v2/onchain/await-deposit`: Wait for deposit on previously
 ///   generated address.
 /// - `/fedimint/v2/onchain/withdraw`: Withdraw funds from the federation.
+///
+/// LNURL-withdraw links, claimed through the public `/lnurlw` routes:
+/// - `/fedimint/v2/lnurl/withdraw-link`: Create a withdraw link.
+/// - `/fedimint/v2/lnurl/withdraw-links`: List withdraw links.
+/// - `/fedimint/v2/lnurl/revoke-withdraw-link`: Revoke a withdraw link.
+///
+/// Bridge to ordinary Cashu mints:
+/// - `/fedimint/v2/bridge/redeem`: Redeem a token of a foreign Cashu mint into
+///   the federation by melting it to pay one of our invoices.
+/// - `/fedimint/v2/bridge/pay`: Pay a foreign Cashu mint's `bolt11` mint quote
+///   from the federation.
 fn fedimint_v2_rest() -> Router<AppState> {
     let mint_router = Router::new()
         .route("/reissue", post(fedimint::mint::reissue::handle_rest))
+        .route(
+            "/reissue-batch",
+            post(fedimint::mint::reissue_batch::handle_rest),
+        )
+        .route(
+            "/await-reissue",
+            post(fedimint::mint::await_reissue::handle_rest),
+        )
         .route("/spend", post(fedimint::mint::spend::handle_rest))
+        .route("/spends", post(fedimint::mint::spends::handle_rest))
+        .route("/reclaim", post(fedimint::mint::reclaim::handle_rest))
         .route("/validate", post(fedimint::mint::validate::handle_rest))
+        .route(
+            "/validate-offline",
+            post(fedimint::mint::validate_offline::handle_rest),
+        )
         .route("/split", post(fedimint::mint::split::handle_rest))
-        .route("/combine", post(fedimint::mint::combine::handle_rest));
+        .route("/combine", post(fedimint::mint::combine::handle_rest))
+        .route(
+            "/consolidate",
+            post(fedimint::mint::consolidate::handle_rest),
+        )
+        .route(
+            "/vouchers",
+            post(fedimint::mint::vouchers::create::handle_rest),
+        )
+        .route(
+            "/voucher-status",
+            post(fedimint::mint::vouchers::status::handle_rest),
+        )
+        .route(
+            "/reclaim-vouchers",
+            post(fedimint::mint::vouchers::reclaim::handle_rest),
+        )
+        .route(
+            "/send-encrypted",
+            post(fedimint::mint::encrypted::send::handle_rest),
+        )
+        .route(
+            "/receive-encrypted",
+            post(fedimint::mint::encrypted::receive::handle_rest),
+        )
+        .route(
+            "/receiving-key",
+            post(fedimint::mint::encrypted::key::handle_rest),
+        )
+        .route("/convert", post(fedimint::mint::convert::handle_rest));
 
     let ln_router = Router::new()
         .route("/invoice", post(fedimint::ln::invoice::handle_rest))
@@ -249,41 +358,137 @@ fn fedimint_v2_rest() -> Router<AppState> {
         .route("/module", post(fedimint::admin::module::handle_rest))
         .route("/config", get(fedimint::admin::config::handle_rest));
 
+    let bridge_router = Router::new()
+        .route("/redeem", post(fedimint::bridge::redeem::handle_rest))
+        .route("/pay", post(fedimint::bridge::pay::handle_rest));
+
+    let lnurl_router = Router::new()
+        .route(
+            "/withdraw-link",
+            post(router::handlers::lnurl::withdraw::create::handle_rest),
+        )
+        .route(
+            "/withdraw-links",
+            post(router::handlers::lnurl::withdraw::list::handle_rest),
+        )
+        .route(
+            "/revoke-withdraw-link",
+            post(router::handlers::lnurl::withdraw::revoke::handle_rest),
+        );
+
     Router::new()
         .nest("/admin", admin_router)
+        .nest("/bridge", bridge_router)
+        .nest("/lnurl", lnurl_router)
         .nest("/mint", mint_router)
         .nest("/ln", ln_router)
         .nest("/wallet", wallet_router)
 }
 
+/// Implements the public LNURL routes:
+///
+/// LUD-06 payRequest, LUD-12 comments and LUD-16 Lightning Addresses for the
+/// usernames in `LNURL_USERS`, callbacks need `LNURL_PUBLIC_URL`
+/// - `/.well-known/lnurlp/{username}`
+/// - `/lnurlp/{username}/callback`
+///
+/// LUD-03 withdrawRequest for the links created through the admin API
+/// - `/lnurlw/{k1}`
+/// - `/lnurlw/callback`
+fn lnurl_rest() -> Router<AppState> {
+    Router::new()
+        .route(
+            "/.well-known/lnurlp/:username",
+            get(router::handlers::lnurl::pay::handle_pay_request),
+        )
+        .route(
+            "/lnurlp/:username/callback",
+            get(router::handlers::lnurl::pay::handle_callback),
+        )
+        .route(
+            "/lnurlw/callback",
+            get(router::handlers::lnurl::withdraw::handle_callback),
+        )
+        .route(
+            "/lnurlw/:k1",
+            get(router::handlers::lnurl::withdraw::handle_withdraw_request),
+        )
+        .layer(axum::middleware::from_fn(
+            router::handlers::lnurl::lnurl_errors,
+        ))
+}
+
+/// Cashu V1 routes called by other wallets, served without authentication:
+///
+/// NUT-18 Payment Requests
+/// - `/cashu/v1/payment-request/receive`: `post` transport of the created
+///   payment requests
+fn cashu_v1_public() -> Router<AppState> {
+    let routes = Router::new()
+        .route(
+            "/payment-request/receive",
+            post(cashu::payment_request::handle_receive),
+        )
+        .layer(axum::middleware::from_fn(cashu::error::nut00_errors));
+    Router::new().nest(cashu::CASHU_V1_PATH, routes)
+}
+
 /// Implements Cashu V1 API Routes:
 ///
+/// Every joined federation is a separate mint under
+/// `/cashu/v1/{federation_id}`, where the federation id can be shortened to
+/// its 8 character prefix. The unprefixed routes below serve the default
+/// federation, or the one selected by a `federationId` request field.
+///
 /// REQUIRED
 /// NUT-01 Mint Public Key Exchange && NUT-02 Keysets and Keyset IDs
 /// - `/cashu/v1/keys`
 /// - `/cashu/v1/keys/{keyset_id}`
 /// - `/cashu/v1/keysets`
+///
 /// NUT-03 Swap Tokens (Equivalent to `reissue` command)
 /// - `/cashu/v1/swap`
+///
 /// NUT-04 Mint Tokens: supports `bolt11` and `onchain` methods
 /// - `/cashu/v1/mint/quote/{method}`
 /// - `/cashu/v1/mint/quote/{method}/{quote_id}`
 /// - `/cashu/v1/mint/{method}`
+///
 /// NUT-05 Melting Tokens: supports `bolt11` and `onchain` methods
 /// - `/cashu/v1/melt/quote/{method}`
 /// - `/cashu/v1/melt/quote/{method}/{quote_id}`
 /// - `/cashu/v1/melt/{method}`
+///
 /// NUT-06 Mint Information
 /// - `/cashu/v1/info`
 ///
+/// Errors are returned as NUT-00 `{"detail": ..., "code": ...}` objects.
+///
 /// OPTIONAL
 /// NUT-07 Token State Check
+/// - `/cashu/v1/checkstate`
 /// - `/cashu/v1/check`
+///
 /// NUT-08 Lightning Fee Return
 /// - Modification of NUT-05 Melt
+///
+/// NUT-09 Restore signatures, when enabled with `CASHU_RESTORE`
+/// - `/cashu/v1/restore`
+///
+/// NUT-17 WebSocket subscriptions: `bolt11_mint_quote`, `bolt11_melt_quote`
+/// and `proof_state`
+/// - `/cashu/v1/ws`
+///
+/// NUT-18 Payment Requests, created requests need `CASHU_PUBLIC_URL`
+/// - `/cashu/v1/payment-request`: Create a `creqA` payment request
+/// - `/cashu/v1/payment-request/{id}`: Status of a created payment request
+/// - `/cashu/v1/payment-request/pay`: Pay someone else's payment request
+///
 /// NUT-10 Spending Conditions
+///
 /// NUT-11 Pay to Public Key (P2PK)
 /// - Fedimint already does this
+///
 /// NUT-12 Offline Ecash Signature Validation
 /// - DLEQ in BlindedSignature for Mint to User
 fn cashu_v1_rest() -> Router<AppState> {
@@ -294,7 +499,7 @@ fn cashu_v1_rest() -> Router<AppState> {
         .route("/swap", post(cashu::swap::handle_swap))
         .route(
             "/mint/quote/:method",
-            get(cashu::mint::quote::handle_method),
+            post(cashu::mint::quote::handle_method),
         )
         .route(
             "/mint/quote/:method/:quote_id",
@@ -303,7 +508,7 @@ fn cashu_v1_rest() -> Router<AppState> {
         .route("/mint/:method", post(cashu::mint::method::handle_method))
         .route(
             "/melt/quote/:method",
-            get(cashu::melt::quote::handle_method),
+            post(cashu::melt::quote::handle_method),
         )
         .route(
             "/melt/quote/:method/:quote_id",
@@ -312,4 +517,20 @@ fn cashu_v1_rest() -> Router<AppState> {
         .route("/melt/:method", post(cashu::melt::method::handle_method))
         .route("/info", get(cashu::info::handle_info))
         .route("/check", post(cashu::check::handle_check))
+        .route("/checkstate", post(cashu::check::handle_check))
+        .route("/restore", post(cashu::restore::handle_restore))
+        .route("/ws", get(cashu::ws::handle_ws))
+        .route(
+            "/payment-request",
+            post(cashu::payment_request::handle_create),
+        )
+        .route(
+            "/payment-request/pay",
+            post(cashu::payment_request::handle_pay),
+        )
+        .route(
+            "/payment-request/:id",
+            get(cashu::payment_request::handle_status),
+        )
+        .layer(axum::middleware::from_fn(cashu::error::nut00_errors))
 }
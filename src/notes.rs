@@ -0,0 +1,202 @@
+use std::pin::pin;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use anyhow::{anyhow, ensure};
+use axum::async_trait;
+use axum::http::StatusCode;
+use fedimint_client::oplog::OperationLogEntry;
+use fedimint_client::ClientArc;
+use fedimint_core::core::OperationId;
+use fedimint_core::{Amount, TieredMulti};
+use fedimint_mint_client::{
+    MintClientModule, MintOperationMeta, NotesSelector, OOBNotes, ReissueExternalNotesState,
+    SelectNotesWithAtleastAmount, SpendableNote,
+};
+use futures_util::{Stream, StreamExt};
+use serde::{Deserialize, Serialize};
+use tracing::info;
+
+use crate::error::AppError;
+
+/// Time after which the client would reclaim notes spent to reissue them,
+/// they are reissued right away so this only matters if the reissue fails
+const INTERNAL_SPEND_TIMEOUT: Duration = Duration::from_secs(3600);
+
+/// Rounds of reissuing notes into smaller denominations before giving up on
+/// spending an exact amount
+const MAX_CHANGE_ROUNDS: usize = 3;
+
+/// Selects notes adding up to exactly the requested amount, taking the
+/// largest fitting denominations first
+pub struct SelectNotesWithExactAmount;
+
+#[async_trait]
+impl<Note: Send> NotesSelector<Note> for SelectNotesWithExactAmount {
+    async fn select_notes(
+        &self,
+        stream: impl Stream<Item = (Amount, Note)> + Send,
+        requested_amount: Amount,
+    ) -> anyhow::Result<TieredMulti<Note>> {
+        let mut stream = pin!(stream);
+        let mut pending = requested_amount;
+        let mut selected = Vec::new();
+        while let Some((amount, note)) = stream.next().await {
+            if pending == Amount::ZERO {
+                break;
+            }
+            if amount <= pending {
+                pending -= amount;
+                selected.push((amount, note));
+            }
+        }
+        ensure!(
+            pending == Amount::ZERO,
+            "Cannot select notes worth exactly {requested_amount}"
+        );
+        Ok(selected.into_iter().collect())
+    }
+}
+
+/// Extra meta of the out-of-band spends made by this server
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct SpendMeta {
+    /// Unix timestamp after which the client reclaims the notes if the
+    /// recipient has not redeemed them
+    pub deadline: u64,
+    /// Set on spends of notes the server reissues into its own wallet, which
+    /// never reach a recipient
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    pub internal: bool,
+}
+
+impl SpendMeta {
+    pub fn new(timeout: Duration) -> Self {
+        let now = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .unwrap_or_default();
+        Self {
+            deadline: (now + timeout).as_secs(),
+            internal: false,
+        }
+    }
+
+    fn internal(timeout: Duration) -> Self {
+        Self {
+            internal: true,
+            ..Self::new(timeout)
+        }
+    }
+
+    /// Meta of a spend in the operation log, `None` for spends made by other
+    /// clients of the wallet
+    pub fn of(entry: &OperationLogEntry) -> Option<Self> {
+        serde_json::from_value(entry.meta::<MintOperationMeta>().extra_meta).ok()
+    }
+}
+
+/// Spends notes worth exactly `amount`. If no combination of the wallet's
+/// notes adds up to the amount, the smallest notes covering it are reissued
+/// into smaller denominations first. Returns the spend together with the
+/// change kept from the reissued notes.
+pub async fn spend_exact_with_change(
+    client: &ClientArc,
+    amount: Amount,
+    timeout: Duration,
+) -> Result<(OperationId, OOBNotes, Amount), AppError> {
+    let mint_module = client.get_first_module::<MintClientModule>();
+    let mut change = Amount::ZERO;
+    for round in 0..=MAX_CHANGE_ROUNDS {
+        let spend = mint_module
+            .spend_notes_with_selector(
+                &SelectNotesWithExactAmount,
+                amount,
+                timeout,
+                SpendMeta::new(timeout),
+            )
+            .await;
+        match spend {
+            Ok((operation_id, notes)) => return Ok((operation_id, notes, change)),
+            Err(_) if round < MAX_CHANGE_ROUNDS => {}
+            Err(_) => break,
+        }
+
+        let (_, reissued) =
+            reissue_own_notes(client, &SelectNotesWithAtleastAmount, amount).await?;
+        change = reissued - amount;
+    }
+
+    // The reissued notes stay in the wallet, so nothing is lost
+    Err(AppError::new(
+        StatusCode::SERVICE_UNAVAILABLE,
+        anyhow!(
+            "Cannot spend exactly {} with the available denominations, retry later",
+            amount
+        ),
+    ))
+}
+
+/// Reissues notes of the wallet picked by `selector` into the denominations
+/// the client issues, returning the reissue operation and the amount
+/// reissued. The notes are spent out of band first, that spend is marked as
+/// internal so it is neither listed nor reclaimable like the spends handed
+/// to recipients.
+pub async fn reissue_own_notes(
+    client: &ClientArc,
+    selector: &impl NotesSelector<SpendableNote>,
+    amount: Amount,
+) -> Result<(OperationId, Amount), AppError> {
+    let mint_module = client.get_first_module::<MintClientModule>();
+    let (spend_id, notes) = mint_module
+        .spend_notes_with_selector(
+            selector,
+            amount,
+            INTERNAL_SPEND_TIMEOUT,
+            SpendMeta::internal(INTERNAL_SPEND_TIMEOUT),
+        )
+        .await?;
+    let reissued = notes.total_amount();
+    info!(
+        "Reissuing {} notes worth {reissued} of spend {spend_id}",
+        notes.notes().count_items()
+    );
+
+    match wait_for_reissue(client, notes).await {
+        Ok(operation_id) => Ok((operation_id, reissued)),
+        Err(e) => {
+            mint_module.try_cancel_spend_notes(spend_id).await;
+            Err(e)
+        }
+    }
+}
+
+/// Reissues the notes without tracking their proof states, for notes that
+/// came out of the client's own wallet
+pub async fn wait_for_reissue(
+    client: &ClientArc,
+    notes: OOBNotes,
+) -> Result<OperationId, AppError> {
+    let operation_id = client
+        .get_first_module::<MintClientModule>()
+        .reissue_external_notes(notes, ())
+        .await?;
+    await_reissue(client, operation_id).await?;
+    Ok(operation_id)
+}
+
+/// Waits for a reissue operation to complete
+pub async fn await_reissue(client: &ClientArc, operation_id: OperationId) -> Result<(), AppError> {
+    let mut updates = client
+        .get_first_module::<MintClientModule>()
+        .subscribe_reissue_external_notes(operation_id)
+        .await?
+        .into_stream();
+
+    while let Some(update) = updates.next().await {
+        info!("Update: {update:?}");
+        if let ReissueExternalNotesState::Failed(e) = update {
+            return Err(AppError::new(StatusCode::INTERNAL_SERVER_ERROR, anyhow!(e)));
+        }
+    }
+
+    Ok(())
+}
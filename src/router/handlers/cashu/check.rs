@@ -1,34 +1,611 @@
+use std::collections::HashSet;
+use std::future::Future;
+use std::str::FromStr;
+
+use anyhow::anyhow;
 use axum::extract::State;
+use axum::http::StatusCode;
 use axum::Json;
-use fedimint_core::Amount;
-use fedimint_mint_client::{MintClientModule, OOBNotes};
+use bitcoin::secp256k1::PublicKey;
+use bitcoin_hashes::{sha256, Hash};
+use fedimint_client::ClientArc;
+use fedimint_core::api::GlobalFederationApi;
+use fedimint_core::core::{DynInput, ModuleInstanceId, OperationId};
+use fedimint_core::db::IDatabaseTransactionOpsCoreTyped;
+use fedimint_core::encoding::{Decodable, Encodable};
+use fedimint_core::transaction::{Transaction, TransactionError, TransactionSignature};
+use fedimint_core::{Amount, TransactionId};
+use fedimint_mint_client::common::{MintInput, MintInputError, Nonce, Note};
+use fedimint_mint_client::{MintClientModule, OOBNotes, ReissueExternalNotesState, SpendableNote};
+use futures_util::future::try_join_all;
+use itertools::Itertools;
 use serde::{Deserialize, Serialize};
+use tracing::info;
 
+use crate::db::ProofStateKey;
 use crate::error::AppError;
+use crate::notes::await_reissue;
+use crate::router::handlers::cashu::error::CashuError;
+use crate::router::handlers::cashu::token::{note_secret, Ecash};
+use crate::router::handlers::cashu::FederationScope;
 use crate::state::AppState;
+use crate::utils::spawn_follower;
+
+/// Domain separator of the NUT-00 `hash_to_curve` function
+const HASH_TO_CURVE_DOMAIN_SEPARATOR: &[u8] = b"Secp256k1_HashToCurve_Cashu_";
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "SCREAMING_SNAKE_CASE")]
+pub enum ProofState {
+    Unspent,
+    Pending,
+    Spent,
+}
+
+/// What this server knows about a note it handed out or redeemed. Maps the
+/// note's `Y` value to the note as the federation knows it, which is what its
+/// spent notes are checked by.
+#[derive(Debug, Clone, PartialEq, Eq, Encodable, Decodable)]
+pub struct ProofRecord {
+    pub amount: Amount,
+    pub note: Note,
+    /// Reissue of the note through this server, if any
+    pub redemption: Option<Redemption>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Encodable, Decodable)]
+pub enum Redemption {
+    /// Reissue in flight, resolved from the outcome of the operation
+    Pending(OperationId),
+    Spent,
+}
 
 #[derive(Debug, Deserialize)]
 pub struct CheckRequest {
-    pub notes: OOBNotes,
+    /// `Y` values of the proofs to check, as defined by NUT-07
+    #[serde(rename = "Ys", default)]
+    pub ys: Vec<String>,
+    /// Proofs or notes to check, their signatures are verified as well
+    pub notes: Option<Ecash>,
+    pub keyset_id: Option<String>,
 }
 
 #[derive(Debug, Serialize)]
 pub struct CheckResponse {
-    pub amount_msat: Amount,
+    pub states: Vec<ProofStateResponse>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct ProofStateResponse {
+    #[serde(rename = "Y")]
+    pub y: String,
+    pub state: ProofState,
+    pub witness: Option<String>,
 }
 
 #[axum_macros::debug_handler]
 pub async fn handle_check(
+    scope: FederationScope,
     State(state): State<AppState>,
     Json(req): Json<CheckRequest>,
 ) -> Result<Json<CheckResponse>, AppError> {
-    let client = state
-        .get_client_by_prefix(&req.notes.federation_id_prefix())
-        .await?;
-    let amount_msat = client
-        .get_first_module::<MintClientModule>()
-        .validate_notes(req.notes)
+    let mut ys = req
+        .ys
+        .iter()
+        .map(|y| {
+            PublicKey::from_str(y).map_err(|e| {
+                AppError::new(StatusCode::BAD_REQUEST, anyhow!("Invalid Y {}: {}", y, e))
+            })
+        })
+        .collect::<Result<Vec<_>, _>>()?;
+
+    if let Some(notes) = req.notes {
+        let notes = notes.to_oob_notes(&state).await?;
+        let client = match &req.keyset_id {
+            Some(keyset_id) => state.get_client_by_keyset_id(keyset_id).await?.0,
+            None => {
+                state
+                    .get_client_by_prefix(&notes.federation_id_prefix())
+                    .await?
+            }
+        };
+        scope.ensure(&state, &client).await?;
+        client
+            .get_first_module::<MintClientModule>()
+            .validate_notes(notes.clone())
+            .await
+            .map_err(|e| {
+                AppError::new(
+                    StatusCode::BAD_REQUEST,
+                    CashuError::TokenNotVerified(e.to_string()),
+                )
+            })?;
+        record_notes(&client, &notes).await?;
+        ys.extend(notes_ys(&notes)?);
+    }
+
+    Ok(Json(CheckResponse {
+        states: proof_states(&scope.clients(&state).await?, &ys).await?,
+    }))
+}
+
+/// Maps a proof secret to a curve point as specified by NUT-00. Wallets use
+/// the resulting `Y` to refer to a proof without revealing its secret.
+pub fn hash_to_curve(secret: &[u8]) -> PublicKey {
+    let msg_hash = sha256::Hash::hash(&[HASH_TO_CURVE_DOMAIN_SEPARATOR, secret].concat());
+    let mut point = [0u8; 33];
+    point[0] = 0x02;
+    for counter in 0u32.. {
+        let hash = sha256::Hash::hash(&[&msg_hash[..], &counter.to_le_bytes()].concat());
+        point[1..].copy_from_slice(&hash[..]);
+        if let Ok(y) = PublicKey::from_slice(&point) {
+            return y;
+        }
+    }
+    unreachable!("about half of all x coordinates are on the curve")
+}
+
+/// Returns the `Y` value of every note, derived from the secret the note is
+/// represented with in a Cashu proof
+pub fn notes_ys(notes: &OOBNotes) -> Result<Vec<PublicKey>, AppError> {
+    notes
+        .notes()
+        .iter_items()
+        .map(|(_, note)| Ok(hash_to_curve(note_secret(note)?.as_bytes())))
+        .collect()
+}
+
+/// Looks up the state of each `Y` value. Notes redeemed through this server
+/// are pending while the reissue is in flight, all other notes this server
+/// knows of are checked against the federation's spent notes, together in
+/// one check per federation.
+///
+/// A `Y` value only identifies a note to a server that has seen the note, so
+/// notes that were never handed out or checked through this server are
+/// reported as unspent.
+pub async fn proof_states(
+    clients: &[ClientArc],
+    ys: &[PublicKey],
+) -> Result<Vec<ProofStateResponse>, AppError> {
+    let mut records = Vec::with_capacity(ys.len());
+    for y in ys {
+        let mut found = None;
+        for (idx, client) in clients.iter().enumerate() {
+            if let Some(record) = proof_record(client, *y).await? {
+                found = Some((idx, record));
+                break;
+            }
+        }
+        records.push(found);
+    }
+
+    let spent = try_join_all(clients.iter().enumerate().map(|(idx, client)| {
+        let unresolved = records
+            .iter()
+            .flatten()
+            .filter(|(client_idx, record)| *client_idx == idx && record.redemption.is_none())
+            .map(|(_, record)| (record.amount, record.note))
+            .collect::<Vec<_>>();
+        spent_nonces(client, unresolved)
+    }))
+    .await?;
+
+    Ok(ys
+        .iter()
+        .zip(records)
+        .map(|(y, record)| ProofStateResponse {
+            y: y.to_string(),
+            state: match record {
+                Some((
+                    _,
+                    ProofRecord {
+                        redemption: Some(Redemption::Spent),
+                        ..
+                    },
+                )) => ProofState::Spent,
+                Some((
+                    _,
+                    ProofRecord {
+                        redemption: Some(Redemption::Pending(_)),
+                        ..
+                    },
+                )) => ProofState::Pending,
+                Some((idx, record)) if spent[idx].contains(&record.note.nonce) => ProofState::Spent,
+                _ => ProofState::Unspent,
+            },
+            witness: None,
+        })
+        .collect())
+}
+
+/// Whether any of the notes is being or has been redeemed through the client,
+/// without asking the federation
+pub async fn is_redeemed(client: &ClientArc, notes: &OOBNotes) -> Result<bool, AppError> {
+    for y in notes_ys(notes)? {
+        let record = proof_record(client, y).await?;
+        if record.is_some_and(|record| record.redemption.is_some()) {
+            return Ok(true);
+        }
+    }
+    Ok(false)
+}
+
+/// Returns the notes as the federation knows them, without their spend keys
+pub fn federation_notes(notes: &OOBNotes) -> Vec<(Amount, Note)> {
+    notes
+        .notes()
+        .iter_items()
+        .map(|(amount, note)| (amount, federation_note(note)))
+        .collect()
+}
+
+pub fn federation_note(note: &SpendableNote) -> Note {
+    Note {
+        nonce: Nonce(note.spend_key.public_key()),
+        signature: note.signature,
+    }
+}
+
+/// Asks the federation which of the notes have been spent and returns their
+/// nonces. Fedimint 0.2 federations do not expose their set of spent notes,
+/// so this relies on `submit_transaction` validating a transaction's inputs
+/// one by one before anything else: the notes are submitted as the inputs of
+/// a probe transaction that lacks signatures and outputs, which the mint
+/// module rejects with `SpentCoin` at the first spent note and which is
+/// rejected for its missing signatures if no note is spent. The probe is
+/// never accepted, so none of the notes are spent by it.
+///
+/// All notes are probed at once and only notes in probes hitting a spent
+/// note are probed again, in halves, so checking unspent notes takes a
+/// single round trip.
+pub async fn spent_nonces(
+    client: &ClientArc,
+    notes: Vec<(Amount, Note)>,
+) -> Result<HashSet<Nonce>, AppError> {
+    let mint_instance = client.get_first_module::<MintClientModule>().id;
+    find_spent(mint_instance, notes, |transaction| async move {
+        client
+            .api()
+            .submit_transaction(transaction)
+            .await
+            .map_err(|e| {
+                AppError::new(
+                    StatusCode::SERVICE_UNAVAILABLE,
+                    anyhow!("Federation did not report whether notes are spent: {e}"),
+                )
+            })?
+            .try_into_inner(client.decoders())
+            .map_err(|e| AppError::new(StatusCode::BAD_GATEWAY, anyhow!(e)))
+    })
+    .await
+}
+
+/// Outcome of submitting a probe transaction
+enum Probe {
+    /// All notes passed the mint module's checks
+    Unspent,
+    /// One of the notes has been spent
+    Spent,
+    /// One of the notes was rejected for another reason
+    Invalid(String),
+}
+
+/// Probes the notes with `submit` as described on [`spent_nonces`]. Probes
+/// of the same round are submitted concurrently.
+async fn find_spent<F, Fut>(
+    mint_instance: ModuleInstanceId,
+    notes: Vec<(Amount, Note)>,
+    submit: F,
+) -> Result<HashSet<Nonce>, AppError>
+where
+    F: Fn(Transaction) -> Fut,
+    Fut: Future<Output = Result<Result<TransactionId, TransactionError>, AppError>>,
+{
+    // A note repeated within a probe would be reported as spent by its own
+    // first occurrence
+    let notes = notes
+        .into_iter()
+        .unique_by(|(_, note)| note.nonce)
+        .collect::<Vec<_>>();
+    let mut spent = HashSet::new();
+    let mut probes = if notes.is_empty() {
+        vec![]
+    } else {
+        vec![notes.as_slice()]
+    };
+    while !probes.is_empty() {
+        let outcomes = try_join_all(
+            probes
+                .iter()
+                .map(|notes| submit(probe_transaction(mint_instance, notes))),
+        )
         .await?;
 
-    Ok(Json(CheckResponse { amount_msat }))
+        let mut next = Vec::new();
+        for (notes, outcome) in probes.into_iter().zip(outcomes) {
+            match (probe_outcome(outcome)?, notes) {
+                (Probe::Unspent, _) => {}
+                (Probe::Spent, [(_, note)]) => {
+                    spent.insert(note.nonce);
+                }
+                (Probe::Invalid(e), [(_, note)]) => {
+                    return Err(AppError::new(
+                        StatusCode::BAD_GATEWAY,
+                        anyhow!("Federation rejected note {:?}: {}", note.nonce, e),
+                    ))
+                }
+                (_, notes) => {
+                    let (left, right) = notes.split_at(notes.len() / 2);
+                    next.extend([left, right]);
+                }
+            }
+        }
+        probes = next;
+    }
+    Ok(spent)
+}
+
+/// Builds a transaction spending the notes that no federation accepts: it has
+/// no signatures for its inputs and no outputs to balance them
+fn probe_transaction(mint_instance: ModuleInstanceId, notes: &[(Amount, Note)]) -> Transaction {
+    Transaction {
+        inputs: notes
+            .iter()
+            .map(|(amount, note)| {
+                DynInput::from_typed(mint_instance, MintInput::new_v0(*amount, *note))
+            })
+            .collect(),
+        outputs: vec![],
+        nonce: [0; 8],
+        signatures: TransactionSignature::NaiveMultisig(vec![]),
+    }
+}
+
+fn probe_outcome(outcome: Result<TransactionId, TransactionError>) -> Result<Probe, AppError> {
+    match outcome {
+        Err(TransactionError::Input(e)) => match e.as_any().downcast_ref::<MintInputError>() {
+            Some(MintInputError::SpentCoin) => Ok(Probe::Spent),
+            _ => Ok(Probe::Invalid(e.to_string())),
+        },
+        // Signatures and funding are only checked once every input passed
+        Err(_) => Ok(Probe::Unspent),
+        Ok(txid) => Err(AppError::new(
+            StatusCode::INTERNAL_SERVER_ERROR,
+            anyhow!("Federation accepted probe transaction {txid}"),
+        )),
+    }
+}
+
+/// Reads the record of a `Y` value. A pending redemption is resolved from the
+/// outcome of its reissue, which is followed in the background until it has
+/// one, e.g. after the server restarted while the reissue was in flight.
+async fn proof_record(client: &ClientArc, y: PublicKey) -> Result<Option<ProofRecord>, AppError> {
+    let record = client
+        .db()
+        .begin_transaction_nc()
+        .await
+        .get_value(&ProofStateKey(y))
+        .await;
+    let Some(ProofRecord {
+        amount,
+        note,
+        redemption: Some(Redemption::Pending(operation_id)),
+    }) = record
+    else {
+        return Ok(record);
+    };
+
+    let outcome = client
+        .operation_log()
+        .get_operation(operation_id)
+        .await
+        .and_then(|entry| entry.outcome::<ReissueExternalNotesState>());
+    let redemption = match outcome {
+        Some(ReissueExternalNotesState::Done) => Some(Redemption::Spent),
+        Some(ReissueExternalNotesState::Failed(_)) => None,
+        _ => {
+            follow_reissue(client, operation_id);
+            return Ok(record);
+        }
+    };
+
+    let resolved = ProofRecord {
+        amount,
+        note,
+        redemption,
+    };
+    let mut dbtx = client.db().begin_transaction().await;
+    dbtx.insert_entry(&ProofStateKey(y), &resolved).await;
+    dbtx.commit_tx_result().await?;
+    Ok(Some(resolved))
+}
+
+/// Drains the updates of a reissue in the background, which caches its
+/// outcome in the operation log
+fn follow_reissue(client: &ClientArc, operation_id: OperationId) {
+    let client = client.clone();
+    spawn_follower(operation_id, async move {
+        if let Err(e) = await_reissue(&client, operation_id).await {
+            info!("Reissue {operation_id} failed: {}", e.error);
+        }
+        Ok(())
+    });
+}
+
+/// Remembers the notes handed out or checked through the client, so that
+/// their `Y` values can be checked against the federation later on. Notes
+/// that already have a record keep it.
+pub async fn record_notes(client: &ClientArc, notes: &OOBNotes) -> Result<(), AppError> {
+    let mut dbtx = client.db().begin_transaction().await;
+    for (amount, note) in notes.notes().iter_items() {
+        let key = ProofStateKey(hash_to_curve(note_secret(note)?.as_bytes()));
+        if dbtx.get_value(&key).await.is_none() {
+            let record = ProofRecord {
+                amount,
+                note: federation_note(note),
+                redemption: None,
+            };
+            dbtx.insert_entry(&key, &record).await;
+        }
+    }
+    dbtx.commit_tx_result().await?;
+    Ok(())
+}
+
+/// Records the redemption of notes by the client, `None` clears it again if
+/// the redemption failed
+pub async fn set_redemption(
+    client: &ClientArc,
+    notes: &OOBNotes,
+    redemption: Option<Redemption>,
+) -> Result<(), AppError> {
+    let mut dbtx = client.db().begin_transaction().await;
+    for (amount, note) in notes.notes().iter_items() {
+        let record = ProofRecord {
+            amount,
+            note: federation_note(note),
+            redemption,
+        };
+        dbtx.insert_entry(
+            &ProofStateKey(hash_to_curve(note_secret(note)?.as_bytes())),
+            &record,
+        )
+        .await;
+    }
+    dbtx.commit_tx_result().await?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use bitcoin::secp256k1::{KeyPair, Secp256k1};
+    use bitcoin_hashes::hex::FromHex;
+    use fedimint_core::config::ConfigGenModuleParams;
+    use fedimint_core::db::mem_impl::MemDatabase;
+    use fedimint_core::db::Database;
+    use fedimint_core::module::registry::ServerModuleRegistry;
+    use fedimint_core::module::ServerModuleInit;
+    use fedimint_core::server::DynServerModule;
+    use fedimint_core::{PeerId, ServerModule};
+    use fedimint_mint_server::common::config::{MintConfig, MintGenParams, MintGenParamsConsensus};
+    use fedimint_mint_server::{Mint, MintInit};
+    use fedimint_server::consensus::process_transaction_with_dbtx;
+
+    use super::*;
+
+    const MINT_INSTANCE: ModuleInstanceId = 0;
+
+    /// Config of the mint module of a federation with a single guardian
+    fn mint_config() -> MintConfig {
+        let peer = PeerId::from(0);
+        let params = ConfigGenModuleParams::from_typed(MintGenParams {
+            local: Default::default(),
+            consensus: MintGenParamsConsensus::new(2),
+        })
+        .unwrap();
+        MintInit.trusted_dealer_gen(&[peer], &params)[&peer]
+            .to_typed()
+            .unwrap()
+    }
+
+    fn issue_note(config: &MintConfig, amount: Amount, seed: u8) -> (Amount, Note) {
+        let spend_key = KeyPair::from_seckey_slice(&Secp256k1::new(), &[seed; 32]).unwrap();
+        let nonce = Nonce(spend_key.public_key());
+        let blinding_key = tbs::BlindingKey::random();
+        let message = tbs::blind_message(nonce.to_message(), blinding_key);
+        let share = tbs::sign_blinded_msg(message, *config.private.tbs_sks.get(amount).unwrap());
+        let signature =
+            tbs::unblind_signature(blinding_key, tbs::combine_valid_shares([(0, share)], 1));
+        (amount, Note { nonce, signature })
+    }
+
+    /// Checks notes by submitting the probes to the transaction processing of
+    /// a guardian, as its `submit_transaction` endpoint does
+    async fn check(
+        modules: &ServerModuleRegistry,
+        db: &Database,
+        notes: &[(Amount, Note)],
+    ) -> Result<HashSet<Nonce>, AppError> {
+        find_spent(MINT_INSTANCE, notes.to_vec(), |transaction| async move {
+            let txid = transaction.tx_hash();
+            let mut dbtx = db.begin_transaction_nc().await;
+            dbtx.ignore_uncommitted();
+            Ok(
+                process_transaction_with_dbtx(modules.clone(), &mut dbtx, transaction)
+                    .await
+                    .map(|()| txid),
+            )
+        })
+        .await
+    }
+
+    #[tokio::test]
+    async fn probes_find_spent_notes() {
+        let config = mint_config();
+        let modules = ServerModuleRegistry::from_iter([(
+            MINT_INSTANCE,
+            MintInit::kind(),
+            DynServerModule::from(Mint::new(config.clone())),
+        )]);
+        let db = Database::new(MemDatabase::new(), modules.decoder_registry());
+        let notes = [
+            issue_note(&config, Amount::from_msats(1024), 1),
+            issue_note(&config, Amount::from_msats(1024), 2),
+            issue_note(&config, Amount::from_msats(2048), 3),
+            issue_note(&config, Amount::from_msats(4096), 4),
+            issue_note(&config, Amount::from_msats(8), 5),
+        ];
+        assert!(check(&modules, &db, &notes).await.unwrap().is_empty());
+        assert!(check(&modules, &db, &[notes[0], notes[0]])
+            .await
+            .unwrap()
+            .is_empty());
+
+        let mint = Mint::new(config.clone());
+        let mut dbtx = db.begin_transaction().await;
+        for (amount, note) in [notes[1], notes[4]] {
+            mint.process_input(
+                &mut dbtx.to_ref_with_prefix_module_id(MINT_INSTANCE).into_nc(),
+                &MintInput::new_v0(amount, note),
+            )
+            .await
+            .unwrap();
+        }
+        dbtx.commit_tx().await;
+
+        assert_eq!(
+            check(&modules, &db, &notes).await.unwrap(),
+            HashSet::from([notes[1].1.nonce, notes[4].1.nonce])
+        );
+        assert!(check(&modules, &db, &[notes[0], notes[2]])
+            .await
+            .unwrap()
+            .is_empty());
+
+        let mut forged = issue_note(&config, Amount::from_msats(1024), 6);
+        forged.1.signature = notes[0].1.signature;
+        assert!(check(&modules, &db, &[notes[0], forged]).await.is_err());
+    }
+
+    #[test]
+    fn hash_to_curve_matches_nut00_vectors() {
+        let vectors = [
+            (
+                "0000000000000000000000000000000000000000000000000000000000000000",
+                "024cce997d3b518f739663b757deaec95bcd9473c30a14ac2fd04023a739d1a725",
+            ),
+            (
+                "0000000000000000000000000000000000000000000000000000000000000001",
+                "022e7158e11c9506f1aa4248bf531298daa7febd6194f003edcd9b93ade6253acf",
+            ),
+            (
+                "0000000000000000000000000000000000000000000000000000000000000002",
+                "026cdbe15362df59cd1dd3c9c11de8aedac2106eca69236ecd9fbe117af897be4f",
+            ),
+        ];
+        for (message, y) in vectors {
+            let message = Vec::<u8>::from_hex(message).unwrap();
+            assert_eq!(hash_to_curve(&message).to_string(), y);
+        }
+    }
 }
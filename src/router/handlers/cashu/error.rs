@@ -0,0 +1,134 @@
+use std::fmt;
+
+use axum::body::{Body, Bytes};
+use axum::extract::{FromRequest, Request};
+use axum::http::response::Parts;
+use axum::middleware::Next;
+use axum::response::{IntoResponse, Response};
+use axum::Json;
+use fedimint_core::core::OperationId;
+use fedimint_core::Amount;
+use serde::{Deserialize, Serialize};
+
+use crate::error::ErrorSource;
+use crate::router::handlers::cashu::Unit;
+
+/// Code of errors that have no dedicated NUT-00 error code
+pub const GENERIC_ERROR_CODE: u32 = 0;
+
+/// Errors with a stable NUT-00 error code. Wrap them in an
+/// [`AppError`](crate::error::AppError) to return them from a handler.
+#[derive(Debug)]
+pub enum CashuError {
+    TokenNotVerified(String),
+    TokenAlreadySpent,
+    AmountMismatch { expected: Amount, actual: Amount },
+    InsufficientInputs { inputs: Amount, required: Amount },
+    UnitNotSupported(Unit),
+    UnknownKeyset(String),
+    QuoteNotPaid(OperationId),
+    QuoteAlreadyIssued(OperationId),
+    QuotePending(OperationId),
+    QuoteAlreadyPaid(OperationId),
+    QuoteExpired(OperationId),
+}
+
+impl CashuError {
+    /// Error code as listed in NUT-00
+    pub fn code(&self) -> u32 {
+        match self {
+            CashuError::TokenNotVerified(_) => 10003,
+            CashuError::TokenAlreadySpent => 11001,
+            CashuError::AmountMismatch { .. } | CashuError::InsufficientInputs { .. } => 11002,
+            CashuError::UnitNotSupported(_) => 11005,
+            CashuError::UnknownKeyset(_) => 12001,
+            CashuError::QuoteNotPaid(_) => 20001,
+            CashuError::QuoteAlreadyIssued(_) => 20002,
+            CashuError::QuotePending(_) => 20005,
+            CashuError::QuoteAlreadyPaid(_) => 20006,
+            CashuError::QuoteExpired(_) => 20007,
+        }
+    }
+}
+
+impl fmt::Display for CashuError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            CashuError::TokenNotVerified(e) => write!(f, "Token could not be verified: {e}"),
+            CashuError::TokenAlreadySpent => write!(f, "Token is already spent"),
+            CashuError::AmountMismatch { expected, actual } => write!(
+                f,
+                "Amount ({actual}) does not match the expected amount ({expected})"
+            ),
+            CashuError::InsufficientInputs { inputs, required } => write!(
+                f,
+                "Inputs ({inputs}) do not cover amount plus fee reserve ({required})"
+            ),
+            CashuError::UnitNotSupported(unit) => {
+                write!(f, "Unit {} is not supported", unit.as_str())
+            }
+            CashuError::UnknownKeyset(keyset_id) => write!(f, "Keyset {keyset_id} is not known"),
+            CashuError::QuoteNotPaid(quote_id) => write!(f, "Quote {quote_id} has not been paid"),
+            CashuError::QuoteAlreadyIssued(quote_id) => {
+                write!(f, "Quote {quote_id} has already been issued")
+            }
+            CashuError::QuotePending(quote_id) => write!(f, "Quote {quote_id} is pending"),
+            CashuError::QuoteAlreadyPaid(quote_id) => {
+                write!(f, "Quote {quote_id} has already been paid")
+            }
+            CashuError::QuoteExpired(quote_id) => write!(f, "Quote {quote_id} has expired"),
+        }
+    }
+}
+
+impl std::error::Error for CashuError {}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct ErrorResponse {
+    pub detail: String,
+    pub code: u32,
+}
+
+/// Returns the NUT-00 code of an error, or the generic code for errors that
+/// are not a [`CashuError`]
+pub fn error_code(error: &anyhow::Error) -> u32 {
+    error
+        .downcast_ref::<CashuError>()
+        .map_or(GENERIC_ERROR_CODE, CashuError::code)
+}
+
+/// Renders error responses of the Cashu API in the NUT-00 error format
+pub async fn nut00_errors(req: Request, next: Next) -> Response {
+    into_nut00(next.run(req).await).await
+}
+
+/// Converts an error response into a NUT-00 error, successful responses are
+/// passed through
+pub async fn into_nut00(response: Response) -> Response {
+    let status = response.status();
+    if !status.is_client_error() && !status.is_server_error() {
+        return response;
+    }
+
+    let (parts, body) = response.into_parts();
+    let (detail, code) = error_detail(&parts, body).await;
+    (status, Json(ErrorResponse { detail, code })).into_response()
+}
+
+/// Takes the detail and code from the handler's error, or the body of
+/// responses not produced by a handler like rejected requests
+async fn error_detail(parts: &Parts, body: Body) -> (String, u32) {
+    if let Some(ErrorSource(error)) = parts.extensions.get::<ErrorSource>() {
+        return (error.to_string(), error_code(error));
+    }
+
+    let detail = match Bytes::from_request(Request::new(body), &()).await {
+        Ok(bytes) if !bytes.is_empty() => String::from_utf8_lossy(&bytes).into_owned(),
+        _ => parts
+            .status
+            .canonical_reason()
+            .unwrap_or("Unknown error")
+            .to_string(),
+    };
+    (detail, GENERIC_ERROR_CODE)
+}
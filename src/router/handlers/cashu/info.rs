@@ -1,30 +1,82 @@
 use std::collections::BTreeMap;
+use std::str::FromStr;
 
-use axum::extract::State;
-use axum::http::StatusCode;
+use anyhow::anyhow;
+use axum::extract::{Query, State};
 use axum::Json;
-use serde::Serialize;
+use fedimint_client::ClientArc;
+use fedimint_core::config::FederationId;
+use serde::{Deserialize, Serialize};
 
 use crate::error::AppError;
+use crate::router::handlers::cashu::ws::{SubscriptionKind, SUPPORTED_KINDS};
+use crate::router::handlers::cashu::{keyset_id, FederationScope, Method, Unit};
 use crate::state::AppState;
 
-#[derive(Debug, Serialize)]
+/// Federation meta keys the mint information falls back to
+const META_WELCOME_MESSAGE_KEY: &str = "welcome_message";
+const META_ICON_URL_KEY: &str = "federation_icon_url";
+
+/// Optional NUTs implemented by the server, besides NUT-04, NUT-05, NUT-09 and
+/// NUT-17 which depend on the configuration
+const SUPPORTED_NUTS: [u8; 2] = [7, 8];
+
+#[derive(Debug, Clone, Serialize)]
 pub struct Contact {
     pub method: String,
-    pub value: String,
+    pub info: String,
 }
 
-#[derive(Debug, Serialize)]
+impl FromStr for Contact {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let (method, info) = s
+            .split_once(':')
+            .ok_or_else(|| anyhow!("Contact must be formatted as method:value, got {}", s))?;
+        Ok(Contact {
+            method: method.trim().to_string(),
+            info: info.trim().to_string(),
+        })
+    }
+}
+
+#[derive(Debug, Clone, Serialize)]
 pub struct NutMethod {
-    pub method: String,
-    pub value: String,
+    pub method: Method,
+    pub unit: Unit,
+}
+
+/// NUT-17 subscription kinds available for a method and unit
+#[derive(Debug, Clone, Serialize)]
+pub struct NutCommands {
+    pub method: Method,
+    pub unit: Unit,
+    pub commands: Vec<SubscriptionKind>,
+}
+
+#[derive(Debug, Serialize)]
+#[serde(untagged)]
+pub enum NutSupport {
+    Flag(bool),
+    Commands(Vec<NutCommands>),
 }
 
 #[derive(Debug, Serialize)]
 pub struct Nut {
+    #[serde(skip_serializing_if = "Option::is_none")]
     pub methods: Option<Vec<NutMethod>>,
+    #[serde(skip_serializing_if = "Option::is_none")]
     pub disabled: Option<bool>,
-    pub supported: Option<bool>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub supported: Option<NutSupport>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct FederationInfo {
+    pub federation_id: FederationId,
+    pub name: Option<String>,
+    pub keysets: Vec<String>,
 }
 
 #[derive(Debug, Serialize)]
@@ -32,78 +84,148 @@ pub struct CashuNUT06InfoResponse {
     pub name: String,
     pub pubkey: String,
     pub version: String,
-    pub description: String,
-    pub description_long: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub description: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub description_long: Option<String>,
     pub contact: Vec<Contact>,
-    pub motd: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub motd: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub icon_url: Option<String>,
     pub nuts: BTreeMap<String, Nut>,
+    /// Every federation the server is joined to, each can be used as a mint
+    /// under `/cashu/v1/{federation_id}`. Only lists the scoped federation for
+    /// federation scoped requests.
+    pub federations: Vec<FederationInfo>,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct InfoQuery {
+    pub federation_id: Option<FederationId>,
 }
 
 #[axum_macros::debug_handler]
 pub async fn handle_info(
+    Query(query): Query<InfoQuery>,
+    scope: FederationScope,
     State(state): State<AppState>,
 ) -> Result<Json<CashuNUT06InfoResponse>, AppError> {
-    let client = match state.multimint.get_default().await {
-        Some(client) => client,
-        None => {
-            return Err(AppError::new(
-                StatusCode::INTERNAL_SERVER_ERROR,
-                anyhow::anyhow!("No default client"),
-            ))
-        }
-    };
-
+    let client = scope.client(&state, query.federation_id).await?;
     let config = client.get_config();
-    let mut nuts = BTreeMap::new();
+    let meta = &config.global.meta;
+    let cashu = &state.cashu;
 
-    nuts.insert(
-        "4".to_string(),
-        Nut {
-            methods: Some(vec![NutMethod {
-                method: "bolt11".to_string(),
-                value: "sat".to_string(),
-            }]),
-            disabled: Some(false),
-            supported: None,
-        },
-    );
-
-    nuts.insert(
-        "5".to_string(),
-        Nut {
-            methods: Some(vec![NutMethod {
-                method: "bolt11".to_string(),
-                value: "sat".to_string(),
-            }]),
-            disabled: None,
-            supported: None,
-        },
-    );
-
-    for &i in &[7, 8, 9, 10, 12] {
+    let methods = Method::ALL
+        .into_iter()
+        .flat_map(|method| Unit::ALL.into_iter().map(move |unit| (method, unit)))
+        .filter(|(method, unit)| cashu.is_enabled(*method, *unit))
+        .map(|(method, unit)| NutMethod { method, unit })
+        .collect::<Vec<_>>();
+
+    let mut nuts = BTreeMap::new();
+    for nut in ["4", "5"] {
         nuts.insert(
-            i.to_string(),
+            nut.to_string(),
+            Nut {
+                disabled: Some(methods.is_empty()),
+                methods: Some(methods.clone()),
+                supported: None,
+            },
+        );
+    }
+    for nut in SUPPORTED_NUTS {
+        nuts.insert(
+            nut.to_string(),
+            Nut {
+                methods: None,
+                disabled: None,
+                supported: Some(NutSupport::Flag(true)),
+            },
+        );
+    }
+    if cashu.restore {
+        nuts.insert(
+            "9".to_string(),
+            Nut {
+                methods: None,
+                disabled: None,
+                supported: Some(NutSupport::Flag(true)),
+            },
+        );
+    }
+    let subscriptions = methods
+        .iter()
+        .filter(|nut_method| nut_method.method == Method::Bolt11)
+        .map(|nut_method| NutCommands {
+            method: nut_method.method,
+            unit: nut_method.unit,
+            commands: SUPPORTED_KINDS.to_vec(),
+        })
+        .collect::<Vec<_>>();
+    if !subscriptions.is_empty() {
+        nuts.insert(
+            "17".to_string(),
             Nut {
                 methods: None,
                 disabled: None,
-                supported: Some(true),
+                supported: Some(NutSupport::Commands(subscriptions)),
             },
         );
     }
 
+    let name = cashu
+        .name
+        .clone()
+        .or_else(|| config.global.federation_name().map(str::to_string))
+        .unwrap_or_else(|| client.federation_id().to_string());
+
+    let mut federations = Vec::new();
+    let listed = match scope.0 {
+        Some(_) => scope.clients(&state).await?,
+        None => state.multimint.all().await,
+    };
+    for client in listed {
+        federations.push(federation_info(&client)?);
+    }
+
     let response = CashuNUT06InfoResponse {
-        name: config.global.federation_name().unwrap().to_string(),
-        pubkey: config.global.federation_id().to_string(),
-        version: format!("{:?}", config.global.consensus_version),
-        description: "Cashu <-> Fedimint Soon (tm)".to_string(),
-        description_long: "Cashu <-> Fedimint Soon (tm)".to_string(),
-        contact: vec![Contact {
-            method: "xmpp".to_string(),
-            value: "local@localhost".to_string(),
-        }],
-        motd: "Cashu <-> Fedimint Soon (tm)".to_string(),
+        pubkey: client.federation_id().to_string(),
+        version: format!("fedimint-http/{}", env!("CARGO_PKG_VERSION")),
+        description: cashu
+            .description
+            .clone()
+            .or_else(|| Some(format!("Cashu mint backed by the {} federation", name))),
+        description_long: cashu.description_long.clone(),
+        contact: cashu.contact.clone(),
+        motd: cashu
+            .motd
+            .clone()
+            .or_else(|| meta.get(META_WELCOME_MESSAGE_KEY).cloned()),
+        icon_url: cashu
+            .icon_url
+            .clone()
+            .or_else(|| meta.get(META_ICON_URL_KEY).cloned()),
+        name,
         nuts,
+        federations,
     };
 
     Ok(Json(response))
 }
+
+fn federation_info(client: &ClientArc) -> Result<FederationInfo, AppError> {
+    Ok(FederationInfo {
+        federation_id: client.federation_id(),
+        name: client
+            .get_config()
+            .global
+            .federation_name()
+            .map(str::to_string),
+        keysets: Unit::ALL
+            .into_iter()
+            .map(|unit| keyset_id(client, unit))
+            .collect::<Result<_, _>>()?,
+    })
+}
@@ -1,17 +1,41 @@
 use anyhow::Result;
-use axum::extract::State;
+use axum::extract::{Path, State};
+use axum::Json;
+use serde::Serialize;
 
 use crate::error::AppError;
+use crate::router::handlers::cashu::{get_keyset, get_keysets, FederationScope, Keyset};
 use crate::state::AppState;
 
+#[derive(Debug, Serialize)]
+pub struct KeysResponse {
+    pub keysets: Vec<Keyset>,
+}
+
 #[axum_macros::debug_handler]
-pub async fn handle_keys(State(_state): State<AppState>) -> Result<(), AppError> {
-    // TODO: Implement this function
-    Ok(())
+pub async fn handle_keys(
+    scope: FederationScope,
+    State(state): State<AppState>,
+) -> Result<Json<KeysResponse>, AppError> {
+    let mut keysets = Vec::new();
+    for client in scope.clients(&state).await? {
+        keysets.extend(get_keysets(&client)?);
+    }
+
+    Ok(Json(KeysResponse { keysets }))
 }
 
 #[axum_macros::debug_handler]
-pub async fn handle_keys_keyset_id(State(_state): State<AppState>) -> Result<(), AppError> {
-    // TODO: Implement this function
-    Ok(())
+pub async fn handle_keys_keyset_id(
+    Path(keyset_id): Path<String>,
+    scope: FederationScope,
+    State(state): State<AppState>,
+) -> Result<Json<KeysResponse>, AppError> {
+    let (client, unit) = state.get_client_by_keyset_id(&keyset_id).await?;
+    scope.ensure(&state, &client).await?;
+    let keyset = get_keyset(&client, unit)?;
+
+    Ok(Json(KeysResponse {
+        keysets: vec![keyset],
+    }))
 }
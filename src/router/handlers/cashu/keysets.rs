@@ -1,10 +1,39 @@
 use axum::extract::State;
+use axum::Json;
+use serde::Serialize;
 
 use crate::error::AppError;
+use crate::router::handlers::cashu::{keyset_id, FederationScope, Unit};
 use crate::state::AppState;
 
+#[derive(Debug, Serialize)]
+pub struct KeysetInfo {
+    pub id: String,
+    pub unit: Unit,
+    pub active: bool,
+}
+
+#[derive(Debug, Serialize)]
+pub struct KeysetsResponse {
+    pub keysets: Vec<KeysetInfo>,
+}
+
 #[axum_macros::debug_handler]
-pub async fn handle_keysets(State(_state): State<AppState>) -> Result<(), AppError> {
-    // TODO: Implement this function
-    Ok(())
+pub async fn handle_keysets(
+    scope: FederationScope,
+    State(state): State<AppState>,
+) -> Result<Json<KeysetsResponse>, AppError> {
+    let mut keysets = Vec::new();
+    for client in scope.clients(&state).await? {
+        for unit in Unit::ALL {
+            keysets.push(KeysetInfo {
+                id: keyset_id(&client, unit)?,
+                unit,
+                // Fedimint never rotates its mint keys, so every keyset stays active
+                active: true,
+            });
+        }
+    }
+
+    Ok(Json(KeysetsResponse { keysets }))
 }
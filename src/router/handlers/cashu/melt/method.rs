@@ -5,157 +5,268 @@ use axum::extract::{Path, State};
 use axum::http::StatusCode;
 use axum::Json;
 use fedimint_client::ClientArc;
-use fedimint_core::config::FederationId;
+use fedimint_core::core::OperationId;
+use fedimint_core::db::IDatabaseTransactionOpsCoreTyped;
+use fedimint_core::time::now;
 use fedimint_core::Amount;
 use fedimint_ln_client::{LightningClientModule, OutgoingLightningPayment};
-use fedimint_wallet_client::{WalletClientModule, WithdrawState};
-use futures_util::StreamExt;
+use fedimint_mint_client::OOBNotes;
+use fedimint_wallet_client::WalletClientModule;
 use lightning_invoice::Bolt11Invoice;
-use serde::{Deserialize, Serialize};
+use serde::Deserialize;
 use tracing::info;
 
+use super::quote::{
+    follow_melt_payment, gateway_fee, get_melt_quote, melt_response, melt_status, parse_address,
+    save_melt_quote, MeltQuoteState, PostMeltQuoteMethodResponse,
+};
+use crate::db::{MeltFunding, MeltPayment, MeltQuote, MeltQuoteKey};
 use crate::error::AppError;
-use crate::router::handlers::cashu::{Method, Unit};
+use crate::router::handlers::cashu::error::CashuError;
+use crate::router::handlers::cashu::mint::quote::parse_quote_id;
+use crate::router::handlers::cashu::restore::{record_outputs, BlindedMessage};
+use crate::router::handlers::cashu::swap::reissue_notes;
+use crate::router::handlers::cashu::token::Ecash;
+use crate::router::handlers::cashu::{FederationScope, Method};
 use crate::state::AppState;
+use crate::utils::system_time_to_u64;
 
 #[derive(Debug, Deserialize)]
-pub struct PostMeltQuoteMethodRequest {
-    pub request: String,
-    pub amount: Amount,
-    pub unit: Unit,
-    pub federation_id: Option<FederationId>,
-}
-
-#[derive(Debug, Serialize)]
-pub struct PostMeltQuoteMethodResponse {
+pub struct PostMeltMethodRequest {
     pub quote: String,
-    pub amount: Amount,
-    pub fee_reserve: Amount,
-    pub paid: bool,
-    pub expiry: u64,
+    /// Notes worth at least the quote's amount plus fee reserve. Can be left
+    /// out when retrying a quote whose previous payment attempt failed.
+    pub inputs: Option<Ecash>,
+    /// Blank outputs of a deterministic wallet, recorded against the change
+    /// for NUT-09 restore
+    #[serde(default)]
+    pub outputs: Vec<BlindedMessage>,
 }
 
 #[axum_macros::debug_handler]
 pub async fn handle_method(
     Path(method): Path<Method>,
+    scope: FederationScope,
     State(state): State<AppState>,
-    Json(req): Json<PostMeltQuoteMethodRequest>,
+    Json(req): Json<PostMeltMethodRequest>,
 ) -> Result<Json<PostMeltQuoteMethodResponse>, AppError> {
-    let client = state.get_client(req.federation_id).await?;
-    let res = match method {
-        Method::Bolt11 => match req.unit {
-            Unit::Msat => melt_bolt11(client, req.request, req.amount).await,
-            Unit::Sat => melt_bolt11(client, req.request, req.amount * 1000).await,
-        },
-        Method::Onchain => match req.unit {
-            Unit::Msat => {
-                let amount_sat = bitcoin::Amount::from_sat(req.amount.try_into_sats()?);
-                melt_onchain(client, req.request, amount_sat).await
-            }
-            Unit::Sat => {
-                let amount_sat = req.amount * 1000;
-                let amount_sat = bitcoin::Amount::from_sat(amount_sat.try_into_sats()?);
-                melt_onchain(client, req.request, amount_sat).await
-            }
-        },
-    }?;
+    let quote_id = parse_quote_id(&req.quote)?;
+    let (client, mut quote) = get_melt_quote(&state, scope, quote_id, method).await?;
+
+    // A request for a quote that is being paid attaches to the payment in
+    // flight instead of starting another one
+    match melt_status(&client, &quote).await?.state {
+        MeltQuoteState::Unpaid => {}
+        MeltQuoteState::Pending => return Ok(Json(melt_response(&client, quote_id, quote).await?)),
+        MeltQuoteState::Paid => {
+            return Err(AppError::new(
+                StatusCode::BAD_REQUEST,
+                CashuError::QuoteAlreadyPaid(quote_id),
+            ))
+        }
+    }
+    // A funded quote holds the wallet's inputs, so its payment can still be
+    // retried once it expired
+    if quote.funding.is_none() && system_time_to_u64(now())? > quote.expiry {
+        return Err(AppError::new(
+            StatusCode::BAD_REQUEST,
+            CashuError::QuoteExpired(quote_id),
+        ));
+    }
+    match (quote.funding, &req.inputs) {
+        (None, None) => {
+            return Err(AppError::new(
+                StatusCode::BAD_REQUEST,
+                anyhow!("Quote {} requires inputs", quote_id),
+            ))
+        }
+        (Some(_), Some(_)) => {
+            return Err(AppError::new(
+                StatusCode::BAD_REQUEST,
+                anyhow!("Quote {} has already been funded", quote_id),
+            ))
+        }
+        (None, Some(_)) => {}
+        (Some(_), None) => info!("Retrying payment of funded melt quote {quote_id}"),
+    }
+
+    start_melt(&client, quote_id, &quote).await?;
+    let res = execute_melt(&state, &client, &mut quote, req.inputs).await;
+    finish_melt(&client, quote_id, &quote).await?;
+    let payment = res?;
+    follow_melt_payment(&client, payment);
+
+    let unit = quote.unit;
+    let res = melt_response(&client, quote_id, quote).await?;
+    if res.change.is_some() {
+        let (_, quote) = get_melt_quote(&state, scope, quote_id, method).await?;
+        if let Some(change) = quote.change {
+            record_outputs(&state, &client, unit, &req.outputs, &change).await?;
+        }
+    }
 
     Ok(Json(res))
 }
 
-pub async fn melt_bolt11(
-    client: ClientArc,
-    request: String,
-    amount_msat: Amount,
-) -> Result<PostMeltQuoteMethodResponse, AppError> {
-    let lightning_module = client.get_first_module::<LightningClientModule>();
-    lightning_module.select_active_gateway().await?;
+/// Claims the quote for this request. Fails if another request changed the
+/// quote since it was read, so that a quote is never funded or paid twice.
+async fn start_melt(
+    client: &ClientArc,
+    quote_id: OperationId,
+    quote: &MeltQuote,
+) -> Result<(), AppError> {
+    let mut dbtx = client.db().begin_transaction().await;
+    let unchanged = dbtx
+        .get_value(&MeltQuoteKey(quote_id))
+        .await
+        .is_some_and(|current| {
+            !current.executing
+                && current.funding == quote.funding
+                && current.payment == quote.payment
+        });
+    let claimed = MeltQuote {
+        executing: true,
+        ..quote.clone()
+    };
+    dbtx.insert_entry(&MeltQuoteKey(quote_id), &claimed).await;
+    if !unchanged || dbtx.commit_tx_result().await.is_err() {
+        return Err(AppError::new(
+            StatusCode::CONFLICT,
+            CashuError::QuotePending(quote_id),
+        ));
+    }
+    Ok(())
+}
 
-    let bolt11 = Bolt11Invoice::from_str(&request)?;
-    let bolt11_amount = Amount::from_msats(
-        bolt11
-            .amount_milli_satoshis()
-            .ok_or_else(|| anyhow!("Cannot pay amountless invoices",))?,
-    );
+/// Funds the quote with the wallet's inputs unless it was funded by an earlier
+/// attempt, then pays it. `quote` is updated with each step that succeeded.
+async fn execute_melt(
+    state: &AppState,
+    client: &ClientArc,
+    quote: &mut MeltQuote,
+    inputs: Option<Ecash>,
+) -> Result<MeltPayment, AppError> {
+    if let Some(inputs) = inputs {
+        let notes = inputs.to_oob_notes(state).await?;
+        quote.funding = Some(fund_quote(client, quote, notes).await?);
+    }
+
+    let (payment, fee_paid) = match quote.method {
+        Method::Bolt11 => pay_bolt11(client, quote).await?,
+        Method::Onchain => pay_onchain(client, quote).await?,
+    };
+    quote.payment = Some(payment);
+    quote.fee_paid = fee_paid;
+    Ok(payment)
+}
 
-    if bolt11_amount != amount_msat {
+/// Records the funding and payment of the quote and releases the claim, so
+/// that a failed attempt can be retried
+async fn finish_melt(
+    client: &ClientArc,
+    quote_id: OperationId,
+    quote: &MeltQuote,
+) -> Result<(), AppError> {
+    let finished = MeltQuote {
+        executing: false,
+        ..quote.clone()
+    };
+    save_melt_quote(client, quote_id, &finished).await
+}
+
+/// Reissues the wallet's inputs into our own wallet so they can pay for the
+/// quote
+async fn fund_quote(
+    client: &ClientArc,
+    quote: &MeltQuote,
+    notes: OOBNotes,
+) -> Result<MeltFunding, AppError> {
+    if notes.federation_id_prefix() != quote.federation_id.to_prefix() {
+        return Err(AppError::new(
+            StatusCode::BAD_REQUEST,
+            anyhow!("Inputs are not from the quote's federation"),
+        ));
+    }
+    let required = quote.amount + quote.fee_reserve;
+    if notes.total_amount() < required {
         return Err(AppError::new(
             StatusCode::BAD_REQUEST,
+            CashuError::InsufficientInputs {
+                inputs: notes.total_amount(),
+                required,
+            },
+        ));
+    }
+
+    let amount = notes.total_amount();
+    let operation_id = reissue_notes(client, notes).await?;
+    Ok(MeltFunding {
+        operation_id,
+        amount,
+    })
+}
+
+async fn pay_bolt11(
+    client: &ClientArc,
+    quote: &MeltQuote,
+) -> Result<(MeltPayment, Amount), AppError> {
+    let lightning_module = client.get_first_module::<LightningClientModule>();
+    let gateway = lightning_module.select_active_gateway().await?;
+    let gateway_fee = gateway_fee(&gateway.fees, quote.amount);
+    if gateway_fee > quote.fee_reserve {
+        return Err(AppError::new(
+            StatusCode::SERVICE_UNAVAILABLE,
             anyhow!(
-                "Invoice amount ({}) does not match request amount ({})",
-                bolt11_amount,
-                amount_msat
+                "Gateway fee ({}) exceeds the quoted fee reserve ({}), retry later",
+                gateway_fee,
+                quote.fee_reserve
             ),
         ));
     }
 
+    let bolt11 = Bolt11Invoice::from_str(&quote.request)?;
     let OutgoingLightningPayment {
         payment_type,
         contract_id: _,
         fee,
-    } = lightning_module.pay_bolt11_invoice(bolt11, ()).await?;
-
-    let operation_id = payment_type.operation_id();
-    info!("Gateway fee: {fee}, payment operation id: {operation_id}");
+    } = lightning_module
+        .pay_bolt11_invoice_with_gateway(Some(gateway), bolt11, ())
+        .await?;
 
-    Ok(PostMeltQuoteMethodResponse {
-        quote: operation_id.to_string(),
-        amount: amount_msat,
-        fee_reserve: fee,
-        paid: false,
-        expiry: 0,
-    })
+    info!(
+        "Gateway fee: {fee}, payment operation id: {}",
+        payment_type.operation_id()
+    );
+    Ok((MeltPayment::Bolt11(payment_type), fee))
 }
 
-async fn melt_onchain(
-    client: ClientArc,
-    request: String,
-    amount_sat: bitcoin::Amount,
-) -> Result<PostMeltQuoteMethodResponse, AppError> {
-    let address = bitcoin::Address::from_str(&request)
-        .expect("Onchain request must be a valid bitcoin address");
+async fn pay_onchain(
+    client: &ClientArc,
+    quote: &MeltQuote,
+) -> Result<(MeltPayment, Amount), AppError> {
+    let address = parse_address(&quote.request)?;
+    let amount_sat = bitcoin::Amount::from_sat(quote.amount.try_into_sats()?);
     let wallet_module = client.get_first_module::<WalletClientModule>();
     let fees = wallet_module
         .get_withdraw_fees(address.clone(), amount_sat)
         .await?;
-    let absolute_fees = fees.amount();
 
-    info!("Attempting withdraw with fees: {fees:?}");
+    if fees.amount() > bitcoin::Amount::from_sat(quote.fee_reserve.try_into_sats()?) {
+        return Err(AppError::new(
+            StatusCode::SERVICE_UNAVAILABLE,
+            anyhow!(
+                "Onchain fees ({}) exceed the quoted fee reserve ({}), retry later",
+                fees.amount(),
+                quote.fee_reserve
+            ),
+        ));
+    }
 
+    info!("Attempting withdraw with fees: {fees:?}");
+    let fee_paid = fees.amount().into();
     let operation_id = wallet_module
         .withdraw(address, amount_sat, fees, ())
         .await?;
 
-    let mut updates = wallet_module
-        .subscribe_withdraw_updates(operation_id)
-        .await?
-        .into_stream();
-
-    while let Some(update) = updates.next().await {
-        info!("Update: {update:?}");
-
-        match update {
-            WithdrawState::Succeeded(_txid) => {
-                return Ok(PostMeltQuoteMethodResponse {
-                    quote: operation_id.to_string(),
-                    amount: amount_sat.into(),
-                    fee_reserve: absolute_fees.into(),
-                    paid: true,
-                    expiry: 0,
-                });
-            }
-            WithdrawState::Failed(e) => {
-                return Err(AppError::new(
-                    StatusCode::INTERNAL_SERVER_ERROR,
-                    anyhow!("Onchain melt failed: {:?}", e),
-                ));
-            }
-            _ => continue,
-        };
-    }
-
-    Err(AppError::new(
-        StatusCode::INTERNAL_SERVER_ERROR,
-        anyhow!("Update stream ended without outcome"),
-    ))
+    Ok((MeltPayment::Onchain(operation_id), fee_paid))
 }
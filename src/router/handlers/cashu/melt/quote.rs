@@ -1,16 +1,516 @@
-use axum::extract::State;
+use std::str::FromStr;
+use std::time::Duration;
 
+use anyhow::anyhow;
+use axum::extract::{Path, State};
+use axum::http::StatusCode;
+use axum::Json;
+use bitcoin_hashes::hex::ToHex;
+use fedimint_client::ClientArc;
+use fedimint_core::config::FederationId;
+use fedimint_core::core::OperationId;
+use fedimint_core::db::IDatabaseTransactionOpsCoreTyped;
+use fedimint_core::encoding::{Decodable, Encodable};
+use fedimint_core::time::now;
+use fedimint_core::{Amount, TieredMulti};
+use fedimint_ln_client::{InternalPayState, LightningClientModule, LnPayState, PayType};
+use fedimint_mint_client::{MintClientModule, OOBNotes};
+use fedimint_wallet_client::{WalletClientModule, WithdrawState};
+use futures_util::StreamExt;
+use lightning_invoice::{Bolt11Invoice, RoutingFees};
+use serde::{Deserialize, Serialize};
+use tracing::{info, warn};
+
+use crate::db::{MeltPayment, MeltQuote, MeltQuoteKey, MeltStatusKey};
 use crate::error::AppError;
+use crate::router::handlers::cashu::check::record_notes;
+use crate::router::handlers::cashu::error::CashuError;
+use crate::router::handlers::cashu::mint::quote::parse_quote_id;
+use crate::router::handlers::cashu::token::EcashFormat;
+use crate::router::handlers::cashu::{spend_exact, FederationScope, Method, Unit};
 use crate::state::AppState;
+use crate::utils::{spawn_follower, system_time_to_u64};
+
+pub const DEFAULT_MELT_EXPIRY_OFFSET: u64 = 3600;
+
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct PostMeltQuoteMethodRequest {
+    pub request: String,
+    /// Amount in `unit`, required for onchain melts and checked against the
+    /// invoice amount for bolt11 melts
+    pub amount: Option<Amount>,
+    pub unit: Unit,
+    pub federation_id: Option<FederationId>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Encodable, Decodable)]
+#[serde(rename_all = "SCREAMING_SNAKE_CASE")]
+pub enum MeltQuoteState {
+    Unpaid,
+    Pending,
+    Paid,
+}
+
+#[derive(Debug, Serialize)]
+pub struct PostMeltQuoteMethodResponse {
+    pub quote: String,
+    /// Amount in the quote's unit, rounded up to a whole unit
+    pub amount: u64,
+    /// Fee reserve in the quote's unit, rounded up to a whole unit
+    pub fee_reserve: u64,
+    pub paid: bool,
+    pub state: MeltQuoteState,
+    pub expiry: u64,
+    pub payment_preimage: Option<String>,
+    pub txid: Option<String>,
+    /// NUT-08 change for the unused part of the fee reserve, as a Cashu token
+    pub change: Option<String>,
+}
+
+/// State of the latest payment attempt of a melt quote
+#[derive(Debug, Clone, PartialEq, Eq, Encodable, Decodable)]
+pub struct MeltStatus {
+    pub state: MeltQuoteState,
+    pub payment_preimage: Option<String>,
+    pub txid: Option<String>,
+}
+
+impl PostMeltQuoteMethodResponse {
+    pub fn new(quote_id: OperationId, quote: &MeltQuote, status: MeltStatus) -> Self {
+        Self {
+            quote: quote_id.to_string(),
+            amount: quote.unit.value_of_ceil(quote.amount),
+            fee_reserve: quote.unit.value_of_ceil(quote.fee_reserve),
+            paid: status.state == MeltQuoteState::Paid,
+            state: status.state,
+            expiry: quote.expiry,
+            payment_preimage: status.payment_preimage,
+            txid: status.txid,
+            change: None,
+        }
+    }
+}
 
 #[axum_macros::debug_handler]
-pub async fn handle_method(State(_state): State<AppState>) -> Result<(), AppError> {
-    // TODO: Implement this function
-    Ok(())
+pub async fn handle_method(
+    Path(method): Path<Method>,
+    scope: FederationScope,
+    State(state): State<AppState>,
+    Json(req): Json<PostMeltQuoteMethodRequest>,
+) -> Result<Json<PostMeltQuoteMethodResponse>, AppError> {
+    state.cashu.ensure_enabled(method, req.unit)?;
+    let client = scope.client(&state, req.federation_id).await?;
+    let amount = req.amount.map(|amount| req.unit.to_amount(amount.msats));
+    let (amount, fee_reserve, expiry) = match method {
+        Method::Bolt11 => quote_bolt11(&client, &req.request, amount).await?,
+        Method::Onchain => quote_onchain(&client, &req.request, amount).await?,
+    };
+
+    let quote_id = OperationId::new_random();
+    let quote = MeltQuote {
+        method,
+        unit: req.unit,
+        request: req.request,
+        amount,
+        fee_reserve,
+        expiry,
+        federation_id: client.federation_id(),
+        funding: None,
+        payment: None,
+        executing: false,
+        fee_paid: Amount::ZERO,
+        change: None,
+    };
+    save_melt_quote(&client, quote_id, &quote).await?;
+
+    Ok(Json(PostMeltQuoteMethodResponse::new(
+        quote_id,
+        &quote,
+        MeltStatus::unpaid(),
+    )))
 }
 
 #[axum_macros::debug_handler]
-pub async fn handle_method_quote_id(State(_state): State<AppState>) -> Result<(), AppError> {
-    // TODO: Implement this function
+pub async fn handle_method_quote_id(
+    Path((method, quote_id)): Path<(Method, String)>,
+    scope: FederationScope,
+    State(state): State<AppState>,
+) -> Result<Json<PostMeltQuoteMethodResponse>, AppError> {
+    let quote_id = parse_quote_id(&quote_id)?;
+    let (client, quote) = get_melt_quote(&state, scope, quote_id, method).await?;
+
+    Ok(Json(melt_response(&client, quote_id, quote).await?))
+}
+
+/// Reports the state of a melt quote, handing out its change once it is paid.
+/// A paid quote is reported as paid even if its change cannot be issued yet,
+/// the change is handed out by a later request instead.
+pub async fn melt_response(
+    client: &ClientArc,
+    quote_id: OperationId,
+    quote: MeltQuote,
+) -> Result<PostMeltQuoteMethodResponse, AppError> {
+    let status = melt_status(client, &quote).await?;
+    let change = match (status.state, &quote.change) {
+        (MeltQuoteState::Paid, Some(change)) => Some(change.clone()),
+        (MeltQuoteState::Paid, None) => match issue_change(client, quote_id, &quote).await {
+            Ok(change) => Some(change),
+            Err(e) => {
+                warn!(
+                    "Could not issue change for melt quote {quote_id}: {}",
+                    e.error
+                );
+                None
+            }
+        },
+        _ => None,
+    };
+
+    Ok(response_with_change(
+        client,
+        quote_id,
+        &quote,
+        status,
+        change.as_ref(),
+    ))
+}
+
+/// Reports the state of a melt quote like [`melt_response`], but only with
+/// change that has already been issued. Lets watchers report the quote
+/// without handing out change as a side effect.
+pub async fn peek_melt_response(
+    client: &ClientArc,
+    quote_id: OperationId,
+    quote: &MeltQuote,
+) -> Result<PostMeltQuoteMethodResponse, AppError> {
+    let status = melt_status(client, quote).await?;
+    let change = quote
+        .change
+        .as_ref()
+        .filter(|_| status.state == MeltQuoteState::Paid);
+    Ok(response_with_change(
+        client, quote_id, quote, status, change,
+    ))
+}
+
+fn response_with_change(
+    client: &ClientArc,
+    quote_id: OperationId,
+    quote: &MeltQuote,
+    status: MeltStatus,
+    change: Option<&OOBNotes>,
+) -> PostMeltQuoteMethodResponse {
+    let mut res = PostMeltQuoteMethodResponse::new(quote_id, quote, status);
+    if let Some(change) = change.filter(|change| change.total_amount() != Amount::ZERO) {
+        match EcashFormat::default().encode(client, change, quote.unit, None) {
+            Ok(change) => res.change = Some(change),
+            Err(e) => warn!(
+                "Could not encode change of melt quote {quote_id}: {}",
+                e.error
+            ),
+        }
+    }
+    res
+}
+
+/// Spends the inputs left over after paying the quote, rounded down to the
+/// quote's unit, and records them as the quote's change. If another request
+/// recorded change first, ours is cancelled and the recorded change is
+/// returned instead.
+async fn issue_change(
+    client: &ClientArc,
+    quote_id: OperationId,
+    quote: &MeltQuote,
+) -> Result<OOBNotes, AppError> {
+    let funded = quote.funding.map_or(Amount::ZERO, |funding| funding.amount);
+    let spent = quote.amount + quote.fee_paid;
+    let left = if funded > spent {
+        funded - spent
+    } else {
+        Amount::ZERO
+    };
+    let amount = quote.unit.to_amount(quote.unit.value_of_floor(left));
+    let (spend_id, change) = if amount > Amount::ZERO {
+        let (spend_id, change) = spend_exact(client, amount).await?;
+        (Some(spend_id), change)
+    } else {
+        let prefix = quote.federation_id.to_prefix();
+        (None, OOBNotes::new(prefix, TieredMulti::default()))
+    };
+
+    let mut dbtx = client.db().begin_transaction().await;
+    let committed = match dbtx.get_value(&MeltQuoteKey(quote_id)).await {
+        Some(MeltQuote {
+            change: Some(recorded),
+            ..
+        }) => {
+            cancel_spend(client, spend_id).await;
+            return Ok(recorded);
+        }
+        Some(current) => {
+            let current = MeltQuote {
+                change: Some(change.clone()),
+                ..current
+            };
+            dbtx.insert_entry(&MeltQuoteKey(quote_id), &current).await;
+            dbtx.commit_tx_result().await
+        }
+        None => Err(anyhow!("Quote {} not found", quote_id)),
+    };
+    if let Err(e) = committed {
+        cancel_spend(client, spend_id).await;
+        return Err(e.into());
+    }
+
+    info!("Issued {amount} change for melt quote {quote_id}");
+    record_notes(client, &change).await?;
+    Ok(change)
+}
+
+async fn cancel_spend(client: &ClientArc, spend_id: Option<OperationId>) {
+    if let Some(spend_id) = spend_id {
+        client
+            .get_first_module::<MintClientModule>()
+            .try_cancel_spend_notes(spend_id)
+            .await;
+    }
+}
+
+async fn quote_bolt11(
+    client: &ClientArc,
+    request: &str,
+    amount: Option<Amount>,
+) -> Result<(Amount, Amount, u64), AppError> {
+    let bolt11 = Bolt11Invoice::from_str(request).map_err(|e| {
+        AppError::new(
+            StatusCode::BAD_REQUEST,
+            anyhow!("Invalid bolt11 invoice: {}", e),
+        )
+    })?;
+    let bolt11_amount = Amount::from_msats(bolt11.amount_milli_satoshis().ok_or_else(|| {
+        AppError::new(
+            StatusCode::BAD_REQUEST,
+            anyhow!("Cannot pay amountless invoices"),
+        )
+    })?);
+
+    if let Some(amount) = amount.filter(|amount| *amount != bolt11_amount) {
+        return Err(AppError::new(
+            StatusCode::BAD_REQUEST,
+            CashuError::AmountMismatch {
+                expected: amount,
+                actual: bolt11_amount,
+            },
+        ));
+    }
+
+    let lightning_module = client.get_first_module::<LightningClientModule>();
+    let gateway = lightning_module.select_active_gateway().await?;
+    let fee_reserve = gateway_fee(&gateway.fees, bolt11_amount);
+
+    let expiry = default_expiry()?;
+    let expiry = match bolt11.expires_at() {
+        Some(expires_at) => expiry.min(expires_at.as_secs()),
+        None => expiry,
+    };
+
+    Ok((bolt11_amount, fee_reserve, expiry))
+}
+
+async fn quote_onchain(
+    client: &ClientArc,
+    request: &str,
+    amount: Option<Amount>,
+) -> Result<(Amount, Amount, u64), AppError> {
+    let amount = amount.ok_or_else(|| {
+        AppError::new(
+            StatusCode::BAD_REQUEST,
+            anyhow!("Onchain melts require an amount"),
+        )
+    })?;
+    let address = parse_address(request)?;
+    let amount_sat = bitcoin::Amount::from_sat(amount.try_into_sats()?);
+
+    let fees = client
+        .get_first_module::<WalletClientModule>()
+        .get_withdraw_fees(address, amount_sat)
+        .await?;
+
+    Ok((amount, fees.amount().into(), default_expiry()?))
+}
+
+/// Fee the gateway charges for routing `amount`, computed the same way the
+/// lightning module funds outgoing contracts
+pub fn gateway_fee(fees: &RoutingFees, amount: Amount) -> Amount {
+    let margin_fee = if fees.proportional_millionths > 0 {
+        amount.msats / (1_000_000 / u64::from(fees.proportional_millionths))
+    } else {
+        0
+    };
+    Amount::from_msats(u64::from(fees.base_msat) + margin_fee)
+}
+
+fn default_expiry() -> Result<u64, AppError> {
+    Ok(system_time_to_u64(
+        now() + Duration::from_secs(DEFAULT_MELT_EXPIRY_OFFSET),
+    )?)
+}
+
+pub fn parse_address(request: &str) -> Result<bitcoin::Address, AppError> {
+    bitcoin::Address::from_str(request).map_err(|e| {
+        AppError::new(
+            StatusCode::BAD_REQUEST,
+            anyhow!("Invalid bitcoin address: {}", e),
+        )
+    })
+}
+
+pub async fn save_melt_quote(
+    client: &ClientArc,
+    quote_id: OperationId,
+    quote: &MeltQuote,
+) -> Result<(), AppError> {
+    let mut dbtx = client.db().begin_transaction().await;
+    dbtx.insert_entry(&MeltQuoteKey(quote_id), quote).await;
+    dbtx.commit_tx_result().await?;
     Ok(())
 }
+
+/// Looks up a melt quote for `method` in the databases of the federations
+/// visible to the request
+pub async fn get_melt_quote(
+    state: &AppState,
+    scope: FederationScope,
+    quote_id: OperationId,
+    method: Method,
+) -> Result<(ClientArc, MeltQuote), AppError> {
+    for client in scope.clients(state).await? {
+        let quote = client
+            .db()
+            .begin_transaction_nc()
+            .await
+            .get_value(&MeltQuoteKey(quote_id))
+            .await;
+        match quote {
+            Some(quote) if quote.method != method => {
+                return Err(AppError::new(
+                    StatusCode::BAD_REQUEST,
+                    anyhow!("Quote {} is not a {:?} quote", quote_id, method),
+                ))
+            }
+            Some(quote) => return Ok((client, quote)),
+            None => {}
+        }
+    }
+
+    Err(AppError::new(
+        StatusCode::NOT_FOUND,
+        anyhow!("Quote {} not found", quote_id),
+    ))
+}
+
+impl MeltStatus {
+    pub fn unpaid() -> Self {
+        Self {
+            state: MeltQuoteState::Unpaid,
+            payment_preimage: None,
+            txid: None,
+        }
+    }
+
+    pub fn pending() -> Self {
+        Self {
+            state: MeltQuoteState::Pending,
+            ..Self::unpaid()
+        }
+    }
+}
+
+/// Reads the state of a melt quote's latest payment attempt as last recorded,
+/// without waiting on the payment. Failed attempts leave the quote unpaid so
+/// it can be executed again.
+pub async fn melt_status(client: &ClientArc, quote: &MeltQuote) -> Result<MeltStatus, AppError> {
+    if quote.executing {
+        return Ok(MeltStatus::pending());
+    }
+    let Some(payment) = quote.payment else {
+        return Ok(MeltStatus::unpaid());
+    };
+
+    // Payments are followed from the moment they are made, this resumes
+    // following payments made before a restart
+    follow_melt_payment(client, payment);
+    Ok(client
+        .db()
+        .begin_transaction_nc()
+        .await
+        .get_value(&MeltStatusKey(payment.operation_id()))
+        .await
+        .unwrap_or_else(MeltStatus::pending))
+}
+
+/// Records the state of a melt quote's payment attempt in the background each
+/// time it progresses
+pub fn follow_melt_payment(client: &ClientArc, payment: MeltPayment) {
+    let client = client.clone();
+    let operation_id = payment.operation_id();
+    spawn_follower(operation_id, async move {
+        let lightning_module = client.get_first_module::<LightningClientModule>();
+        let mut updates = match payment {
+            MeltPayment::Bolt11(PayType::Internal(operation_id)) => lightning_module
+                .subscribe_internal_pay(operation_id)
+                .await?
+                .into_stream()
+                .map(|update| match update {
+                    InternalPayState::Preimage(preimage) => MeltStatus {
+                        state: MeltQuoteState::Paid,
+                        payment_preimage: Some(preimage.0.to_hex()),
+                        txid: None,
+                    },
+                    InternalPayState::Funding => MeltStatus::pending(),
+                    _ => MeltStatus::unpaid(),
+                })
+                .boxed(),
+            MeltPayment::Bolt11(PayType::Lightning(operation_id)) => lightning_module
+                .subscribe_ln_pay(operation_id)
+                .await?
+                .into_stream()
+                .map(|update| match update {
+                    LnPayState::Success { preimage } => MeltStatus {
+                        state: MeltQuoteState::Paid,
+                        payment_preimage: Some(preimage),
+                        txid: None,
+                    },
+                    LnPayState::Canceled
+                    | LnPayState::Refunded { .. }
+                    | LnPayState::UnexpectedError { .. } => MeltStatus::unpaid(),
+                    _ => MeltStatus::pending(),
+                })
+                .boxed(),
+            MeltPayment::Onchain(operation_id) => client
+                .get_first_module::<WalletClientModule>()
+                .subscribe_withdraw_updates(operation_id)
+                .await?
+                .into_stream()
+                .map(|update| match update {
+                    WithdrawState::Succeeded(txid) => MeltStatus {
+                        state: MeltQuoteState::Paid,
+                        payment_preimage: None,
+                        txid: Some(txid.to_string()),
+                    },
+                    WithdrawState::Failed(_) => MeltStatus::unpaid(),
+                    _ => MeltStatus::pending(),
+                })
+                .boxed(),
+        };
+
+        while let Some(status) = updates.next().await {
+            let mut dbtx = client.db().begin_transaction().await;
+            dbtx.insert_entry(&MeltStatusKey(operation_id), &status)
+                .await;
+            dbtx.commit_tx_result().await?;
+        }
+        Ok(())
+    });
+}
@@ -1,105 +1,123 @@
-use std::time::Duration;
-
 use anyhow::anyhow;
 use axum::extract::{Path, State};
 use axum::http::StatusCode;
 use axum::Json;
 use fedimint_client::ClientArc;
-use fedimint_core::config::FederationId;
-use fedimint_core::time::now;
-use fedimint_core::Amount;
-use fedimint_ln_client::LightningClientModule;
-use fedimint_wallet_client::WalletClientModule;
+use fedimint_core::core::OperationId;
+use fedimint_core::db::IDatabaseTransactionOpsCoreTyped;
+use fedimint_mint_client::{MintClientModule, OOBNotes};
 use serde::{Deserialize, Serialize};
+use tracing::info;
 
+use super::quote::{get_mint_quote, mint_quote_state, parse_quote_id, MintQuoteState};
+use crate::db::{MintQuote, MintQuoteIssuedKey};
 use crate::error::AppError;
-use crate::router::handlers::cashu::{Method, Unit};
+use crate::router::handlers::cashu::check::record_notes;
+use crate::router::handlers::cashu::error::CashuError;
+use crate::router::handlers::cashu::restore::{record_outputs, BlindedMessage};
+use crate::router::handlers::cashu::token::EcashFormat;
+use crate::router::handlers::cashu::{spend_exact, FederationScope, Method};
 use crate::state::AppState;
 
 #[derive(Debug, Deserialize)]
 #[serde(rename_all = "camelCase")]
-pub struct PostMintQuoteMethodRequest {
-    pub amount: Amount,
-    pub unit: Unit,
-    pub federation_id: Option<FederationId>,
+pub struct PostMintMethodRequest {
+    pub quote: String,
+    #[serde(default)]
+    pub format: EcashFormat,
+    pub mint_url: Option<String>,
+    /// Blinded messages of a deterministic wallet, recorded against the issued
+    /// notes for NUT-09 restore
+    #[serde(default)]
+    pub outputs: Vec<BlindedMessage>,
 }
 
 #[derive(Debug, Serialize)]
-pub struct PostMintQuoteMethodResponse {
+pub struct PostMintMethodResponse {
     pub quote: String,
-    pub request: String,
-    pub paid: bool,
-    pub expiry: u64,
+    pub state: MintQuoteState,
+    /// The issued e-cash, absent while the payment is still pending
+    pub notes: Option<String>,
 }
 
 #[axum_macros::debug_handler]
 pub async fn handle_method(
     Path(method): Path<Method>,
+    scope: FederationScope,
     State(state): State<AppState>,
-    Json(req): Json<PostMintQuoteMethodRequest>,
-) -> Result<Json<PostMintQuoteMethodResponse>, AppError> {
-    let client = state.get_client(req.federation_id).await?;
-    let res = match method {
-        Method::Bolt11 => match req.unit {
-            Unit::Msat => mint_bolt11(client, req.amount).await,
-            Unit::Sat => mint_bolt11(client, req.amount * 1000).await,
-        },
-        Method::Onchain => match req.unit {
-            Unit::Msat => Err(AppError::new(
-                StatusCode::BAD_REQUEST,
-                anyhow!("Unsupported unit for onchain mint, use sat instead"),
-            )),
-            Unit::Sat => mint_onchain(client, req.amount * 1000).await,
-        },
-    }?;
-
-    Ok(Json(res))
-}
+    Json(req): Json<PostMintMethodRequest>,
+) -> Result<Json<PostMintMethodResponse>, AppError> {
+    let quote_id = parse_quote_id(&req.quote)?;
+    let (client, quote) = get_mint_quote(&state, scope, quote_id).await?;
+    if quote.method != method {
+        return Err(AppError::new(
+            StatusCode::BAD_REQUEST,
+            anyhow!("Quote {} is not a {:?} quote", quote_id, method),
+        ));
+    }
 
-const DEFAULT_MINT_EXPIRY_OFFSET: u64 = 3600;
-const DEFAULT_MINT_DESCRIPTION: &str = "Cashu mint operation";
-
-pub async fn mint_bolt11(
-    client: ClientArc,
-    amount_msat: Amount,
-) -> Result<PostMintQuoteMethodResponse, AppError> {
-    let lightning_module = client.get_first_module::<LightningClientModule>();
-    lightning_module.select_active_gateway().await?;
-
-    let valid_until = now() + Duration::from_secs(DEFAULT_MINT_EXPIRY_OFFSET);
-    let expiry_time = crate::utils::system_time_to_u64(valid_until)?;
-
-    let (operation_id, invoice) = lightning_module
-        .create_bolt11_invoice(
-            amount_msat,
-            format!("{}, method={:?}", DEFAULT_MINT_DESCRIPTION, Method::Bolt11),
-            Some(expiry_time),
-            (),
-        )
-        .await?;
+    let quote_state = mint_quote_state(&client, quote_id, &quote).await?;
+    let notes = match quote_state {
+        MintQuoteState::Unpaid => {
+            return Err(AppError::new(
+                StatusCode::BAD_REQUEST,
+                CashuError::QuoteNotPaid(quote_id),
+            ))
+        }
+        MintQuoteState::Issued => {
+            return Err(AppError::new(
+                StatusCode::BAD_REQUEST,
+                CashuError::QuoteAlreadyIssued(quote_id),
+            ))
+        }
+        MintQuoteState::Pending => None,
+        MintQuoteState::Paid => {
+            let notes = issue_notes(&client, quote_id, &quote).await?;
+            record_notes(&client, &notes).await?;
+            record_outputs(&state, &client, quote.unit, &req.outputs, &notes).await?;
+            Some(
+                req.format
+                    .encode(&client, &notes, quote.unit, req.mint_url)?,
+            )
+        }
+    };
 
-    Ok(PostMintQuoteMethodResponse {
-        quote: operation_id.to_string(),
-        request: invoice.to_string(),
-        paid: false,
-        expiry: expiry_time,
-    })
+    Ok(Json(PostMintMethodResponse {
+        quote: req.quote,
+        state: if notes.is_some() {
+            MintQuoteState::Issued
+        } else {
+            quote_state
+        },
+        notes,
+    }))
 }
 
-async fn mint_onchain(
-    client: ClientArc,
-    _amount_sat: Amount,
-) -> Result<PostMintQuoteMethodResponse, AppError> {
-    let wallet_client = client.get_first_module::<WalletClientModule>();
-    let valid_until = now() + Duration::from_secs(DEFAULT_MINT_EXPIRY_OFFSET);
-    let expiry_time = crate::utils::system_time_to_u64(valid_until)?;
+/// Spends notes worth exactly the quoted amount and marks the quote as issued.
+/// The spend is cancelled again if the quote was issued concurrently.
+async fn issue_notes(
+    client: &ClientArc,
+    quote_id: OperationId,
+    quote: &MintQuote,
+) -> Result<OOBNotes, AppError> {
+    let (spend_id, notes) = spend_exact(client, quote.amount).await?;
 
-    let (operation_id, address) = wallet_client.get_deposit_address(valid_until, ()).await?;
+    let mut dbtx = client.db().begin_transaction().await;
+    let issued = dbtx
+        .insert_entry(&MintQuoteIssuedKey(quote_id), &spend_id)
+        .await;
+    let committed = dbtx.commit_tx_result().await;
+    if issued.is_some() || committed.is_err() {
+        client
+            .get_first_module::<MintClientModule>()
+            .try_cancel_spend_notes(spend_id)
+            .await;
+        return Err(AppError::new(
+            StatusCode::CONFLICT,
+            CashuError::QuoteAlreadyIssued(quote_id),
+        ));
+    }
 
-    Ok(PostMintQuoteMethodResponse {
-        quote: operation_id.to_string(),
-        request: address.to_string(),
-        paid: false,
-        expiry: expiry_time,
-    })
+    info!("Issued {} for mint quote {quote_id}", quote.amount);
+    Ok(notes)
 }
@@ -1,16 +1,359 @@
-use axum::extract::State;
+use std::time::Duration;
 
+use anyhow::anyhow;
+use axum::extract::{Path, State};
+use axum::http::StatusCode;
+use axum::Json;
+use fedimint_client::ClientArc;
+use fedimint_core::config::FederationId;
+use fedimint_core::core::OperationId;
+use fedimint_core::db::IDatabaseTransactionOpsCoreTyped;
+use fedimint_core::encoding::{Decodable, Encodable};
+use fedimint_core::time::now;
+use fedimint_core::Amount;
+use fedimint_ln_client::{LightningClientModule, LnReceiveState};
+use fedimint_wallet_client::config::WalletClientConfig;
+use fedimint_wallet_client::{BitcoinTransactionData, DepositState, WalletClientModule};
+use futures_util::StreamExt;
+use serde::{Deserialize, Serialize};
+
+use crate::db::{MintQuote, MintQuoteIssuedKey, MintQuoteKey, MintQuoteStateKey};
 use crate::error::AppError;
+use crate::router::handlers::cashu::{FederationScope, Method, Unit};
 use crate::state::AppState;
+use crate::utils::{spawn_follower, system_time_to_u64};
+
+pub const DEFAULT_MINT_EXPIRY_OFFSET: u64 = 3600;
+const DEFAULT_MINT_DESCRIPTION: &str = "Cashu mint operation";
+
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct PostMintQuoteMethodRequest {
+    pub amount: Amount,
+    pub unit: Unit,
+    pub federation_id: Option<FederationId>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Encodable, Decodable)]
+#[serde(rename_all = "SCREAMING_SNAKE_CASE")]
+pub enum MintQuoteState {
+    Unpaid,
+    Pending,
+    Paid,
+    Issued,
+}
+
+#[derive(Debug, Serialize)]
+pub struct PostMintQuoteMethodResponse {
+    pub quote: String,
+    pub request: String,
+    pub paid: bool,
+    pub state: MintQuoteState,
+    pub expiry: u64,
+}
+
+impl PostMintQuoteMethodResponse {
+    pub fn new(quote_id: OperationId, quote: &MintQuote, state: MintQuoteState) -> Self {
+        Self {
+            quote: quote_id.to_string(),
+            request: quote.request.clone(),
+            paid: matches!(state, MintQuoteState::Paid | MintQuoteState::Issued),
+            state,
+            expiry: quote.expiry,
+        }
+    }
+}
 
 #[axum_macros::debug_handler]
-pub async fn handle_method(State(_state): State<AppState>) -> Result<(), AppError> {
-    // TODO: Implement this function
-    Ok(())
+pub async fn handle_method(
+    Path(method): Path<Method>,
+    scope: FederationScope,
+    State(state): State<AppState>,
+    Json(req): Json<PostMintQuoteMethodRequest>,
+) -> Result<Json<PostMintQuoteMethodResponse>, AppError> {
+    state.cashu.ensure_enabled(method, req.unit)?;
+    let client = scope.client(&state, req.federation_id).await?;
+    let res = create_mint_quote(client, method, req.unit, req.amount).await?;
+
+    Ok(Json(res))
 }
 
 #[axum_macros::debug_handler]
-pub async fn handle_method_quote_id(State(_state): State<AppState>) -> Result<(), AppError> {
-    // TODO: Implement this function
-    Ok(())
+pub async fn handle_method_quote_id(
+    Path((method, quote_id)): Path<(Method, String)>,
+    scope: FederationScope,
+    State(state): State<AppState>,
+) -> Result<Json<PostMintQuoteMethodResponse>, AppError> {
+    let quote_id = parse_quote_id(&quote_id)?;
+    let (client, quote) = get_mint_quote(&state, scope, quote_id).await?;
+    if quote.method != method {
+        return Err(AppError::new(
+            StatusCode::BAD_REQUEST,
+            anyhow!("Quote {} is not a {:?} quote", quote_id, method),
+        ));
+    }
+
+    // Expired quotes are reported like any other, an unpaid one stays unpaid
+    // and wallets can tell from its expiry that it can no longer be paid
+    let quote_state = mint_quote_state(&client, quote_id, &quote).await?;
+
+    Ok(Json(PostMintQuoteMethodResponse::new(
+        quote_id,
+        &quote,
+        quote_state,
+    )))
+}
+
+/// Creates an invoice or deposit address for `amount` and persists it as a
+/// mint quote in the client database
+pub async fn create_mint_quote(
+    client: ClientArc,
+    method: Method,
+    unit: Unit,
+    amount: Amount,
+) -> Result<PostMintQuoteMethodResponse, AppError> {
+    let amount_msat = unit.to_amount(amount.msats);
+    let (operation_id, request, expiry) = match method {
+        Method::Bolt11 => mint_bolt11(&client, amount_msat).await?,
+        Method::Onchain => mint_onchain(&client).await?,
+    };
+
+    let quote = MintQuote {
+        method,
+        unit,
+        amount: amount_msat,
+        request,
+        expiry,
+        federation_id: client.federation_id(),
+    };
+
+    let mut dbtx = client.db().begin_transaction().await;
+    dbtx.insert_new_entry(&MintQuoteKey(operation_id), &quote)
+        .await;
+    dbtx.commit_tx_result().await?;
+    follow_mint_quote(&client, operation_id, &quote);
+
+    Ok(PostMintQuoteMethodResponse::new(
+        operation_id,
+        &quote,
+        MintQuoteState::Unpaid,
+    ))
+}
+
+async fn mint_bolt11(
+    client: &ClientArc,
+    amount_msat: Amount,
+) -> Result<(OperationId, String, u64), AppError> {
+    let lightning_module = client.get_first_module::<LightningClientModule>();
+    lightning_module.select_active_gateway().await?;
+
+    let valid_until = now() + Duration::from_secs(DEFAULT_MINT_EXPIRY_OFFSET);
+    let expiry_time = system_time_to_u64(valid_until)?;
+
+    let (operation_id, invoice) = lightning_module
+        .create_bolt11_invoice(
+            amount_msat,
+            format!("{}, method={:?}", DEFAULT_MINT_DESCRIPTION, Method::Bolt11),
+            Some(DEFAULT_MINT_EXPIRY_OFFSET),
+            (),
+        )
+        .await?;
+
+    Ok((operation_id, invoice.to_string(), expiry_time))
+}
+
+async fn mint_onchain(client: &ClientArc) -> Result<(OperationId, String, u64), AppError> {
+    let wallet_client = client.get_first_module::<WalletClientModule>();
+    let valid_until = now() + Duration::from_secs(DEFAULT_MINT_EXPIRY_OFFSET);
+    let expiry_time = system_time_to_u64(valid_until)?;
+
+    let (operation_id, address) = wallet_client.get_deposit_address(valid_until, ()).await?;
+
+    Ok((operation_id, address.to_string(), expiry_time))
+}
+
+pub fn parse_quote_id(quote_id: &str) -> Result<OperationId, AppError> {
+    quote_id.parse().map_err(|e| {
+        AppError::new(
+            StatusCode::BAD_REQUEST,
+            anyhow!("Invalid quote id {}: {}", quote_id, e),
+        )
+    })
+}
+
+/// Looks up a mint quote in the databases of the federations visible to the
+/// request
+pub async fn get_mint_quote(
+    state: &AppState,
+    scope: FederationScope,
+    quote_id: OperationId,
+) -> Result<(ClientArc, MintQuote), AppError> {
+    for client in scope.clients(state).await? {
+        let quote = client
+            .db()
+            .begin_transaction_nc()
+            .await
+            .get_value(&MintQuoteKey(quote_id))
+            .await;
+        if let Some(quote) = quote {
+            return Ok((client, quote));
+        }
+    }
+
+    Err(AppError::new(
+        StatusCode::NOT_FOUND,
+        anyhow!("Quote {} not found", quote_id),
+    ))
+}
+
+/// Reads the state of a mint quote as last recorded from the lightning receive
+/// or deposit operation backing it, without waiting on the operation
+pub async fn mint_quote_state(
+    client: &ClientArc,
+    quote_id: OperationId,
+    quote: &MintQuote,
+) -> Result<MintQuoteState, AppError> {
+    let mut dbtx = client.db().begin_transaction_nc().await;
+    if dbtx
+        .get_value(&MintQuoteIssuedKey(quote_id))
+        .await
+        .is_some()
+    {
+        return Ok(MintQuoteState::Issued);
+    }
+
+    // Quotes are followed from their creation on, this resumes following
+    // quotes created before a restart
+    follow_mint_quote(client, quote_id, quote);
+    Ok(dbtx
+        .get_value(&MintQuoteStateKey(quote_id))
+        .await
+        .unwrap_or(MintQuoteState::Unpaid))
+}
+
+/// Records the state of the lightning receive or deposit backing a mint quote
+/// in the background each time it progresses
+pub fn follow_mint_quote(client: &ClientArc, quote_id: OperationId, quote: &MintQuote) {
+    let client = client.clone();
+    let (method, amount) = (quote.method, quote.amount);
+    spawn_follower(quote_id, async move {
+        let mut updates = match method {
+            Method::Bolt11 => client
+                .get_first_module::<LightningClientModule>()
+                .subscribe_ln_receive(quote_id)
+                .await?
+                .into_stream()
+                .map(|update| match update {
+                    LnReceiveState::Claimed => MintQuoteState::Paid,
+                    LnReceiveState::Funded | LnReceiveState::AwaitingFunds => {
+                        MintQuoteState::Pending
+                    }
+                    _ => MintQuoteState::Unpaid,
+                })
+                .boxed(),
+            Method::Onchain => {
+                let peg_in_fee = peg_in_fee(&client)?;
+                client
+                    .get_first_module::<WalletClientModule>()
+                    .subscribe_deposit_updates(quote_id)
+                    .await?
+                    .into_stream()
+                    .map(move |update| deposit_quote_state(&update, amount, peg_in_fee))
+                    .boxed()
+            }
+        };
+
+        while let Some(quote_state) = updates.next().await {
+            let mut dbtx = client.db().begin_transaction().await;
+            dbtx.insert_entry(&MintQuoteStateKey(quote_id), &quote_state)
+                .await;
+            dbtx.commit_tx_result().await?;
+        }
+        Ok(())
+    });
+}
+
+/// State of an on-chain mint quote after a deposit update. The quote is only
+/// paid once the deposit claimed into the wallet covers its amount, a deposit
+/// paying less than quoted leaves it pending.
+fn deposit_quote_state(
+    update: &DepositState,
+    amount: Amount,
+    peg_in_fee: Amount,
+) -> MintQuoteState {
+    match update {
+        DepositState::Claimed(deposit) if deposit_value(deposit, peg_in_fee) >= amount => {
+            MintQuoteState::Paid
+        }
+        DepositState::WaitingForConfirmation(_)
+        | DepositState::Confirmed(_)
+        | DepositState::Claimed(_) => MintQuoteState::Pending,
+        _ => MintQuoteState::Unpaid,
+    }
+}
+
+/// E-cash the federation issues for a deposit: the value of the deposit output
+/// minus the federation's peg-in fee
+fn deposit_value(deposit: &BitcoinTransactionData, peg_in_fee: Amount) -> Amount {
+    let sats = deposit
+        .btc_transaction
+        .output
+        .get(deposit.out_idx as usize)
+        .map_or(0, |output| output.value);
+    Amount::from_sats(sats).saturating_sub(peg_in_fee)
+}
+
+fn peg_in_fee(client: &ClientArc) -> anyhow::Result<Amount> {
+    let (_, config) = client
+        .get_config()
+        .get_first_module_by_kind::<WalletClientConfig>(fedimint_wallet_client::KIND)?;
+    Ok(config.fee_consensus.peg_in_abs)
+}
+
+#[cfg(test)]
+mod tests {
+    use bitcoin::{PackedLockTime, Script, Transaction, TxOut};
+
+    use super::*;
+
+    fn deposit(values: &[u64], out_idx: u32) -> BitcoinTransactionData {
+        BitcoinTransactionData {
+            btc_transaction: Transaction {
+                version: 2,
+                lock_time: PackedLockTime::ZERO,
+                input: vec![],
+                output: values
+                    .iter()
+                    .map(|value| TxOut {
+                        value: *value,
+                        script_pubkey: Script::new(),
+                    })
+                    .collect(),
+            },
+            out_idx,
+        }
+    }
+
+    #[test]
+    fn claimed_deposit_pays_quote_only_if_it_covers_the_amount() {
+        let amount = Amount::from_sats(10_000);
+        let fee = Amount::from_sats(100);
+        let claimed = |values: &[u64], out_idx| {
+            deposit_quote_state(
+                &DepositState::Claimed(deposit(values, out_idx)),
+                amount,
+                fee,
+            )
+        };
+
+        assert_eq!(claimed(&[10_100], 0), MintQuoteState::Paid);
+        assert_eq!(claimed(&[50_000, 10_200], 1), MintQuoteState::Paid);
+        assert_eq!(claimed(&[10_099], 0), MintQuoteState::Pending);
+        assert_eq!(claimed(&[50_000, 10_000], 1), MintQuoteState::Pending);
+        assert_eq!(claimed(&[50_000], 1), MintQuoteState::Pending);
+        assert_eq!(
+            deposit_quote_state(&DepositState::Confirmed(deposit(&[10_100], 0)), amount, fee),
+            MintQuoteState::Pending
+        );
+    }
 }
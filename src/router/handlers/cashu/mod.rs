@@ -1,23 +1,523 @@
+use std::collections::BTreeMap;
+use std::convert::Infallible;
+use std::time::Duration;
+
+use anyhow::anyhow;
+use axum::async_trait;
+use axum::extract::{FromRequestParts, Request, State};
+use axum::http::request::Parts;
+use axum::http::{StatusCode, Uri};
+use axum::middleware::Next;
+use axum::response::{IntoResponse, Response};
+use bitcoin_hashes::hex::ToHex;
+use bitcoin_hashes::{sha256, Hash, HashEngine};
+use clap::{Args, ValueEnum};
+use fedimint_client::ClientArc;
+use fedimint_core::config::FederationId;
+use fedimint_core::core::OperationId;
+use fedimint_core::db::IDatabaseTransactionOpsCoreTyped;
+use fedimint_core::encoding::{Decodable, Encodable};
+use fedimint_core::Amount;
+use fedimint_mint_client::common::config::MintClientConfig;
+use fedimint_mint_client::OOBNotes;
+use futures_util::StreamExt;
 use serde::{Deserialize, Serialize};
+use tracing::{error, info};
+
+use crate::db::{
+    MeltQuote, MeltQuoteKey, MeltQuoteKeyPrefix, MintQuoteIssuedKey, MintQuoteKeyPrefix,
+};
+use crate::error::AppError;
+use crate::notes::spend_exact_with_change;
+use crate::router::handlers::cashu::error::{into_nut00, CashuError};
+use crate::router::handlers::cashu::info::Contact;
+use crate::router::handlers::cashu::melt::quote::follow_melt_payment;
+use crate::router::handlers::cashu::mint::quote::follow_mint_quote;
+use crate::state::AppState;
 
 pub mod check;
+pub mod error;
 pub mod info;
 pub mod keys;
 pub mod keysets;
 pub mod melt;
 pub mod mint;
+pub mod payment_request;
+pub mod restore;
 pub mod swap;
+pub mod token;
+pub mod ws;
 
-#[derive(Debug, Deserialize)]
+#[derive(
+    Debug, Clone, Copy, PartialEq, Eq, Deserialize, Serialize, Encodable, Decodable, ValueEnum,
+)]
 #[serde(rename_all = "lowercase")]
 pub enum Unit {
     Msat,
     Sat,
 }
 
-#[derive(Debug, Deserialize, Serialize)]
+impl Unit {
+    pub const ALL: [Unit; 2] = [Unit::Sat, Unit::Msat];
+
+    pub fn as_str(self) -> &'static str {
+        match self {
+            Unit::Msat => "msat",
+            Unit::Sat => "sat",
+        }
+    }
+
+    /// Converts a fedimint amount into this unit, if it can be represented
+    /// exactly
+    pub fn value_of(self, amount: Amount) -> Option<u64> {
+        match self {
+            Unit::Msat => Some(amount.msats),
+            Unit::Sat => {
+                let sats = amount.msats / 1000;
+                (Amount::from_sats(sats) == amount).then_some(sats)
+            }
+        }
+    }
+
+    /// Converts a fedimint amount into this unit, rounding down to a whole unit
+    pub fn value_of_floor(self, amount: Amount) -> u64 {
+        match self {
+            Unit::Msat => amount.msats,
+            Unit::Sat => amount.msats / 1000,
+        }
+    }
+
+    /// Converts a fedimint amount into this unit, rounding up to the next whole
+    /// unit
+    pub fn value_of_ceil(self, amount: Amount) -> u64 {
+        match self {
+            Unit::Msat => amount.msats,
+            Unit::Sat => amount.msats.div_ceil(1000),
+        }
+    }
+
+    pub fn to_amount(self, value: u64) -> Amount {
+        match self {
+            Unit::Msat => Amount::from_msats(value),
+            Unit::Sat => Amount::from_sats(value),
+        }
+    }
+
+    /// Converts a value in this unit into a fedimint amount, `None` if it does
+    /// not fit
+    pub fn checked_to_amount(self, value: u64) -> Option<Amount> {
+        match self {
+            Unit::Msat => Some(Amount::from_msats(value)),
+            Unit::Sat => value.checked_mul(1000).map(Amount::from_msats),
+        }
+    }
+}
+
+#[derive(
+    Debug, Clone, Copy, PartialEq, Eq, Deserialize, Serialize, Encodable, Decodable, ValueEnum,
+)]
 #[serde(rename_all = "lowercase")]
 pub enum Method {
     Bolt11,
     Onchain,
 }
+
+impl Method {
+    pub const ALL: [Method; 2] = [Method::Bolt11, Method::Onchain];
+
+    pub fn as_str(self) -> &'static str {
+        match self {
+            Method::Bolt11 => "bolt11",
+            Method::Onchain => "onchain",
+        }
+    }
+}
+
+/// Path the Cashu API is served under
+pub const CASHU_V1_PATH: &str = "/cashu/v1";
+
+/// Federation a Cashu request is scoped to. Set by [`scope_federation`] for
+/// requests made under `/cashu/v1/{federation}/...`, unscoped requests are
+/// served by the default federation alone.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct FederationScope(pub Option<FederationId>);
+
+#[async_trait]
+impl<S: Send + Sync> FromRequestParts<S> for FederationScope {
+    type Rejection = Infallible;
+
+    async fn from_request_parts(parts: &mut Parts, _state: &S) -> Result<Self, Self::Rejection> {
+        Ok(parts
+            .extensions
+            .get::<FederationScope>()
+            .copied()
+            .unwrap_or_default())
+    }
+}
+
+impl FederationScope {
+    /// Returns the client of the scoped federation, or of `federation_id` for
+    /// unscoped requests
+    pub async fn client(
+        self,
+        state: &AppState,
+        federation_id: Option<FederationId>,
+    ) -> Result<ClientArc, AppError> {
+        state.get_client(self.0.or(federation_id)).await
+    }
+
+    /// Returns the clients visible to the request: the scoped federation's
+    /// client, or the default federation's client for unscoped requests
+    pub async fn clients(self, state: &AppState) -> Result<Vec<ClientArc>, AppError> {
+        Ok(vec![state.get_client(self.0).await?])
+    }
+
+    /// Rejects clients of other federations than the scoped one, or than the
+    /// default federation for unscoped requests
+    pub async fn ensure(self, state: &AppState, client: &ClientArc) -> Result<(), AppError> {
+        let federation_id = state.get_client(self.0).await?.federation_id();
+        if federation_id != client.federation_id() {
+            return Err(AppError::new(
+                StatusCode::BAD_REQUEST,
+                anyhow!("Not a keyset or note of federation {}", federation_id),
+            ));
+        }
+        Ok(())
+    }
+}
+
+/// Rewrites requests for `/cashu/v1/{federation}/...`, where `{federation}`
+/// is a joined federation's id or 8 character id prefix, to the unprefixed
+/// Cashu routes and scopes them to that federation. Has to wrap the router
+/// since routing happens before any layer added to it runs.
+pub async fn scope_federation(
+    State(state): State<AppState>,
+    mut req: Request,
+    next: Next,
+) -> Response {
+    let Some((segment, rest)) = req
+        .uri()
+        .path()
+        .strip_prefix(CASHU_V1_PATH)
+        .and_then(|path| path.strip_prefix('/'))
+        .map(|path| path.split_once('/').unwrap_or((path, "")))
+    else {
+        return next.run(req).await;
+    };
+
+    let is_federation =
+        matches!(segment.len(), 8 | 64) && segment.chars().all(|c| c.is_ascii_hexdigit());
+    if !is_federation {
+        return next.run(req).await;
+    }
+
+    let Some(federation_id) = state.find_federation(segment).await else {
+        let response = AppError::new(
+            StatusCode::NOT_FOUND,
+            anyhow!("Not joined to federation {}", segment),
+        )
+        .into_response();
+        return into_nut00(response).await;
+    };
+
+    let path_and_query = match req.uri().query() {
+        Some(query) => format!("{CASHU_V1_PATH}/{rest}?{query}"),
+        None => format!("{CASHU_V1_PATH}/{rest}"),
+    };
+    let mut parts = req.uri().clone().into_parts();
+    parts.path_and_query = path_and_query.parse().ok();
+    match Uri::from_parts(parts) {
+        Ok(uri) => *req.uri_mut() = uri,
+        Err(e) => return AppError::new(StatusCode::BAD_REQUEST, e).into_response(),
+    }
+    req.extensions_mut()
+        .insert(FederationScope(Some(federation_id)));
+
+    next.run(req).await
+}
+
+/// Mint information and enabled payment methods of the Cashu API
+#[derive(Debug, Clone, Args)]
+pub struct CashuConfig {
+    /// Name of the mint, defaults to the federation name
+    #[clap(long = "cashu-name", env = "CASHU_NAME")]
+    pub name: Option<String>,
+
+    /// Short description of the mint
+    #[clap(long = "cashu-description", env = "CASHU_DESCRIPTION")]
+    pub description: Option<String>,
+
+    /// Long description of the mint
+    #[clap(long = "cashu-description-long", env = "CASHU_DESCRIPTION_LONG")]
+    pub description_long: Option<String>,
+
+    /// Comma separated contact information as `method:value`, e.g.
+    /// `email:admin@example.com`
+    #[clap(long = "cashu-contact", env = "CASHU_CONTACT", value_delimiter = ',')]
+    pub contact: Vec<Contact>,
+
+    /// Message of the day, defaults to the federation's welcome message
+    #[clap(long = "cashu-motd", env = "CASHU_MOTD")]
+    pub motd: Option<String>,
+
+    /// URL of the mint's icon, defaults to the federation's icon
+    #[clap(long = "cashu-icon-url", env = "CASHU_ICON_URL")]
+    pub icon_url: Option<String>,
+
+    /// Payment methods wallets can mint and melt with
+    #[clap(
+        long = "cashu-methods",
+        env = "CASHU_METHODS",
+        value_delimiter = ',',
+        default_values = ["bolt11", "onchain"]
+    )]
+    pub methods: Vec<Method>,
+
+    /// Units wallets can mint and melt in
+    #[clap(
+        long = "cashu-units",
+        env = "CASHU_UNITS",
+        value_delimiter = ',',
+        default_values = ["sat", "msat"]
+    )]
+    pub units: Vec<Unit>,
+
+    /// Public url of this server, used for the mint urls and transport of
+    /// NUT-18 payment requests
+    #[clap(long = "cashu-public-url", env = "CASHU_PUBLIC_URL")]
+    pub public_url: Option<String>,
+
+    /// Record the signatures of the notes issued for wallet outputs so wallets
+    /// can recover them with NUT-09 restore
+    #[clap(long = "cashu-restore", env = "CASHU_RESTORE")]
+    pub restore: bool,
+}
+
+impl CashuConfig {
+    /// Returns whether minting and melting with `method` in `unit` is enabled.
+    /// Onchain payments can only be denominated in sats.
+    pub fn is_enabled(&self, method: Method, unit: Unit) -> bool {
+        self.methods.contains(&method)
+            && self.units.contains(&unit)
+            && (method != Method::Onchain || unit == Unit::Sat)
+    }
+
+    pub fn ensure_enabled(&self, method: Method, unit: Unit) -> Result<(), AppError> {
+        if !self.units.contains(&unit) {
+            return Err(AppError::new(
+                StatusCode::BAD_REQUEST,
+                CashuError::UnitNotSupported(unit),
+            ));
+        }
+        if !self.is_enabled(method, unit) {
+            return Err(AppError::new(
+                StatusCode::BAD_REQUEST,
+                anyhow!(
+                    "Method {} is not enabled for unit {}",
+                    method.as_str(),
+                    unit.as_str()
+                ),
+            ));
+        }
+        Ok(())
+    }
+
+    /// Public url of the Cashu mint serving `federation_id`
+    pub fn mint_url(&self, federation_id: FederationId) -> Option<String> {
+        let public_url = self.public_url.as_deref()?.trim_end_matches('/');
+        let prefix = &federation_id.to_string()[..8];
+        Some(format!("{public_url}{CASHU_V1_PATH}/{prefix}"))
+    }
+}
+
+/// Time after which the client reclaims issued notes the wallet never redeemed
+pub const ISSUED_NOTES_RECLAIM_AFTER: u64 = 7 * 24 * 3600;
+
+/// Version byte prepended to every keyset id, as specified by NUT-02
+const KEYSET_ID_VERSION: &str = "00";
+
+/// A Cashu keyset as returned by NUT-01: the public key for every amount the
+/// mint can sign, keyed by the amount in `unit`.
+#[derive(Debug, Serialize)]
+pub struct Keyset {
+    pub id: String,
+    pub unit: Unit,
+    pub keys: BTreeMap<u64, String>,
+}
+
+/// Returns the mint module config of the federation the client is joined to
+pub fn get_mint_config(client: &ClientArc) -> Result<&MintClientConfig, AppError> {
+    let (_, config) = client
+        .get_config()
+        .get_first_module_by_kind::<MintClientConfig>(fedimint_mint_client::KIND)
+        .map_err(|e| AppError::new(StatusCode::INTERNAL_SERVER_ERROR, e))?;
+    Ok(config)
+}
+
+/// Derives the keyset id for the client's federation and `unit`.
+///
+/// Follows the NUT-02 derivation: the public keys are sorted by amount,
+/// concatenated and hashed with SHA256, and the first 7 bytes of the hash are
+/// prefixed with the version byte. Since fedimint signs every unit with the
+/// same keys the unit is appended before hashing, so each unit gets its own
+/// stable id.
+pub fn keyset_id(client: &ClientArc, unit: Unit) -> Result<String, AppError> {
+    let config = get_mint_config(client)?;
+    // `Tiered` is backed by a `BTreeMap`, so the keys are already sorted by amount
+    let keys = config
+        .tbs_pks
+        .iter()
+        .map(|(_, pk)| {
+            pk.consensus_encode_to_vec().map_err(|e| {
+                AppError::new(
+                    StatusCode::INTERNAL_SERVER_ERROR,
+                    anyhow!("Failed to encode mint public key: {e}"),
+                )
+            })
+        })
+        .collect::<Result<Vec<_>, _>>()?;
+    Ok(derive_keyset_id(&keys, unit.as_str().as_bytes()))
+}
+
+/// NUT-02 keyset id of the encoded public keys, which must be sorted by
+/// amount, with `suffix` hashed after the keys
+fn derive_keyset_id(keys: &[Vec<u8>], suffix: &[u8]) -> String {
+    let mut engine = sha256::Hash::engine();
+    for key in keys {
+        engine.input(key);
+    }
+    engine.input(suffix);
+    let hash = sha256::Hash::from_engine(engine);
+
+    format!("{}{}", KEYSET_ID_VERSION, &hash[..7].to_hex())
+}
+
+/// Builds the NUT-01 keyset for the client's federation and `unit` from the
+/// mint module's per-denomination aggregate public keys. Denominations that
+/// cannot be expressed in `unit` are left out.
+pub fn get_keyset(client: &ClientArc, unit: Unit) -> Result<Keyset, AppError> {
+    let config = get_mint_config(client)?;
+    let mut keys = BTreeMap::new();
+    for (amount, pk) in config.tbs_pks.iter() {
+        if let Some(value) = unit.value_of(amount) {
+            keys.insert(value, encode_hex(pk)?);
+        }
+    }
+
+    Ok(Keyset {
+        id: keyset_id(client, unit)?,
+        unit,
+        keys,
+    })
+}
+
+/// Builds one keyset per supported unit for the client's federation
+pub fn get_keysets(client: &ClientArc) -> Result<Vec<Keyset>, AppError> {
+    Unit::ALL
+        .iter()
+        .map(|unit| get_keyset(client, *unit))
+        .collect()
+}
+
+/// Follows the operations behind the quotes that may still progress again
+/// after a restart, so that their recorded states and NUT-17 subscriptions
+/// stay current without anyone polling them
+pub async fn resume_followers(state: &AppState) {
+    for client in state.multimint.all().await {
+        let mut dbtx = client.db().begin_transaction_nc().await;
+        let mint_quotes = dbtx
+            .find_by_prefix(&MintQuoteKeyPrefix)
+            .await
+            .collect::<Vec<_>>()
+            .await;
+        for (key, quote) in mint_quotes {
+            if dbtx.get_value(&MintQuoteIssuedKey(key.0)).await.is_none() {
+                follow_mint_quote(&client, key.0, &quote);
+            }
+        }
+
+        let melt_quotes = dbtx
+            .find_by_prefix(&MeltQuoteKeyPrefix)
+            .await
+            .collect::<Vec<_>>()
+            .await;
+        for (key, quote) in melt_quotes {
+            if quote.executing {
+                release_melt_quote(&client, key, quote.clone()).await;
+            }
+            if let (Some(payment), None) = (quote.payment, &quote.change) {
+                follow_melt_payment(&client, payment);
+            }
+        }
+    }
+}
+
+/// Releases the claim on a melt quote left by a request that was interrupted
+/// by a restart, no request survives one. The quote can then be retried: a
+/// funded quote pays from its funding and a lightning payment that did go
+/// out is found again by its payment hash.
+async fn release_melt_quote(client: &ClientArc, key: MeltQuoteKey, quote: MeltQuote) {
+    let mut dbtx = client.db().begin_transaction().await;
+    let released = MeltQuote {
+        executing: false,
+        ..quote
+    };
+    dbtx.insert_entry(&key, &released).await;
+    match dbtx.commit_tx_result().await {
+        Ok(()) => info!("Released interrupted melt quote {}", key.0),
+        Err(e) => error!("Failed to release interrupted melt quote {}: {e}", key.0),
+    }
+}
+
+/// Spends notes worth exactly `amount` out of the client's wallet, making
+/// change first if the wallet lacks the denominations to do so
+pub async fn spend_exact(
+    client: &ClientArc,
+    amount: Amount,
+) -> Result<(OperationId, OOBNotes), AppError> {
+    let timeout = Duration::from_secs(ISSUED_NOTES_RECLAIM_AFTER);
+    let (operation_id, notes, _) = spend_exact_with_change(client, amount, timeout).await?;
+    Ok((operation_id, notes))
+}
+
+fn encode_hex(value: &impl Encodable) -> Result<String, AppError> {
+    value.consensus_encode_to_hex().map_err(|e| {
+        AppError::new(
+            StatusCode::INTERNAL_SERVER_ERROR,
+            anyhow!("Failed to hex encode: {e}"),
+        )
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use bitcoin_hashes::hex::FromHex;
+
+    use super::*;
+
+    #[test]
+    fn keyset_id_matches_nut02_vector() {
+        let keys = [
+            "03a40f20667ed53513075dc51e715ff2046cad64eb68960632269ba7f0210e38bc",
+            "03fd4ce5a16b65576145949e6f99f445f8249fee17c606b688b504a849cdc452de",
+            "02648eccfa4c026960966276fa5a4cae46ce0fd432211a4f449bf84f13aa5f8303",
+            "02fdfd6796bfeac490cbee12f778f867f0a2c68f6508d17c649759ea0dc3547528",
+        ]
+        .iter()
+        .map(|key| Vec::<u8>::from_hex(key).unwrap())
+        .collect::<Vec<_>>();
+
+        assert_eq!(derive_keyset_id(&keys, &[]), "00456a94ab4e1c46");
+    }
+
+    #[test]
+    fn keyset_ids_differ_per_unit() {
+        let keys = vec![vec![2u8; 48], vec![3u8; 48]];
+        let sat = derive_keyset_id(&keys, Unit::Sat.as_str().as_bytes());
+        let msat = derive_keyset_id(&keys, Unit::Msat.as_str().as_bytes());
+
+        assert_ne!(sat, msat);
+        assert_eq!(sat.len(), 16);
+        assert!(sat.starts_with(KEYSET_ID_VERSION));
+    }
+}
@@ -0,0 +1,657 @@
+use std::fmt;
+use std::str::FromStr;
+use std::time::Duration;
+
+use anyhow::{anyhow, bail};
+use axum::extract::{Path, State};
+use axum::http::StatusCode;
+use axum::Json;
+use base64::engine::general_purpose::URL_SAFE_NO_PAD;
+use base64::Engine;
+use fedimint_client::ClientArc;
+use fedimint_core::config::FederationId;
+use fedimint_core::core::OperationId;
+use fedimint_core::db::IDatabaseTransactionOpsCoreTyped;
+use fedimint_core::Amount;
+use fedimint_mint_client::MintClientModule;
+use serde::{Deserialize, Serialize};
+use tracing::info;
+use url::Url;
+
+use crate::db::{PaymentRequest as PaymentRequestRecord, PaymentRequestKey};
+use crate::error::AppError;
+use crate::notes::spend_exact_with_change;
+use crate::router::handlers::cashu::check::record_notes;
+use crate::router::handlers::cashu::error::CashuError;
+use crate::router::handlers::cashu::mint::quote::parse_quote_id;
+use crate::router::handlers::cashu::swap::reissue_notes;
+use crate::router::handlers::cashu::token::{decode_base64, CashuToken, Proof};
+use crate::router::handlers::cashu::{
+    FederationScope, Unit, CASHU_V1_PATH, ISSUED_NOTES_RECLAIM_AFTER,
+};
+use crate::state::AppState;
+
+const PAYMENT_REQUEST_PREFIX: &str = "creqA";
+const POST_TRANSPORT: &str = "post";
+const RECEIVE_PATH: &str = "/payment-request/receive";
+
+/// A NUT-18 payment request, serialized as `creqA` followed by its base64url
+/// encoded CBOR
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct PaymentRequest {
+    /// Payment id to be included in the payload
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub i: Option<String>,
+    /// Amount
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub a: Option<u64>,
+    /// Unit
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub u: Option<Unit>,
+    /// Single use
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub s: Option<bool>,
+    /// Accepted mint urls
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub m: Option<Vec<String>>,
+    /// Description
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub d: Option<String>,
+    /// Transports the payload can be sent over
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub t: Vec<Transport>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Transport {
+    /// Transport type: `post` or `nostr`
+    pub t: String,
+    /// Target: an http url for `post`, an nprofile for `nostr`
+    pub a: String,
+    /// Tags
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub g: Option<Vec<Vec<String>>>,
+}
+
+impl FromStr for PaymentRequest {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let Some(request) = s.trim().strip_prefix(PAYMENT_REQUEST_PREFIX) else {
+            bail!("Not a payment request, expected a creqA prefix")
+        };
+        let cbor = decode_base64(request)?;
+        Ok(ciborium::from_reader(cbor.as_slice())?)
+    }
+}
+
+impl fmt::Display for PaymentRequest {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let mut cbor = Vec::new();
+        ciborium::into_writer(self, &mut cbor).map_err(|_| fmt::Error)?;
+        write!(
+            f,
+            "{PAYMENT_REQUEST_PREFIX}{}",
+            URL_SAFE_NO_PAD.encode(cbor)
+        )
+    }
+}
+
+/// The payload a payer sends to the `post` transport of a payment request
+#[derive(Debug, Serialize, Deserialize)]
+pub struct PaymentRequestPayload {
+    pub id: Option<String>,
+    pub memo: Option<String>,
+    pub mint: String,
+    pub unit: Unit,
+    pub proofs: Vec<Proof>,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct CreatePaymentRequest {
+    pub amount: Option<u64>,
+    pub unit: Option<Unit>,
+    pub description: Option<String>,
+    #[serde(default = "default_single_use")]
+    pub single_use: bool,
+    /// Federations whose notes are accepted, defaults to the scoped or the
+    /// default federation
+    #[serde(default)]
+    pub federation_ids: Vec<FederationId>,
+}
+
+fn default_single_use() -> bool {
+    true
+}
+
+#[derive(Debug, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct CreatePaymentRequestResponse {
+    pub id: String,
+    pub request: String,
+}
+
+#[derive(Debug, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct PaymentRequestStatus {
+    pub id: String,
+    pub amount: Option<u64>,
+    pub unit: Unit,
+    pub description: Option<String>,
+    pub single_use: bool,
+    pub federation_ids: Vec<FederationId>,
+    pub received_msat: Amount,
+    pub payments: Vec<OperationId>,
+    pub fulfilled: bool,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct PayPaymentRequest {
+    /// A `creqA` payment request
+    pub request: String,
+    /// Amount to pay, in the request's unit, if the request leaves it open
+    pub amount: Option<u64>,
+    pub memo: Option<String>,
+    /// Federation to pay from if the request does not restrict the mints
+    pub federation_id: Option<FederationId>,
+}
+
+#[derive(Debug, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct PayPaymentRequestResponse {
+    pub operation_id: OperationId,
+    pub amount_msat: Amount,
+    /// The sent token, to be handed over out of band if the request has no
+    /// transport
+    pub token: String,
+    /// Whether the payload was delivered over the request's `post` transport
+    pub delivered: bool,
+}
+
+#[axum_macros::debug_handler]
+pub async fn handle_create(
+    scope: FederationScope,
+    State(state): State<AppState>,
+    Json(req): Json<CreatePaymentRequest>,
+) -> Result<Json<CreatePaymentRequestResponse>, AppError> {
+    if state.cashu.public_url.is_none() {
+        return Err(AppError::new(
+            StatusCode::BAD_REQUEST,
+            anyhow!("Payment requests need CASHU_PUBLIC_URL to be set"),
+        ));
+    }
+    let unit = req.unit.unwrap_or(Unit::Sat);
+    if !state.cashu.units.contains(&unit) {
+        return Err(AppError::new(
+            StatusCode::BAD_REQUEST,
+            CashuError::UnitNotSupported(unit),
+        ));
+    }
+
+    let mut clients = Vec::new();
+    if req.federation_ids.is_empty() {
+        clients.push(scope.client(&state, None).await?);
+    }
+    for federation_id in &req.federation_ids {
+        let client = scope.client(&state, Some(*federation_id)).await?;
+        if client.federation_id() != *federation_id {
+            return Err(AppError::new(
+                StatusCode::BAD_REQUEST,
+                anyhow!(
+                    "Federation {} is outside the request's scope",
+                    federation_id
+                ),
+            ));
+        }
+        clients.push(client);
+    }
+    let federation_ids = clients
+        .iter()
+        .map(|client| client.federation_id())
+        .collect::<Vec<_>>();
+    let mints = federation_ids
+        .iter()
+        .filter_map(|federation_id| state.cashu.mint_url(*federation_id))
+        .collect::<Vec<_>>();
+    // Payments are received under the mint of the federation keeping the
+    // record, so that the request's scope is the same for status and receive
+    let receive_url = format!("{}{RECEIVE_PATH}", mints[0]);
+
+    let id = OperationId::new_random();
+    let record = PaymentRequestRecord {
+        amount: req.amount,
+        unit,
+        description: req.description,
+        single_use: req.single_use,
+        federation_ids,
+        received: Amount::ZERO,
+        payments: Vec::new(),
+        receiving: 0,
+    };
+    let request = PaymentRequest {
+        i: Some(id.to_string()),
+        a: record.amount,
+        u: Some(unit),
+        s: Some(record.single_use),
+        m: Some(mints),
+        d: record.description.clone(),
+        t: vec![Transport {
+            t: POST_TRANSPORT.to_string(),
+            a: receive_url,
+            g: None,
+        }],
+    };
+
+    let mut dbtx = clients[0].db().begin_transaction().await;
+    dbtx.insert_entry(&PaymentRequestKey(id), &record).await;
+    dbtx.commit_tx_result().await?;
+
+    Ok(Json(CreatePaymentRequestResponse {
+        id: id.to_string(),
+        request: request.to_string(),
+    }))
+}
+
+#[axum_macros::debug_handler]
+pub async fn handle_status(
+    Path(id): Path<String>,
+    scope: FederationScope,
+    State(state): State<AppState>,
+) -> Result<Json<PaymentRequestStatus>, AppError> {
+    let id = parse_quote_id(&id)?;
+    let (client, record) = get_payment_request(&state, id).await?;
+    scope.ensure(&state, &client).await?;
+    Ok(Json(PaymentRequestStatus {
+        id: id.to_string(),
+        amount: record.amount,
+        unit: record.unit,
+        fulfilled: record.is_fulfilled(),
+        description: record.description,
+        single_use: record.single_use,
+        federation_ids: record.federation_ids,
+        received_msat: record.received,
+        payments: record.payments,
+    }))
+}
+
+/// The `post` transport of the payment requests created by this server
+#[axum_macros::debug_handler]
+pub async fn handle_receive(
+    scope: FederationScope,
+    State(state): State<AppState>,
+    Json(payload): Json<PaymentRequestPayload>,
+) -> Result<Json<PaymentRequestStatus>, AppError> {
+    let id = payload.id.as_deref().ok_or_else(|| {
+        AppError::new(
+            StatusCode::BAD_REQUEST,
+            anyhow!("Payload does not name a payment request"),
+        )
+    })?;
+    let id = parse_quote_id(id)?;
+    let (record_client, record) = get_payment_request(&state, id).await?;
+    scope.ensure(&state, &record_client).await?;
+    if payload.unit != record.unit {
+        return Err(AppError::new(
+            StatusCode::BAD_REQUEST,
+            anyhow!("Payment request is denominated in {}", record.unit.as_str()),
+        ));
+    }
+
+    let token = CashuToken {
+        mint: Some(payload.mint),
+        unit: Some(payload.unit),
+        memo: payload.memo,
+        proofs: payload.proofs,
+    };
+    let notes = token.to_oob_notes(&state).await?;
+    let federation_id = record
+        .federation_ids
+        .iter()
+        .find(|id| id.to_prefix() == notes.federation_id_prefix());
+    let Some(federation_id) = federation_id.copied() else {
+        return Err(AppError::new(
+            StatusCode::BAD_REQUEST,
+            anyhow!("Payment request does not accept notes of this federation"),
+        ));
+    };
+    let amount = notes.total_amount();
+    if let Some(expected) = record.amount.map(|amount| record.unit.to_amount(amount)) {
+        if amount != expected {
+            return Err(AppError::new(
+                StatusCode::BAD_REQUEST,
+                CashuError::AmountMismatch {
+                    expected,
+                    actual: amount,
+                },
+            ));
+        }
+    }
+
+    let client = state.get_client(Some(federation_id)).await?;
+    start_receive(&record_client, id).await?;
+    let payment = reissue_notes(&client, notes).await;
+    finish_receive(
+        &record_client,
+        id,
+        payment
+            .as_ref()
+            .ok()
+            .map(|operation_id| (*operation_id, amount)),
+    )
+    .await?;
+    payment?;
+    info!("Received {amount} for payment request {id}");
+
+    handle_status(Path(id.to_string()), scope, State(state)).await
+}
+
+/// Claims a payment of the request before its notes are reissued. Fails if a
+/// single use request has been paid or is being paid concurrently.
+async fn start_receive(client: &ClientArc, id: OperationId) -> Result<(), AppError> {
+    let mut dbtx = client.db().begin_transaction().await;
+    let mut record = dbtx
+        .get_value(&PaymentRequestKey(id))
+        .await
+        .ok_or_else(|| {
+            AppError::new(
+                StatusCode::NOT_FOUND,
+                anyhow!("Payment request {} not found", id),
+            )
+        })?;
+    if record.single_use && (record.is_fulfilled() || record.receiving > 0) {
+        return Err(AppError::new(
+            StatusCode::BAD_REQUEST,
+            anyhow!("Payment request {} has already been paid", id),
+        ));
+    }
+    record.receiving += 1;
+    dbtx.insert_entry(&PaymentRequestKey(id), &record).await;
+    dbtx.commit_tx_result().await.map_err(|_| {
+        AppError::new(
+            StatusCode::CONFLICT,
+            anyhow!("Payment request is being paid concurrently"),
+        )
+    })?;
+    Ok(())
+}
+
+/// Records a received payment, or gives the claim back if the notes could not
+/// be reissued
+async fn finish_receive(
+    client: &ClientArc,
+    id: OperationId,
+    payment: Option<(OperationId, Amount)>,
+) -> Result<(), AppError> {
+    let mut dbtx = client.db().begin_transaction().await;
+    if let Some(mut record) = dbtx.get_value(&PaymentRequestKey(id)).await {
+        record.receiving = record.receiving.saturating_sub(1);
+        if let Some((operation_id, amount)) = payment {
+            record.received += amount;
+            record.payments.push(operation_id);
+        }
+        dbtx.insert_entry(&PaymentRequestKey(id), &record).await;
+    }
+    dbtx.commit_tx_result().await?;
+    Ok(())
+}
+
+#[axum_macros::debug_handler]
+pub async fn handle_pay(
+    scope: FederationScope,
+    State(state): State<AppState>,
+    Json(req): Json<PayPaymentRequest>,
+) -> Result<Json<PayPaymentRequestResponse>, AppError> {
+    let request = PaymentRequest::from_str(&req.request).map_err(|e| {
+        AppError::new(
+            StatusCode::BAD_REQUEST,
+            anyhow!("Invalid payment request: {}", e),
+        )
+    })?;
+    let unit = request.u.unwrap_or(Unit::Sat);
+    let amount = request.a.or(req.amount).ok_or_else(|| {
+        AppError::new(
+            StatusCode::BAD_REQUEST,
+            anyhow!("Payment request does not specify an amount, pass one"),
+        )
+    })?;
+    let transport = request.t.iter().find(|t| t.t == POST_TRANSPORT);
+    if transport.is_none() && !request.t.is_empty() {
+        return Err(AppError::new(
+            StatusCode::BAD_REQUEST,
+            anyhow!("Only the post transport is supported"),
+        ));
+    }
+    // Checked before any notes are spent
+    let target = transport
+        .map(|transport| transport_url(&transport.a))
+        .transpose()?;
+
+    let (client, mint_url) = select_mint(&state, scope, &request, req.federation_id).await?;
+    let timeout = Duration::from_secs(ISSUED_NOTES_RECLAIM_AFTER);
+    let (operation_id, notes, _) =
+        spend_exact_with_change(&client, unit.to_amount(amount), timeout).await?;
+    record_notes(&client, &notes).await?;
+    let mut token = CashuToken::from_oob_notes(&client, &notes, unit, Some(mint_url.clone()))?;
+    token.memo = req.memo.clone();
+
+    let delivered = match target {
+        Some(target) => {
+            let payload = PaymentRequestPayload {
+                id: request.i.clone(),
+                memo: req.memo,
+                mint: mint_url,
+                unit,
+                proofs: token.proofs.clone(),
+            };
+            if let Err(e) = post_payload(target, &payload).await {
+                client
+                    .get_first_module::<MintClientModule>()
+                    .try_cancel_spend_notes(operation_id)
+                    .await;
+                return Err(e);
+            }
+            true
+        }
+        None => false,
+    };
+
+    Ok(Json(PayPaymentRequestResponse {
+        operation_id,
+        amount_msat: notes.total_amount(),
+        token: token.to_string(),
+        delivered,
+    }))
+}
+
+/// Looks up a payment request in the databases of all joined federations,
+/// returning it together with the client whose database holds it
+async fn get_payment_request(
+    state: &AppState,
+    id: OperationId,
+) -> Result<(ClientArc, PaymentRequestRecord), AppError> {
+    for client in state.multimint.all().await {
+        let record = client
+            .db()
+            .begin_transaction_nc()
+            .await
+            .get_value(&PaymentRequestKey(id))
+            .await;
+        if let Some(record) = record {
+            return Ok((client, record));
+        }
+    }
+    Err(AppError::new(
+        StatusCode::NOT_FOUND,
+        anyhow!("Payment request {} not found", id),
+    ))
+}
+
+/// Picks the joined federation to pay from: the first one among the
+/// request's accepted mints, or any federation if the request accepts all
+async fn select_mint(
+    state: &AppState,
+    scope: FederationScope,
+    request: &PaymentRequest,
+    federation_id: Option<FederationId>,
+) -> Result<(ClientArc, String), AppError> {
+    let accepted = request.m.as_deref().unwrap_or_default();
+    if accepted.is_empty() {
+        let client = scope.client(state, federation_id).await?;
+        let mint_url = state
+            .cashu
+            .mint_url(client.federation_id())
+            .ok_or_else(|| {
+                AppError::new(
+                    StatusCode::BAD_REQUEST,
+                    anyhow!("Naming the paying mint needs CASHU_PUBLIC_URL to be set"),
+                )
+            })?;
+        return Ok((client, mint_url));
+    }
+
+    for mint_url in accepted {
+        let Some(federation_id) = federation_of_mint_url(state, mint_url).await else {
+            continue;
+        };
+        if scope.0.is_some_and(|scoped| scoped != federation_id) {
+            continue;
+        }
+        let client = state.get_client(Some(federation_id)).await?;
+        return Ok((client, mint_url.clone()));
+    }
+    Err(AppError::new(
+        StatusCode::BAD_REQUEST,
+        anyhow!("None of the accepted mints is a joined federation"),
+    ))
+}
+
+/// Resolves a mint url of the form `.../cashu/v1/{federation}` to a joined
+/// federation
+async fn federation_of_mint_url(state: &AppState, mint_url: &str) -> Option<FederationId> {
+    let url = Url::parse(mint_url).ok()?;
+    let (path, segment) = url.path().trim_end_matches('/').rsplit_once('/')?;
+    if !path.ends_with(CASHU_V1_PATH) || segment.is_empty() {
+        return None;
+    }
+    state.find_federation(segment).await
+}
+
+/// Parses the target of a `post` transport, which has to be an http(s) url
+fn transport_url(target: &str) -> Result<Url, AppError> {
+    let url = Url::parse(target).map_err(|e| {
+        AppError::new(
+            StatusCode::BAD_REQUEST,
+            anyhow!("Invalid post transport target {}: {}", target, e),
+        )
+    })?;
+    if !matches!(url.scheme(), "http" | "https") {
+        return Err(AppError::new(
+            StatusCode::BAD_REQUEST,
+            anyhow!("Post transport target {} is not an http(s) url", target),
+        ));
+    }
+    Ok(url)
+}
+
+async fn post_payload(target: Url, payload: &PaymentRequestPayload) -> Result<(), AppError> {
+    let bad_gateway = |e: anyhow::Error| {
+        AppError::new(
+            StatusCode::BAD_GATEWAY,
+            anyhow!("Sending payment to {} failed: {}", target, e),
+        )
+    };
+    let res = reqwest::Client::new()
+        .post(target.clone())
+        .json(payload)
+        .send()
+        .await
+        .map_err(|e| bad_gateway(e.into()))?;
+    if !res.status().is_success() {
+        let status = res.status();
+        let body = res.text().await.unwrap_or_default();
+        return Err(bad_gateway(anyhow!("{}: {}", status, body)));
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// NUT-18 example payment request
+    const NUT18_REQUEST: &str = "creqApWF0gaNhdGVub3N0cmFheKlucHJvZmlsZTFxeTI4d3VtbjhnaGo3dW45ZDNzaGp0bnl2OWtoMnVld2Q5aHN6OW1od2RlbjV0ZTB3ZmprY2N0ZTljdXJ4dmVuOWVlaHFjdHJ2NWhzenJ0aHdkZW41dGUwZGVoaHh0bnZkYWtxcWd5ZGFxeTdjdXJrNDM5eWtwdGt5c3Y3dWRoZGh1NjhzdWNtMjk1YWtxZWZkZWhrZjBkNDk1Y3d1bmw1YWeBgmFuYjE3YWloYjdhOTAxNzZhYQphdWNzYXRhbYF4Imh0dHBzOi8vbm9mZWVzLnRlc3RudXQuY2FzaHUuc3BhY2U=";
+
+    #[test]
+    fn decodes_nut18_vector() {
+        let request = PaymentRequest::from_str(NUT18_REQUEST).unwrap();
+
+        assert_eq!(request.i.as_deref(), Some("b7a90176"));
+        assert_eq!(request.a, Some(10));
+        assert_eq!(request.u, Some(Unit::Sat));
+        assert_eq!(request.s, None);
+        assert_eq!(
+            request.m,
+            Some(vec!["https://nofees.testnut.cashu.space".to_string()])
+        );
+        assert_eq!(request.d, None);
+        assert_eq!(request.t.len(), 1);
+        assert_eq!(request.t[0].t, "nostr");
+        assert!(request.t[0].a.starts_with("nprofile1"));
+        assert_eq!(
+            request.t[0].g,
+            Some(vec![vec!["n".to_string(), "17".to_string()]])
+        );
+    }
+
+    #[test]
+    fn round_trips_created_requests() {
+        let request = PaymentRequest {
+            i: Some("c0ffee".to_string()),
+            a: Some(21),
+            u: Some(Unit::Msat),
+            s: Some(true),
+            m: Some(vec!["https://example.com/cashu/v1/0123abcd".to_string()]),
+            d: Some("Coffee".to_string()),
+            t: vec![Transport {
+                t: POST_TRANSPORT.to_string(),
+                a: format!("https://example.com{CASHU_V1_PATH}{RECEIVE_PATH}"),
+                g: None,
+            }],
+        };
+
+        let encoded = request.to_string();
+        assert!(encoded.starts_with(PAYMENT_REQUEST_PREFIX));
+        let decoded = PaymentRequest::from_str(&encoded).unwrap();
+        assert_eq!(decoded.i, request.i);
+        assert_eq!(decoded.a, request.a);
+        assert_eq!(decoded.u, request.u);
+        assert_eq!(decoded.s, request.s);
+        assert_eq!(decoded.m, request.m);
+        assert_eq!(decoded.d, request.d);
+        assert_eq!(decoded.t.len(), 1);
+        assert_eq!(decoded.t[0].t, POST_TRANSPORT);
+        assert_eq!(decoded.t[0].a, request.t[0].a);
+        assert_eq!(decoded.t[0].g, None);
+    }
+
+    #[test]
+    fn accepts_only_http_transport_targets() {
+        assert!(transport_url("https://example.com/cashu/v1/payment-request/receive").is_ok());
+        assert!(transport_url("http://127.0.0.1:3001/receive").is_ok());
+        assert!(transport_url("nprofile1qy28wumn8ghj7un9d3shjtnyv9kh2uewd9hsz9").is_err());
+        assert!(transport_url("file:///etc/passwd").is_err());
+        assert!(transport_url("").is_err());
+    }
+
+    #[test]
+    fn leaves_unset_fields_out() {
+        let encoded = PaymentRequest::default().to_string();
+        let cbor = decode_base64(&encoded[PAYMENT_REQUEST_PREFIX.len()..]).unwrap();
+
+        // An empty CBOR map
+        assert_eq!(cbor, [0xa0]);
+        assert!(PaymentRequest::from_str("creqB").is_err());
+    }
+}
@@ -0,0 +1,213 @@
+use std::str::FromStr;
+
+use anyhow::anyhow;
+use axum::extract::State;
+use axum::http::StatusCode;
+use axum::Json;
+use bitcoin::secp256k1::PublicKey;
+use fedimint_client::ClientArc;
+use fedimint_core::db::IDatabaseTransactionOpsCoreTyped;
+use fedimint_mint_client::OOBNotes;
+use serde::{Deserialize, Serialize};
+use tracing::info;
+
+use crate::db::{IssuedOutput, IssuedOutputKey};
+use crate::error::AppError;
+use crate::router::handlers::cashu::token::note_signature;
+use crate::router::handlers::cashu::{keyset_id, FederationScope, Unit};
+use crate::state::AppState;
+
+/// A NUT-00 blinded message
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct BlindedMessage {
+    pub amount: u64,
+    pub id: String,
+    #[serde(rename = "B_")]
+    pub b: String,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct RestoreRequest {
+    pub outputs: Vec<BlindedMessage>,
+}
+
+/// A NUT-00 blind signature. Fedimint does not sign the wallet's blinded
+/// messages, so `C_` is the signature of the note issued for the output. The
+/// note's spend key is never handed out, so a restored signature cannot be
+/// spent by whoever presents the public `B_`.
+#[derive(Debug, Serialize)]
+pub struct BlindSignature {
+    pub amount: u64,
+    pub id: String,
+    #[serde(rename = "C_")]
+    pub c: String,
+}
+
+#[derive(Debug, Serialize)]
+pub struct RestoreResponse {
+    pub outputs: Vec<BlindedMessage>,
+    pub signatures: Vec<BlindSignature>,
+}
+
+#[axum_macros::debug_handler]
+pub async fn handle_restore(
+    scope: FederationScope,
+    State(state): State<AppState>,
+    Json(req): Json<RestoreRequest>,
+) -> Result<Json<RestoreResponse>, AppError> {
+    if !state.cashu.restore {
+        return Err(AppError::new(
+            StatusCode::BAD_REQUEST,
+            anyhow!("Restore is not enabled on this mint"),
+        ));
+    }
+
+    let clients = scope.clients(&state).await?;
+    let mut outputs = Vec::new();
+    let mut signatures = Vec::new();
+    for output in req.outputs {
+        let b = parse_blinded_message(&output)?;
+        for client in &clients {
+            let issued = client
+                .db()
+                .begin_transaction_nc()
+                .await
+                .get_value(&IssuedOutputKey(b))
+                .await;
+            if let Some(issued) = issued {
+                signatures.push(BlindSignature {
+                    amount: issued.amount,
+                    id: issued.keyset_id,
+                    c: issued.signature,
+                });
+                outputs.push(output);
+                break;
+            }
+        }
+    }
+
+    Ok(Json(RestoreResponse {
+        outputs,
+        signatures,
+    }))
+}
+
+/// Records the signature of each issued note against the wallet output it was
+/// handed out for, if restore is enabled
+pub async fn record_outputs(
+    state: &AppState,
+    client: &ClientArc,
+    unit: Unit,
+    outputs: &[BlindedMessage],
+    notes: &OOBNotes,
+) -> Result<(), AppError> {
+    if !state.cashu.restore || outputs.is_empty() {
+        return Ok(());
+    }
+
+    let keyset_id = keyset_id(client, unit)?;
+    let outputs = outputs
+        .iter()
+        .map(|output| Ok((output.amount, parse_blinded_message(output)?)))
+        .collect::<Result<Vec<_>, AppError>>()?;
+    let notes = notes
+        .notes()
+        .iter_items()
+        .filter_map(|(amount, note)| Some((unit.value_of(amount)?, note)))
+        .collect::<Vec<_>>();
+    let assigned = assign_outputs(outputs, notes.iter().map(|(amount, _)| *amount));
+
+    let mut dbtx = client.db().begin_transaction().await;
+    for (b, index) in &assigned {
+        let (amount, note) = notes[*index];
+        let issued = IssuedOutput {
+            keyset_id: keyset_id.clone(),
+            amount,
+            signature: note_signature(note)?,
+        };
+        dbtx.insert_entry(&IssuedOutputKey(*b), &issued).await;
+    }
+    dbtx.commit_tx_result().await?;
+
+    info!("Recorded {} issued outputs for restore", assigned.len());
+    Ok(())
+}
+
+/// Pairs each issued note, given by its amount, with a wallet output:
+/// outputs of the same amount first and the remaining outputs in order
+/// otherwise, as blank NUT-08 outputs carry no meaningful amount. Returns the
+/// output's `B_` and the index of its note.
+fn assign_outputs(
+    mut unused: Vec<(u64, PublicKey)>,
+    amounts: impl Iterator<Item = u64>,
+) -> Vec<(PublicKey, usize)> {
+    let mut assigned = Vec::new();
+    for (index, amount) in amounts.enumerate() {
+        let matching = unused
+            .iter()
+            .position(|(output_amount, _)| *output_amount == amount);
+        let Some(position) = matching.or((!unused.is_empty()).then_some(0)) else {
+            break;
+        };
+        let (_, b) = unused.remove(position);
+        assigned.push((b, index));
+    }
+    assigned
+}
+
+fn parse_blinded_message(output: &BlindedMessage) -> Result<PublicKey, AppError> {
+    PublicKey::from_str(&output.b).map_err(|e| {
+        AppError::new(
+            StatusCode::BAD_REQUEST,
+            anyhow!("Invalid blinded message {}: {}", output.b, e),
+        )
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use bitcoin::secp256k1::{Secp256k1, SecretKey};
+
+    use super::*;
+
+    fn key(byte: u8) -> PublicKey {
+        PublicKey::from_secret_key(
+            &Secp256k1::new(),
+            &SecretKey::from_slice(&[byte; 32]).unwrap(),
+        )
+    }
+
+    #[test]
+    fn assigns_outputs_of_the_same_amount_first() {
+        let outputs = vec![(1, key(1)), (4, key(2)), (2, key(3))];
+
+        let assigned = assign_outputs(outputs, [2, 4].into_iter());
+
+        assert_eq!(assigned, vec![(key(3), 0), (key(2), 1)]);
+    }
+
+    #[test]
+    fn assigns_blank_outputs_in_order() {
+        let outputs = vec![(0, key(1)), (0, key(2))];
+
+        let assigned = assign_outputs(outputs, [8, 1, 2].into_iter());
+
+        assert_eq!(assigned, vec![(key(1), 0), (key(2), 1)]);
+    }
+
+    #[test]
+    fn restored_signatures_carry_no_secret() {
+        let signature = BlindSignature {
+            amount: 8,
+            id: "00456a94ab4e1c46".to_string(),
+            c: "02".to_string(),
+        };
+
+        let json = serde_json::to_value(signature).unwrap();
+
+        assert_eq!(
+            json,
+            serde_json::json!({"amount": 8, "id": "00456a94ab4e1c46", "C_": "02"})
+        );
+    }
+}
@@ -1,21 +1,27 @@
-use anyhow::anyhow;
 use axum::extract::State;
 use axum::http::StatusCode;
 use axum::Json;
+use fedimint_client::ClientArc;
 use fedimint_core::config::FederationId;
+use fedimint_core::core::OperationId;
 use fedimint_core::Amount;
 use fedimint_mint_client::{MintClientModule, OOBNotes};
-use futures_util::StreamExt;
 use serde::{Deserialize, Serialize};
-use tracing::info;
+use tracing::warn;
 
 use crate::error::AppError;
+use crate::notes::await_reissue;
+use crate::router::handlers::cashu::check::{is_redeemed, set_redemption, Redemption};
+use crate::router::handlers::cashu::error::CashuError;
+use crate::router::handlers::cashu::token::Ecash;
+use crate::router::handlers::cashu::FederationScope;
 use crate::state::AppState;
 
 #[derive(Debug, Deserialize)]
 pub struct SwapRequest {
-    pub notes: OOBNotes,
+    pub notes: Ecash,
     pub federation_id: Option<FederationId>,
+    pub keyset_id: Option<String>,
 }
 
 #[derive(Debug, Serialize)]
@@ -25,29 +31,79 @@ pub struct SwapResponse {
 
 #[axum_macros::debug_handler]
 pub async fn handle_swap(
+    scope: FederationScope,
     State(state): State<AppState>,
     Json(req): Json<SwapRequest>,
 ) -> Result<Json<SwapResponse>, AppError> {
-    let amount_msat = req.notes.total_amount();
-
-    let client = state.get_client(req.federation_id).await?;
-    let mint = client.get_first_module::<MintClientModule>();
-
-    let operation_id = mint.reissue_external_notes(req.notes, ()).await?;
-    let mut updates = mint
-        .subscribe_reissue_external_notes(operation_id)
-        .await
-        .unwrap()
-        .into_stream();
-
-    while let Some(update) = updates.next().await {
-        let update_clone = update.clone();
-        if let fedimint_mint_client::ReissueExternalNotesState::Failed(e) = update {
-            Err(AppError::new(StatusCode::INTERNAL_SERVER_ERROR, anyhow!(e)))?;
-        }
+    let notes = req.notes.to_oob_notes(&state).await?;
+    let amount_msat = notes.total_amount();
 
-        info!("Update: {update_clone:?}");
+    let client = match (&req.keyset_id, req.federation_id) {
+        (Some(keyset_id), _) => state.get_client_by_keyset_id(keyset_id).await?.0,
+        (None, Some(federation_id)) => scope.client(&state, Some(federation_id)).await?,
+        (None, None) => {
+            state
+                .get_client_by_prefix(&notes.federation_id_prefix())
+                .await?
+        }
+    };
+    if req.federation_id.is_none() || req.keyset_id.is_some() {
+        scope.ensure(&state, &client).await?;
     }
+    reissue_notes(&client, notes).await?;
 
     Ok(Json(SwapResponse { amount_msat }))
 }
+
+/// Reissues external notes into the client's wallet and waits for the reissue
+/// to complete. The notes are reported as pending by NUT-07 while the reissue
+/// is in flight and as spent afterwards, also if the server restarts in
+/// between.
+pub async fn reissue_notes(client: &ClientArc, notes: OOBNotes) -> Result<OperationId, AppError> {
+    let operation_id = submit_reissue(client, &notes).await?;
+    complete_reissue(client, operation_id, &notes).await?;
+    Ok(operation_id)
+}
+
+/// Like [`reissue_notes`], but returns as soon as the reissue is submitted.
+/// The proof states are updated once the reissue completes in the background.
+pub async fn reissue_notes_in_background(
+    client: &ClientArc,
+    notes: OOBNotes,
+) -> Result<OperationId, AppError> {
+    let operation_id = submit_reissue(client, &notes).await?;
+    let client = client.clone();
+    tokio::spawn(async move {
+        if let Err(e) = complete_reissue(&client, operation_id, &notes).await {
+            warn!("Reissue {operation_id} failed: {}", e.error);
+        }
+    });
+    Ok(operation_id)
+}
+
+async fn submit_reissue(client: &ClientArc, notes: &OOBNotes) -> Result<OperationId, AppError> {
+    if is_redeemed(client, notes).await? {
+        return Err(AppError::new(
+            StatusCode::BAD_REQUEST,
+            CashuError::TokenAlreadySpent,
+        ));
+    }
+
+    let operation_id = client
+        .get_first_module::<MintClientModule>()
+        .reissue_external_notes(notes.clone(), ())
+        .await?;
+    set_redemption(client, notes, Some(Redemption::Pending(operation_id))).await?;
+    Ok(operation_id)
+}
+
+async fn complete_reissue(
+    client: &ClientArc,
+    operation_id: OperationId,
+    notes: &OOBNotes,
+) -> Result<(), AppError> {
+    let res = await_reissue(client, operation_id).await;
+    let redemption = res.is_ok().then_some(Redemption::Spent);
+    set_redemption(client, notes, redemption).await?;
+    res
+}
@@ -0,0 +1,596 @@
+use std::fmt;
+use std::str::FromStr;
+
+use anyhow::{anyhow, bail, ensure, Context};
+use axum::http::StatusCode;
+use base64::engine::general_purpose::{URL_SAFE, URL_SAFE_NO_PAD};
+use base64::Engine;
+use bitcoin_hashes::hex::{FromHex, ToHex};
+use fedimint_client::ClientArc;
+use fedimint_core::encoding::{Decodable, Encodable};
+use fedimint_core::module::registry::ModuleDecoderRegistry;
+use fedimint_core::TieredMulti;
+use fedimint_mint_client::{OOBNotes, SpendableNote};
+use serde::{Deserialize, Deserializer, Serialize, Serializer};
+use serde_bytes::ByteBuf;
+
+use crate::error::AppError;
+use crate::router::handlers::cashu::{get_mint_config, keyset_id, Unit};
+use crate::state::AppState;
+
+const TOKEN_V3_PREFIX: &str = "cashuA";
+const TOKEN_V4_PREFIX: &str = "cashuB";
+
+/// Length of the BLS signature part of an encoded `SpendableNote`, the
+/// remaining bytes are the spend key.
+const NOTE_SIGNATURE_LEN: usize = 48;
+
+/// A NUT-00 proof. Fedimint notes map onto proofs by using the note's spend
+/// key as `secret` and the federation signature as `C`.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Proof {
+    pub amount: u64,
+    pub id: String,
+    pub secret: String,
+    #[serde(rename = "C")]
+    pub c: String,
+}
+
+/// A Cashu token independent of its serialization version
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct CashuToken {
+    pub mint: Option<String>,
+    pub unit: Option<Unit>,
+    pub memo: Option<String>,
+    pub proofs: Vec<Proof>,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+struct TokenV3 {
+    token: Vec<TokenV3Entry>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    unit: Option<Unit>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    memo: Option<String>,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+struct TokenV3Entry {
+    #[serde(default)]
+    mint: String,
+    proofs: Vec<Proof>,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+struct TokenV4 {
+    #[serde(rename = "m")]
+    mint: String,
+    #[serde(rename = "u")]
+    unit: Unit,
+    #[serde(rename = "d", default, skip_serializing_if = "Option::is_none")]
+    memo: Option<String>,
+    #[serde(rename = "t")]
+    token: Vec<TokenV4Entry>,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+struct TokenV4Entry {
+    #[serde(rename = "i")]
+    id: ByteBuf,
+    #[serde(rename = "p")]
+    proofs: Vec<ProofV4>,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+struct ProofV4 {
+    #[serde(rename = "a")]
+    amount: u64,
+    #[serde(rename = "s")]
+    secret: String,
+    #[serde(rename = "c")]
+    c: ByteBuf,
+}
+
+impl CashuToken {
+    /// Serializes the token in the V3 `cashuA` JSON format
+    pub fn to_v3_string(&self) -> String {
+        let token = TokenV3 {
+            token: vec![TokenV3Entry {
+                mint: self.mint.clone().unwrap_or_default(),
+                proofs: self.proofs.clone(),
+            }],
+            unit: self.unit,
+            memo: self.memo.clone(),
+        };
+        let json = serde_json::to_vec(&token).expect("Cashu token is serializable");
+        format!("{}{}", TOKEN_V3_PREFIX, URL_SAFE.encode(json))
+    }
+
+    /// Serializes the token in the V4 `cashuB` CBOR format
+    pub fn to_v4_string(&self) -> anyhow::Result<String> {
+        let mut entries: Vec<TokenV4Entry> = Vec::new();
+        for proof in &self.proofs {
+            let id = Vec::<u8>::from_hex(&proof.id).context("Invalid keyset id")?;
+            let c = Vec::<u8>::from_hex(&proof.c).context("Invalid proof signature")?;
+            let proof = ProofV4 {
+                amount: proof.amount,
+                secret: proof.secret.clone(),
+                c: ByteBuf::from(c),
+            };
+            match entries.iter_mut().find(|entry| entry.id.as_slice() == id) {
+                Some(entry) => entry.proofs.push(proof),
+                None => entries.push(TokenV4Entry {
+                    id: ByteBuf::from(id),
+                    proofs: vec![proof],
+                }),
+            }
+        }
+
+        let token = TokenV4 {
+            mint: self.mint.clone().unwrap_or_default(),
+            unit: self.unit.unwrap_or(Unit::Sat),
+            memo: self.memo.clone(),
+            token: entries,
+        };
+        let mut cbor = Vec::new();
+        ciborium::into_writer(&token, &mut cbor)?;
+        Ok(format!(
+            "{}{}",
+            TOKEN_V4_PREFIX,
+            URL_SAFE_NO_PAD.encode(cbor)
+        ))
+    }
+
+    /// Converts the proofs of the token into fedimint notes. All proofs must
+    /// belong to the same joined federation and their amounts must match one
+    /// of the federation's denominations.
+    pub async fn to_oob_notes(&self, state: &AppState) -> Result<OOBNotes, AppError> {
+        let mut client: Option<(ClientArc, Unit)> = None;
+        let mut notes = Vec::new();
+        for proof in &self.proofs {
+            let (proof_client, unit) = state.get_client_by_keyset_id(&proof.id).await?;
+            if let Some((client, _)) = &client {
+                if client.federation_id() != proof_client.federation_id() {
+                    return Err(unrepresentable(anyhow!(
+                        "Proofs from different federations cannot be combined"
+                    )));
+                }
+            }
+            if self.unit.is_some_and(|token_unit| token_unit != unit) {
+                return Err(unrepresentable(anyhow!(
+                    "Keyset {} is not denominated in the token unit",
+                    proof.id
+                )));
+            }
+
+            let amount = unit.to_amount(proof.amount);
+            if get_mint_config(&proof_client)?
+                .tbs_pks
+                .get(amount)
+                .is_none()
+            {
+                return Err(unrepresentable(anyhow!(
+                    "Proof amount {} {} is not a denomination of the federation",
+                    proof.amount,
+                    unit.as_str()
+                )));
+            }
+            notes.push((amount, decode_note(proof).map_err(unrepresentable)?));
+            client = Some((proof_client, unit));
+        }
+
+        let (client, _) =
+            client.ok_or_else(|| unrepresentable(anyhow!("Token does not contain any proofs")))?;
+        Ok(OOBNotes::new(
+            client.federation_id().to_prefix(),
+            notes.into_iter().collect::<TieredMulti<_>>(),
+        ))
+    }
+
+    /// Converts fedimint notes into a Cashu token denominated in `unit`
+    pub fn from_oob_notes(
+        client: &ClientArc,
+        notes: &OOBNotes,
+        unit: Unit,
+        mint: Option<String>,
+    ) -> Result<Self, AppError> {
+        let id = keyset_id(client, unit)?;
+        let proofs = notes
+            .notes()
+            .iter_items()
+            .map(|(amount, note)| {
+                let amount = unit.value_of(amount).ok_or_else(|| {
+                    unrepresentable(anyhow!(
+                        "Note of {} cannot be expressed in {}",
+                        amount,
+                        unit.as_str()
+                    ))
+                })?;
+                encode_note(&id, amount, note)
+            })
+            .collect::<Result<Vec<_>, _>>()?;
+
+        Ok(Self {
+            mint,
+            unit: Some(unit),
+            memo: None,
+            proofs,
+        })
+    }
+}
+
+impl FromStr for CashuToken {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let s = s.trim();
+        if let Some(token) = s.strip_prefix(TOKEN_V3_PREFIX) {
+            let json = decode_base64(token)?;
+            let token: TokenV3 = serde_json::from_slice(&json)?;
+            let mut mints = token.token.iter().map(|entry| &entry.mint);
+            let mint = mints.next().cloned();
+            ensure!(
+                mints.all(|other| Some(other) == mint.as_ref()),
+                "Tokens from multiple mints are not supported"
+            );
+            Ok(Self {
+                mint: mint.filter(|mint| !mint.is_empty()),
+                unit: token.unit,
+                memo: token.memo,
+                proofs: token
+                    .token
+                    .into_iter()
+                    .flat_map(|entry| entry.proofs)
+                    .collect(),
+            })
+        } else if let Some(token) = s.strip_prefix(TOKEN_V4_PREFIX) {
+            let cbor = decode_base64(token)?;
+            let token: TokenV4 = ciborium::from_reader(cbor.as_slice())?;
+            let proofs = token
+                .token
+                .into_iter()
+                .flat_map(|entry| {
+                    let id = entry.id.to_hex();
+                    entry.proofs.into_iter().map(move |proof| Proof {
+                        amount: proof.amount,
+                        id: id.clone(),
+                        secret: proof.secret,
+                        c: proof.c.to_hex(),
+                    })
+                })
+                .collect();
+            Ok(Self {
+                mint: Some(token.mint).filter(|mint| !mint.is_empty()),
+                unit: Some(token.unit),
+                memo: token.memo,
+                proofs,
+            })
+        } else {
+            bail!("Not a Cashu token, expected a cashuA or cashuB prefix")
+        }
+    }
+}
+
+impl fmt::Display for CashuToken {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(&self.to_v3_string())
+    }
+}
+
+/// E-cash accepted by the Cashu and mint endpoints: either fedimint
+/// `OOBNotes`, a `cashuA`/`cashuB` token string or a NUT-00 array of proofs
+#[derive(Debug, Clone)]
+pub enum Ecash {
+    Fedimint(OOBNotes),
+    Cashu(CashuToken),
+}
+
+impl Ecash {
+    /// Resolves the e-cash into fedimint notes of a joined federation
+    pub async fn to_oob_notes(&self, state: &AppState) -> Result<OOBNotes, AppError> {
+        match self {
+            Ecash::Fedimint(notes) => Ok(notes.clone()),
+            Ecash::Cashu(token) => token.to_oob_notes(state).await,
+        }
+    }
+}
+
+impl FromStr for Ecash {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let s = s.trim();
+        if s.starts_with(TOKEN_V3_PREFIX) || s.starts_with(TOKEN_V4_PREFIX) {
+            Ok(Ecash::Cashu(CashuToken::from_str(s)?))
+        } else {
+            Ok(Ecash::Fedimint(OOBNotes::from_str(s)?))
+        }
+    }
+}
+
+impl fmt::Display for Ecash {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Ecash::Fedimint(notes) => notes.fmt(f),
+            Ecash::Cashu(token) => token.fmt(f),
+        }
+    }
+}
+
+impl Serialize for Ecash {
+    fn serialize<S>(&self, serializer: S) -> Result<S::Ok, S::Error>
+    where
+        S: Serializer,
+    {
+        serializer.serialize_str(&self.to_string())
+    }
+}
+
+impl<'de> Deserialize<'de> for Ecash {
+    fn deserialize<D>(deserializer: D) -> Result<Self, D::Error>
+    where
+        D: Deserializer<'de>,
+    {
+        #[derive(Deserialize)]
+        #[serde(untagged)]
+        enum EcashRepr {
+            Encoded(String),
+            Proofs(Vec<Proof>),
+        }
+
+        match EcashRepr::deserialize(deserializer)? {
+            EcashRepr::Encoded(s) => Ecash::from_str(&s).map_err(serde::de::Error::custom),
+            EcashRepr::Proofs(proofs) => Ok(Ecash::Cashu(CashuToken {
+                mint: None,
+                unit: None,
+                memo: None,
+                proofs,
+            })),
+        }
+    }
+}
+
+/// Encoding used when handing e-cash out to a wallet
+#[derive(Debug, Clone, Copy, Default, Deserialize)]
+pub enum EcashFormat {
+    #[serde(rename = "fedimint")]
+    Fedimint,
+    #[default]
+    #[serde(rename = "cashuA")]
+    CashuV3,
+    #[serde(rename = "cashuB")]
+    CashuV4,
+}
+
+impl EcashFormat {
+    /// Encodes `notes` of `client`'s federation, `unit` and `mint_url` are only
+    /// used by the Cashu formats
+    pub fn encode(
+        self,
+        client: &ClientArc,
+        notes: &OOBNotes,
+        unit: Unit,
+        mint_url: Option<String>,
+    ) -> Result<String, AppError> {
+        let token = match self {
+            EcashFormat::Fedimint => return Ok(notes.to_string()),
+            EcashFormat::CashuV3 | EcashFormat::CashuV4 => {
+                CashuToken::from_oob_notes(client, notes, unit, mint_url)?
+            }
+        };
+        match self {
+            EcashFormat::CashuV4 => Ok(token.to_v4_string()?),
+            _ => Ok(token.to_v3_string()),
+        }
+    }
+}
+
+pub fn decode_base64(s: &str) -> anyhow::Result<Vec<u8>> {
+    // Wallets are inconsistent about padding and the alphabet, accept all of them
+    let s = s.trim_end_matches('=').replace('+', "-").replace('/', "_");
+    Ok(URL_SAFE_NO_PAD.decode(s)?)
+}
+
+fn decode_note(proof: &Proof) -> anyhow::Result<SpendableNote> {
+    let mut bytes = Vec::<u8>::from_hex(&proof.c).context("Proof C is not hex encoded")?;
+    ensure!(
+        bytes.len() == NOTE_SIGNATURE_LEN,
+        "Proof C is not a fedimint note signature"
+    );
+    bytes.extend(Vec::<u8>::from_hex(&proof.secret).context("Proof secret is not hex encoded")?);
+    SpendableNote::consensus_decode(
+        &mut std::io::Cursor::new(bytes),
+        &ModuleDecoderRegistry::default(),
+    )
+    .context("Proof is not a fedimint note")
+}
+
+fn encode_note(keyset_id: &str, amount: u64, note: &SpendableNote) -> Result<Proof, AppError> {
+    let (c, secret) = split_note(note)?;
+    Ok(Proof {
+        amount,
+        id: keyset_id.to_string(),
+        secret,
+        c,
+    })
+}
+
+/// Returns the secret a note is represented with in a Cashu proof: the hex
+/// encoded spend key
+pub fn note_secret(note: &SpendableNote) -> Result<String, AppError> {
+    Ok(split_note(note)?.1)
+}
+
+/// Returns the signature a note is represented with in a Cashu proof: the hex
+/// encoded `C`
+pub fn note_signature(note: &SpendableNote) -> Result<String, AppError> {
+    Ok(split_note(note)?.0)
+}
+
+/// Splits a note into its hex encoded signature and spend key
+fn split_note(note: &SpendableNote) -> Result<(String, String), AppError> {
+    let bytes = note
+        .consensus_encode_to_vec()
+        .map_err(|e| AppError::new(StatusCode::INTERNAL_SERVER_ERROR, e))?;
+    let (signature, secret) = bytes.split_at(NOTE_SIGNATURE_LEN);
+    Ok((signature.to_hex(), secret.to_hex()))
+}
+
+fn unrepresentable(e: anyhow::Error) -> AppError {
+    AppError::new(
+        StatusCode::BAD_REQUEST,
+        anyhow!("Proofs cannot be represented as fedimint notes: {e}"),
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// NUT-00 example V3 token
+    const V3_TOKEN: &str = "cashuAeyJ0b2tlbiI6W3sibWludCI6Imh0dHBzOi8vODMzMy5zcGFjZTozMzM4IiwicHJvb2ZzIjpbeyJhbW91bnQiOjIsImlkIjoiMDA5YTFmMjkzMjUzZTQxZSIsInNlY3JldCI6IjQwNzkxNWJjMjEyYmU2MWE3N2UzZTZkMmFlYjRjNzI3OTgwYmRhNTFjZDA2YTZhZmMyOWUyODYxNzY4YTc4MzciLCJDIjoiMDJiYzkwOTc5OTdkODFhZmIyY2M3MzQ2YjVlNDM0NWE5MzQ2YmQyYTUwNmViNzk1ODU5OGE3MmYwY2Y4NTE2M2VhIn0seyJhbW91bnQiOjgsImlkIjoiMDA5YTFmMjkzMjUzZTQxZSIsInNlY3JldCI6ImZlMTUxMDkzMTRlNjFkNzc1NmIwZjhlZTBmMjNhNjI0YWNhYTNmNGUwNDJmNjE0MzNjNzI4YzcwNTdiOTMxYmUiLCJDIjoiMDI5ZThlNTA1MGI4OTBhN2Q2YzA5NjhkYjE2YmMxZDVkNWZhMDQwZWExZGUyODRmNmVjNjlkNjEyOTlmNjcxMDU5In1dfV0sInVuaXQiOiJzYXQiLCJtZW1vIjoiVGhhbmsgeW91LiJ9";
+
+    /// NUT-00 example V4 token
+    const V4_TOKEN: &str = "cashuBpGF0gaJhaUgArSaMTR9YJmFwgaNhYQFhc3hAOWE2ZGJiODQ3YmQyMzJiYTc2ZGIwZGYxOTcyMTZiMjlkM2I4Y2MxNDU1M2NkMjc4MjdmYzFjYzk0MmZlZGI0ZWFjWCEDhhhUP_trhpXfStS6vN6So0qWvc2X3O4NfM-Y1HISZ5JhZGlUaGFuayB5b3VhbXVodHRwOi8vbG9jYWxob3N0OjMzMzhhdWNzYXQ=";
+
+    fn v3_proofs() -> Vec<Proof> {
+        vec![
+            Proof {
+                amount: 2,
+                id: "009a1f293253e41e".to_string(),
+                secret: "407915bc212be61a77e3e6d2aeb4c727980bda51cd06a6afc29e2861768a7837"
+                    .to_string(),
+                c: "02bc9097997d81afb2cc7346b5e4345a9346bd2a506eb7958598a72f0cf85163ea".to_string(),
+            },
+            Proof {
+                amount: 8,
+                id: "009a1f293253e41e".to_string(),
+                secret: "fe15109314e61d7756b0f8ee0f23a624acaa3f4e042f61433c728c7057b931be"
+                    .to_string(),
+                c: "029e8e5050b890a7d6c0968db16bc1d5d5fa040ea1de284f6ec69d61299f671059".to_string(),
+            },
+        ]
+    }
+
+    #[test]
+    fn decodes_v3_vector() {
+        let token = CashuToken::from_str(V3_TOKEN).unwrap();
+
+        assert_eq!(
+            token,
+            CashuToken {
+                mint: Some("https://8333.space:3338".to_string()),
+                unit: Some(Unit::Sat),
+                memo: Some("Thank you.".to_string()),
+                proofs: v3_proofs(),
+            }
+        );
+    }
+
+    #[test]
+    fn decodes_v4_vector() {
+        let token = CashuToken::from_str(V4_TOKEN).unwrap();
+
+        assert_eq!(
+            token,
+            CashuToken {
+                mint: Some("http://localhost:3338".to_string()),
+                unit: Some(Unit::Sat),
+                memo: Some("Thank you".to_string()),
+                proofs: vec![Proof {
+                    amount: 1,
+                    id: "00ad268c4d1f5826".to_string(),
+                    secret: "9a6dbb847bd232ba76db0df197216b29d3b8cc14553cd27827fc1cc942fedb4e"
+                        .to_string(),
+                    c: "038618543ffb6b8695df4ad4babcde92a34a96bdcd97dcee0d7ccf98d472126792"
+                        .to_string(),
+                }],
+            }
+        );
+    }
+
+    #[test]
+    fn round_trips_v3_and_v4() {
+        let token = CashuToken::from_str(V3_TOKEN).unwrap();
+
+        let v3 = token.to_v3_string();
+        assert!(v3.starts_with(TOKEN_V3_PREFIX));
+        assert_eq!(CashuToken::from_str(&v3).unwrap(), token);
+
+        let v4 = token.to_v4_string().unwrap();
+        assert!(v4.starts_with(TOKEN_V4_PREFIX));
+        assert_eq!(CashuToken::from_str(&v4).unwrap(), token);
+    }
+
+    #[test]
+    fn groups_v4_proofs_by_keyset() {
+        let mut proofs = v3_proofs();
+        proofs[1].id = "00ad268c4d1f5826".to_string();
+        proofs.push(Proof {
+            amount: 4,
+            ..v3_proofs()[0].clone()
+        });
+        let token = CashuToken {
+            mint: None,
+            unit: Some(Unit::Sat),
+            memo: None,
+            proofs,
+        };
+
+        let cbor = decode_base64(&token.to_v4_string().unwrap()[TOKEN_V4_PREFIX.len()..]).unwrap();
+        let v4: TokenV4 = ciborium::from_reader(cbor.as_slice()).unwrap();
+        assert_eq!(v4.token.len(), 2);
+        assert_eq!(v4.token[0].proofs.len(), 2);
+        assert_eq!(v4.token[1].proofs.len(), 1);
+    }
+
+    #[test]
+    fn decodes_base64_of_any_alphabet_and_padding() {
+        let bytes = [0xfbu8, 0xff, 0xbf];
+        assert_eq!(decode_base64("-_-_").unwrap(), bytes);
+        assert_eq!(decode_base64("+/+/").unwrap(), bytes);
+        assert_eq!(decode_base64("AA==").unwrap(), [0]);
+        assert_eq!(decode_base64("AA").unwrap(), [0]);
+    }
+
+    #[test]
+    fn rejects_tokens_of_multiple_mints() {
+        let token = TokenV3 {
+            token: vec![
+                TokenV3Entry {
+                    mint: "https://a.example".to_string(),
+                    proofs: v3_proofs(),
+                },
+                TokenV3Entry {
+                    mint: "https://b.example".to_string(),
+                    proofs: v3_proofs(),
+                },
+            ],
+            unit: None,
+            memo: None,
+        };
+        let encoded = format!(
+            "{}{}",
+            TOKEN_V3_PREFIX,
+            URL_SAFE.encode(serde_json::to_vec(&token).unwrap())
+        );
+
+        assert!(CashuToken::from_str(&encoded).is_err());
+        assert!(CashuToken::from_str("cashuCabc").is_err());
+    }
+
+    #[test]
+    fn deserializes_ecash_from_proof_arrays_and_tokens() {
+        let proofs = serde_json::to_value(v3_proofs()).unwrap();
+        match serde_json::from_value::<Ecash>(proofs).unwrap() {
+            Ecash::Cashu(token) => assert_eq!(token.proofs, v3_proofs()),
+            Ecash::Fedimint(_) => panic!("proofs parsed as fedimint notes"),
+        }
+
+        let token = serde_json::Value::String(V4_TOKEN.to_string());
+        assert!(matches!(
+            serde_json::from_value::<Ecash>(token).unwrap(),
+            Ecash::Cashu(_)
+        ));
+    }
+}
@@ -0,0 +1,527 @@
+use std::collections::HashMap;
+use std::future;
+use std::str::FromStr;
+use std::time::Duration;
+
+use anyhow::anyhow;
+use axum::extract::ws::{Message, WebSocket, WebSocketUpgrade};
+use axum::extract::State;
+use axum::http::StatusCode;
+use axum::response::IntoResponse;
+use bitcoin::secp256k1::PublicKey;
+use fedimint_client::ClientArc;
+use fedimint_core::core::OperationId;
+use fedimint_core::db::IDatabaseTransactionOpsCoreTyped;
+use futures_util::future::{join_all, select_all};
+use futures_util::{SinkExt, StreamExt};
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use tokio::sync::mpsc;
+use tokio::task::JoinHandle;
+use tokio::time::sleep;
+use tracing::{info, warn};
+
+use crate::db::{
+    MeltQuote, MeltQuoteKey, MeltStatusKey, MintQuote, MintQuoteIssuedKey, MintQuoteStateKey,
+    ProofStateKey,
+};
+use crate::error::AppError;
+use crate::router::handlers::cashu::check::{proof_states, ProofState};
+use crate::router::handlers::cashu::error::{error_code, GENERIC_ERROR_CODE};
+use crate::router::handlers::cashu::melt::quote::{
+    get_melt_quote, peek_melt_response, MeltQuoteState,
+};
+use crate::router::handlers::cashu::mint::quote::{
+    get_mint_quote, mint_quote_state, parse_quote_id, MintQuoteState, PostMintQuoteMethodResponse,
+};
+use crate::router::handlers::cashu::{FederationScope, Method};
+use crate::state::AppState;
+
+const JSONRPC_VERSION: &str = "2.0";
+const JSONRPC_ERROR_INVALID_REQUEST: i64 = -32600;
+const JSONRPC_ERROR_INVALID_PARAMS: i64 = -32602;
+
+/// How often proof state subscriptions ask the federation about spends made
+/// outside this server, which it sends no notifications for
+const PROOF_STATE_POLL_INTERVAL: Duration = Duration::from_secs(10);
+
+/// Commands supported by the NUT-17 endpoint, advertised in the NUT-06 info
+pub const SUPPORTED_KINDS: [SubscriptionKind; 3] = [
+    SubscriptionKind::Bolt11MintQuote,
+    SubscriptionKind::Bolt11MeltQuote,
+    SubscriptionKind::ProofState,
+];
+
+#[derive(Debug, Clone, Copy, Deserialize, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum SubscriptionKind {
+    Bolt11MintQuote,
+    Bolt11MeltQuote,
+    ProofState,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum WsMethod {
+    Subscribe,
+    Unsubscribe,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct WsRequest {
+    pub jsonrpc: String,
+    pub method: WsMethod,
+    pub params: Value,
+    pub id: u64,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct SubscribeParams {
+    pub kind: SubscriptionKind,
+    pub sub_id: String,
+    /// Quote ids for quote subscriptions, `Y` values for proof state ones
+    pub filters: Vec<String>,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct UnsubscribeParams {
+    pub sub_id: String,
+}
+
+#[derive(Debug, Serialize)]
+pub struct WsResponse {
+    pub jsonrpc: &'static str,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub result: Option<WsResult>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub error: Option<WsError>,
+    pub id: u64,
+}
+
+#[derive(Debug, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct WsResult {
+    pub status: &'static str,
+    pub sub_id: String,
+}
+
+#[derive(Debug, Serialize)]
+pub struct WsError {
+    pub code: i64,
+    pub message: String,
+}
+
+#[derive(Debug, Serialize)]
+pub struct WsNotification {
+    pub jsonrpc: &'static str,
+    pub method: &'static str,
+    pub params: NotificationParams,
+}
+
+#[derive(Debug, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct NotificationParams {
+    pub sub_id: String,
+    pub payload: Value,
+}
+
+/// What a subscription watches, resolved when subscribing so that unknown
+/// quotes and malformed filters are rejected right away
+enum Watch {
+    MintQuote(ClientArc, OperationId, MintQuote),
+    MeltQuote(ClientArc, OperationId, MeltQuote),
+    ProofState(Vec<ClientArc>, PublicKey),
+}
+
+/// Running subscriptions of a connection, aborted when the connection closes
+#[derive(Default)]
+struct Subscriptions(HashMap<String, JoinHandle<()>>);
+
+impl Drop for Subscriptions {
+    fn drop(&mut self) {
+        for task in self.0.values() {
+            task.abort();
+        }
+    }
+}
+
+#[axum_macros::debug_handler]
+pub async fn handle_ws(
+    ws: WebSocketUpgrade,
+    scope: FederationScope,
+    State(state): State<AppState>,
+) -> impl IntoResponse {
+    ws.on_upgrade(move |socket| handle_socket(socket, scope, state))
+}
+
+async fn handle_socket(socket: WebSocket, scope: FederationScope, state: AppState) {
+    let (mut sink, mut stream) = socket.split();
+    let (notifications, mut outgoing) = mpsc::unbounded_channel::<Message>();
+    let mut subscriptions = Subscriptions::default();
+
+    loop {
+        let msg = tokio::select! {
+            incoming = stream.next() => match incoming {
+                Some(Ok(Message::Text(text))) => {
+                    let res =
+                        handle_request(&text, scope, &state, &notifications, &mut subscriptions)
+                            .await;
+                    Message::Text(serde_json::to_string(&res).expect("serializable response"))
+                }
+                Some(Ok(Message::Close(_)) | Err(_)) | None => break,
+                Some(Ok(_)) => continue,
+            },
+            Some(notification) = outgoing.recv() => notification,
+        };
+        if sink.send(msg).await.is_err() {
+            break;
+        }
+    }
+
+    info!(
+        "Cashu websocket closed, dropping {} subscriptions",
+        subscriptions.0.len()
+    );
+}
+
+async fn handle_request(
+    text: &str,
+    scope: FederationScope,
+    state: &AppState,
+    notifications: &mpsc::UnboundedSender<Message>,
+    subscriptions: &mut Subscriptions,
+) -> WsResponse {
+    let req = match serde_json::from_str::<WsRequest>(text) {
+        Ok(req) if req.jsonrpc == JSONRPC_VERSION => req,
+        Ok(req) => {
+            return error_response(
+                req.id,
+                JSONRPC_ERROR_INVALID_REQUEST,
+                format!("Unsupported JSON-RPC version {}", req.jsonrpc),
+            )
+        }
+        Err(e) => {
+            let id = serde_json::from_str::<Value>(text)
+                .ok()
+                .and_then(|v| v.get("id").and_then(Value::as_u64));
+            return error_response(
+                id.unwrap_or(0),
+                JSONRPC_ERROR_INVALID_REQUEST,
+                e.to_string(),
+            );
+        }
+    };
+
+    let res = match req.method {
+        WsMethod::Subscribe => {
+            subscribe(req.params, scope, state, notifications, subscriptions).await
+        }
+        WsMethod::Unsubscribe => unsubscribe(req.params, subscriptions),
+    };
+    match res {
+        Ok(sub_id) => WsResponse {
+            jsonrpc: JSONRPC_VERSION,
+            result: Some(WsResult {
+                status: "OK",
+                sub_id,
+            }),
+            error: None,
+            id: req.id,
+        },
+        Err(e) => {
+            let code = match error_code(&e.error) {
+                GENERIC_ERROR_CODE => JSONRPC_ERROR_INVALID_PARAMS,
+                code => code.into(),
+            };
+            error_response(req.id, code, e.error.to_string())
+        }
+    }
+}
+
+fn error_response(id: u64, code: i64, message: String) -> WsResponse {
+    WsResponse {
+        jsonrpc: JSONRPC_VERSION,
+        result: None,
+        error: Some(WsError { code, message }),
+        id,
+    }
+}
+
+async fn subscribe(
+    params: Value,
+    scope: FederationScope,
+    state: &AppState,
+    notifications: &mpsc::UnboundedSender<Message>,
+    subscriptions: &mut Subscriptions,
+) -> Result<String, AppError> {
+    let params: SubscribeParams = serde_json::from_value(params)
+        .map_err(|e| AppError::new(StatusCode::BAD_REQUEST, anyhow!("Invalid params: {e}")))?;
+    if subscriptions
+        .0
+        .get(&params.sub_id)
+        .is_some_and(|task| !task.is_finished())
+    {
+        return Err(AppError::new(
+            StatusCode::BAD_REQUEST,
+            anyhow!("Subscription {} already exists", params.sub_id),
+        ));
+    }
+
+    let mut watches = Vec::with_capacity(params.filters.len());
+    for filter in &params.filters {
+        watches.push(resolve_watch(params.kind, filter, scope, state).await?);
+    }
+
+    let sub_id = params.sub_id.clone();
+    let notifications = notifications.clone();
+    let task = tokio::spawn(async move {
+        join_all(
+            watches
+                .into_iter()
+                .map(|watch| run_watch(watch, &params.sub_id, &notifications)),
+        )
+        .await;
+    });
+    subscriptions.0.insert(sub_id.clone(), task);
+
+    Ok(sub_id)
+}
+
+fn unsubscribe(params: Value, subscriptions: &mut Subscriptions) -> Result<String, AppError> {
+    let params: UnsubscribeParams = serde_json::from_value(params)
+        .map_err(|e| AppError::new(StatusCode::BAD_REQUEST, anyhow!("Invalid params: {e}")))?;
+    match subscriptions.0.remove(&params.sub_id) {
+        Some(task) => {
+            task.abort();
+            Ok(params.sub_id)
+        }
+        None => Err(AppError::new(
+            StatusCode::NOT_FOUND,
+            anyhow!("Subscription {} not found", params.sub_id),
+        )),
+    }
+}
+
+async fn resolve_watch(
+    kind: SubscriptionKind,
+    filter: &str,
+    scope: FederationScope,
+    state: &AppState,
+) -> Result<Watch, AppError> {
+    match kind {
+        SubscriptionKind::Bolt11MintQuote => {
+            let quote_id = parse_quote_id(filter)?;
+            let (client, quote) = get_mint_quote(state, scope, quote_id).await?;
+            if quote.method != Method::Bolt11 {
+                return Err(AppError::new(
+                    StatusCode::BAD_REQUEST,
+                    anyhow!("Quote {} is not a bolt11 quote", quote_id),
+                ));
+            }
+            Ok(Watch::MintQuote(client, quote_id, quote))
+        }
+        SubscriptionKind::Bolt11MeltQuote => {
+            let quote_id = parse_quote_id(filter)?;
+            let (client, quote) = get_melt_quote(state, scope, quote_id, Method::Bolt11).await?;
+            Ok(Watch::MeltQuote(client, quote_id, quote))
+        }
+        SubscriptionKind::ProofState => {
+            let y = PublicKey::from_str(filter).map_err(|e| {
+                AppError::new(
+                    StatusCode::BAD_REQUEST,
+                    anyhow!("Invalid Y {}: {}", filter, e),
+                )
+            })?;
+            Ok(Watch::ProofState(scope.clients(state).await?, y))
+        }
+    }
+}
+
+async fn run_watch(watch: Watch, sub_id: &str, notifications: &mpsc::UnboundedSender<Message>) {
+    let notify = |payload: Value| {
+        let notification = WsNotification {
+            jsonrpc: JSONRPC_VERSION,
+            method: "subscribe",
+            params: NotificationParams {
+                sub_id: sub_id.to_string(),
+                payload,
+            },
+        };
+        let msg = serde_json::to_string(&notification).expect("serializable notification");
+        notifications.send(Message::Text(msg)).is_ok()
+    };
+
+    let res = match watch {
+        Watch::MintQuote(client, quote_id, quote) => {
+            watch_mint_quote(&client, quote_id, &quote, notify).await
+        }
+        Watch::MeltQuote(client, quote_id, quote) => {
+            watch_melt_quote(&client, quote_id, quote, notify).await
+        }
+        Watch::ProofState(clients, y) => watch_proof_state(&clients, y, notify).await,
+    };
+    if let Err(e) = res {
+        warn!("Cashu subscription {sub_id} stopped: {}", e.error);
+    }
+}
+
+/// Pushes the quote's state whenever the follower of its lightning receive
+/// records progress, until notes have been issued for it
+async fn watch_mint_quote(
+    client: &ClientArc,
+    quote_id: OperationId,
+    quote: &MintQuote,
+    notify: impl Fn(Value) -> bool,
+) -> Result<(), AppError> {
+    let mut last = None;
+    loop {
+        // Read before reporting the state so no update is missed in between
+        let recorded = client
+            .db()
+            .begin_transaction_nc()
+            .await
+            .get_value(&MintQuoteStateKey(quote_id))
+            .await;
+        let quote_state = mint_quote_state(client, quote_id, quote).await?;
+        let payload = serde_json::to_value(PostMintQuoteMethodResponse::new(
+            quote_id,
+            quote,
+            quote_state,
+        ))?;
+        if last.as_ref() != Some(&payload) {
+            if !notify(payload.clone()) {
+                return Ok(());
+            }
+            last = Some(payload);
+        }
+        if quote_state == MintQuoteState::Issued {
+            return Ok(());
+        }
+
+        let state_key = MintQuoteStateKey(quote_id);
+        let issued_key = MintQuoteIssuedKey(quote_id);
+        tokio::select! {
+            _ = client.db().wait_key_check(&state_key, |value| (value != recorded).then_some(())) => {}
+            _ = client.db().wait_key_exists(&issued_key) => {}
+        }
+    }
+}
+
+/// Pushes the quote's state whenever the follower of its payment records
+/// progress or the payment is attempted again, until it has been paid. Only
+/// change that was already issued is reported, wallets get it from the melt
+/// endpoints.
+async fn watch_melt_quote(
+    client: &ClientArc,
+    quote_id: OperationId,
+    mut quote: MeltQuote,
+    notify: impl Fn(Value) -> bool,
+) -> Result<(), AppError> {
+    let mut last = None;
+    loop {
+        let status_key = quote
+            .payment
+            .map(|payment| MeltStatusKey(payment.operation_id()));
+        let recorded = match &status_key {
+            Some(key) => {
+                client
+                    .db()
+                    .begin_transaction_nc()
+                    .await
+                    .get_value(key)
+                    .await
+            }
+            None => None,
+        };
+        let response = peek_melt_response(client, quote_id, &quote).await?;
+        let paid = response.state == MeltQuoteState::Paid;
+        let payload = serde_json::to_value(response)?;
+        if last.as_ref() != Some(&payload) {
+            if !notify(payload.clone()) {
+                return Ok(());
+            }
+            last = Some(payload);
+        }
+        if paid {
+            return Ok(());
+        }
+
+        let status_changed = async {
+            match &status_key {
+                Some(key) => {
+                    client
+                        .db()
+                        .wait_key_check(key, |value| (value != recorded).then_some(()))
+                        .await;
+                }
+                None => future::pending().await,
+            }
+        };
+        let quote_key = MeltQuoteKey(quote_id);
+        let (payment, executing) = (quote.payment, quote.executing);
+        tokio::select! {
+            _ = status_changed => {}
+            (changed, _) = client.db().wait_key_check(&quote_key, |q| {
+                q.filter(|q| q.payment != payment || q.executing != executing)
+            }) => quote = changed,
+        }
+    }
+}
+
+/// Pushes the state of a proof whenever it changes, until it has been spent.
+/// Reissues through this server are picked up as they are recorded, spends
+/// elsewhere are only known to the federation and are polled for.
+async fn watch_proof_state(
+    clients: &[ClientArc],
+    y: PublicKey,
+    notify: impl Fn(Value) -> bool,
+) -> Result<(), AppError> {
+    let mut last = None;
+    loop {
+        let mut recorded = Vec::with_capacity(clients.len());
+        for client in clients {
+            recorded.push(
+                client
+                    .db()
+                    .begin_transaction_nc()
+                    .await
+                    .get_value(&ProofStateKey(y))
+                    .await,
+            );
+        }
+
+        let response = proof_states(clients, &[y])
+            .await?
+            .pop()
+            .expect("one state per Y");
+        let proof_state = response.state;
+        let payload = serde_json::to_value(response)?;
+        if last.as_ref() != Some(&payload) {
+            if !notify(payload.clone()) {
+                return Ok(());
+            }
+            last = Some(payload);
+        }
+        if proof_state == ProofState::Spent || clients.is_empty() {
+            return Ok(());
+        }
+
+        let recorded_changed =
+            select_all(clients.iter().zip(recorded).map(|(client, recorded)| {
+                Box::pin(async move {
+                    client
+                        .db()
+                        .wait_key_check(&ProofStateKey(y), |value| {
+                            (value != recorded).then_some(())
+                        })
+                        .await
+                })
+            }));
+        tokio::select! {
+            _ = recorded_changed => {}
+            _ = sleep(PROOF_STATE_POLL_INTERVAL) => {}
+        }
+    }
+}
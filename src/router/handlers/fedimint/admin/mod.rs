@@ -9,14 +9,15 @@ pub mod module;
 pub mod restore;
 
 use fedimint_client::ClientArc;
+use fedimint_core::TieredSummary;
 use fedimint_mint_client::MintClientModule;
 use fedimint_wallet_client::WalletClientModule;
 use info::InfoResponse;
 
-pub async fn get_note_summary(client: &ClientArc) -> anyhow::Result<InfoResponse> {
-    let mint_client = client.get_first_module::<MintClientModule>();
-    let wallet_client = client.get_first_module::<WalletClientModule>();
-    let summary = mint_client
+/// Returns the number of notes of each denomination held by the wallet
+pub async fn get_wallet_summary(client: &ClientArc) -> TieredSummary {
+    client
+        .get_first_module::<MintClientModule>()
         .get_wallet_summary(
             &mut client
                 .db()
@@ -24,7 +25,12 @@ pub async fn get_note_summary(client: &ClientArc) -> anyhow::Result<InfoResponse
                 .await
                 .to_ref_with_prefix_module_id(1),
         )
-        .await;
+        .await
+}
+
+pub async fn get_note_summary(client: &ClientArc) -> anyhow::Result<InfoResponse> {
+    let wallet_client = client.get_first_module::<WalletClientModule>();
+    let summary = get_wallet_summary(client).await;
     Ok(InfoResponse {
         network: wallet_client.get_network().to_string(),
         meta: client.get_config().global.meta.clone(),
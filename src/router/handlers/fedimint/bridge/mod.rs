@@ -0,0 +1,235 @@
+use anyhow::anyhow;
+use axum::http::StatusCode;
+use serde::de::DeserializeOwned;
+use serde::{Deserialize, Serialize};
+use url::Url;
+
+use crate::error::AppError;
+use crate::router::handlers::cashu::error::ErrorResponse;
+use crate::router::handlers::cashu::token::Proof;
+use crate::router::handlers::cashu::Unit;
+
+pub mod pay;
+pub mod redeem;
+
+/// NUT-04 mint quote of a foreign Cashu mint
+#[derive(Debug, Deserialize)]
+pub struct ForeignMintQuote {
+    pub quote: String,
+    pub request: String,
+    /// Amount and unit of the quote, left out by mints predating them
+    pub amount: Option<u64>,
+    pub unit: Option<String>,
+    #[serde(default)]
+    pub paid: bool,
+    pub state: Option<String>,
+}
+
+/// NUT-05 melt quote of a foreign Cashu mint, amounts are in the quote's unit
+#[derive(Debug, Deserialize)]
+pub struct ForeignMeltQuote {
+    pub quote: String,
+    pub amount: u64,
+    pub fee_reserve: u64,
+    #[serde(default)]
+    pub paid: bool,
+    pub state: Option<String>,
+}
+
+impl ForeignMintQuote {
+    pub fn is_paid(&self) -> bool {
+        self.paid || matches!(self.state.as_deref(), Some("PAID" | "ISSUED"))
+    }
+}
+
+impl ForeignMeltQuote {
+    pub fn is_paid(&self) -> bool {
+        self.paid || self.state.as_deref() == Some("PAID")
+    }
+
+    pub fn is_pending(&self) -> bool {
+        self.state.as_deref() == Some("PENDING")
+    }
+
+    /// Inputs the quote needs, `None` if the mint quoted more than fits in a
+    /// u64
+    pub fn required(&self) -> Option<u64> {
+        self.amount.checked_add(self.fee_reserve)
+    }
+}
+
+#[derive(Debug, Serialize)]
+struct MintQuoteRequest {
+    amount: u64,
+    unit: Unit,
+}
+
+#[derive(Debug, Serialize)]
+struct MeltQuoteRequest<'a> {
+    request: &'a str,
+    unit: Unit,
+}
+
+#[derive(Debug, Serialize)]
+struct MeltRequest<'a> {
+    quote: &'a str,
+    inputs: &'a [Proof],
+}
+
+/// Client for the NUT-04 and NUT-05 `bolt11` API of an ordinary Cashu mint
+pub struct ForeignMint {
+    url: Url,
+    http: reqwest::Client,
+}
+
+impl ForeignMint {
+    pub fn new(mint_url: &str) -> Result<Self, AppError> {
+        let url = Url::parse(mint_url.trim_end_matches('/')).map_err(|e| {
+            AppError::new(
+                StatusCode::BAD_REQUEST,
+                anyhow!("Invalid mint url {}: {}", mint_url, e),
+            )
+        })?;
+        if !matches!(url.scheme(), "http" | "https") {
+            return Err(AppError::new(
+                StatusCode::BAD_REQUEST,
+                anyhow!("Mint url {} is not an http(s) url", mint_url),
+            ));
+        }
+        Ok(Self {
+            url,
+            http: reqwest::Client::new(),
+        })
+    }
+
+    pub fn url(&self) -> &Url {
+        &self.url
+    }
+
+    pub async fn mint_quote_bolt11(
+        &self,
+        amount: u64,
+        unit: Unit,
+    ) -> Result<ForeignMintQuote, AppError> {
+        self.post("v1/mint/quote/bolt11", &MintQuoteRequest { amount, unit })
+            .await
+    }
+
+    pub async fn get_mint_quote_bolt11(&self, quote: &str) -> Result<ForeignMintQuote, AppError> {
+        self.get(&format!("v1/mint/quote/bolt11/{quote}")).await
+    }
+
+    pub async fn melt_quote_bolt11(
+        &self,
+        request: &str,
+        unit: Unit,
+    ) -> Result<ForeignMeltQuote, AppError> {
+        self.post("v1/melt/quote/bolt11", &MeltQuoteRequest { request, unit })
+            .await
+    }
+
+    pub async fn melt_bolt11(
+        &self,
+        quote: &str,
+        inputs: &[Proof],
+    ) -> Result<ForeignMeltQuote, AppError> {
+        self.post("v1/melt/bolt11", &MeltRequest { quote, inputs })
+            .await
+    }
+
+    async fn get<T: DeserializeOwned>(&self, path: &str) -> Result<T, AppError> {
+        let res = self.http.get(self.endpoint(path)).send().await;
+        self.parse_response(res).await
+    }
+
+    async fn post<B: Serialize, T: DeserializeOwned>(
+        &self,
+        path: &str,
+        body: &B,
+    ) -> Result<T, AppError> {
+        let res = self.http.post(self.endpoint(path)).json(body).send().await;
+        self.parse_response(res).await
+    }
+
+    fn endpoint(&self, path: &str) -> String {
+        format!("{}/{}", self.url.as_str().trim_end_matches('/'), path)
+    }
+
+    /// Parses a successful response, turning NUT-00 errors and unreachable
+    /// mints into bad gateway errors
+    async fn parse_response<T: DeserializeOwned>(
+        &self,
+        res: reqwest::Result<reqwest::Response>,
+    ) -> Result<T, AppError> {
+        let bad_gateway = |e: anyhow::Error| {
+            AppError::new(
+                StatusCode::BAD_GATEWAY,
+                anyhow!("Mint {} failed: {}", self.url, e),
+            )
+        };
+
+        let res = res.map_err(|e| bad_gateway(e.into()))?;
+        let status = res.status();
+        let body = res.bytes().await.map_err(|e| bad_gateway(e.into()))?;
+        if !status.is_success() {
+            let e = match serde_json::from_slice::<ErrorResponse>(&body) {
+                Ok(error) => anyhow!("{} (code {})", error.detail, error.code),
+                Err(_) => anyhow!("{}: {}", status, String::from_utf8_lossy(&body)),
+            };
+            return Err(bad_gateway(e));
+        }
+        serde_json::from_slice(&body).map_err(|e| bad_gateway(e.into()))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use axum::routing::post;
+    use axum::{Json, Router};
+    use serde_json::{json, Value};
+    use tokio::net::TcpListener;
+
+    use super::*;
+
+    /// Serves `quote` as the answer to every NUT-05 melt quote request and
+    /// returns the url of the stand-in mint
+    async fn stand_in_mint(quote: Value) -> String {
+        let app = Router::new().route(
+            "/v1/melt/quote/bolt11",
+            post(move || async move { Json(quote) }),
+        );
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        tokio::spawn(async move { axum::serve(listener, app).await.unwrap() });
+        format!("http://{addr}")
+    }
+
+    #[tokio::test]
+    async fn melt_quote_overflowing_u64_is_not_covered() {
+        let url = stand_in_mint(json!({
+            "quote": "q",
+            "amount": 10,
+            "fee_reserve": u64::MAX,
+            "state": "UNPAID",
+        }))
+        .await;
+        let mint = ForeignMint::new(&url).unwrap();
+        let quote = mint.melt_quote_bolt11("lnbc1", Unit::Sat).await.unwrap();
+        assert_eq!(quote.required(), None);
+    }
+
+    #[tokio::test]
+    async fn melt_quote_requires_amount_plus_fee_reserve() {
+        let url = stand_in_mint(json!({
+            "quote": "q",
+            "amount": 1000,
+            "fee_reserve": 20,
+            "state": "UNPAID",
+        }))
+        .await;
+        let mint = ForeignMint::new(&url).unwrap();
+        let quote = mint.melt_quote_bolt11("lnbc1", Unit::Sat).await.unwrap();
+        assert_eq!(quote.required(), Some(1020));
+        assert!(!quote.is_paid());
+    }
+}
@@ -0,0 +1,234 @@
+use std::str::FromStr;
+
+use anyhow::{anyhow, bail};
+use axum::extract::State;
+use axum::http::StatusCode;
+use axum::Json;
+use fedimint_client::ClientArc;
+use fedimint_core::config::FederationId;
+use fedimint_core::core::OperationId;
+use fedimint_core::Amount;
+use fedimint_ln_client::{LightningClientModule, OutgoingLightningPayment};
+use lightning_invoice::Bolt11Invoice;
+use serde::{Deserialize, Serialize};
+use serde_json::{json, Value};
+use tracing::info;
+
+use super::{ForeignMint, ForeignMintQuote};
+use crate::error::AppError;
+use crate::router::handlers::cashu::Unit;
+use crate::router::handlers::fedimint::ln::wait_for_ln_payment;
+use crate::state::AppState;
+
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct PayRequest {
+    pub mint_url: String,
+    /// An existing `bolt11` mint quote of the foreign mint to pay, a new one is
+    /// created if left out
+    pub quote: Option<String>,
+    /// Amount to pay in `unit`, the quote must be for exactly this amount
+    pub amount: u64,
+    pub unit: Option<Unit>,
+    pub federation_id: Option<FederationId>,
+}
+
+#[derive(Debug, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct PayResponse {
+    /// The paid mint quote, tokens can now be minted for it at the foreign mint
+    pub quote: String,
+    pub invoice: String,
+    pub operation_id: OperationId,
+    pub fee: Amount,
+}
+
+async fn _pay(client: ClientArc, req: PayRequest) -> Result<PayResponse, AppError> {
+    let mint = ForeignMint::new(&req.mint_url)?;
+    let unit = req.unit.unwrap_or(Unit::Sat);
+    let amount_msat = unit.checked_to_amount(req.amount).ok_or_else(|| {
+        AppError::new(
+            StatusCode::BAD_REQUEST,
+            anyhow!("Amount of {} {} is too large", req.amount, unit.as_str()),
+        )
+    })?;
+    // A quote picked by the caller that does not match is the caller's
+    // mistake, a new quote that does not match is the mint's
+    let (quote, mismatch_status) = match req.quote {
+        Some(quote) => (
+            mint.get_mint_quote_bolt11(&quote).await?,
+            StatusCode::BAD_REQUEST,
+        ),
+        None => (
+            mint.mint_quote_bolt11(req.amount, unit).await?,
+            StatusCode::BAD_GATEWAY,
+        ),
+    };
+    if quote.is_paid() {
+        return Err(AppError::new(
+            StatusCode::BAD_REQUEST,
+            anyhow!("Quote {} has already been paid", quote.quote),
+        ));
+    }
+
+    let bolt11 = Bolt11Invoice::from_str(&quote.request).map_err(|e| {
+        AppError::new(
+            StatusCode::BAD_GATEWAY,
+            anyhow!("Mint {} returned an invalid invoice: {}", mint.url(), e),
+        )
+    })?;
+    check_quote(&quote, &bolt11, req.amount, unit, amount_msat).map_err(|e| {
+        AppError::new(
+            mismatch_status,
+            anyhow!("Quote {} of mint {}: {}", quote.quote, mint.url(), e),
+        )
+    })?;
+
+    let lightning_module = client.get_first_module::<LightningClientModule>();
+    lightning_module.select_active_gateway().await?;
+    let OutgoingLightningPayment {
+        payment_type,
+        contract_id,
+        fee,
+    } = lightning_module.pay_bolt11_invoice(bolt11, ()).await?;
+    info!(
+        "Paying mint quote {} of {}, gateway fee: {fee}",
+        quote.quote,
+        mint.url()
+    );
+    wait_for_ln_payment(&client, payment_type, contract_id.to_string(), false).await?;
+
+    Ok(PayResponse {
+        quote: quote.quote,
+        invoice: quote.request,
+        operation_id: payment_type.operation_id(),
+        fee,
+    })
+}
+
+/// Checks that the quote and its invoice are for the amount the caller wants
+/// to pay, so that a mint cannot have more paid than requested
+fn check_quote(
+    quote: &ForeignMintQuote,
+    bolt11: &Bolt11Invoice,
+    amount: u64,
+    unit: Unit,
+    amount_msat: Amount,
+) -> anyhow::Result<()> {
+    if let Some(quote_unit) = quote.unit.as_deref().filter(|u| *u != unit.as_str()) {
+        bail!("quoted in {quote_unit} instead of {}", unit.as_str());
+    }
+    if let Some(quote_amount) = quote.amount.filter(|quoted| *quoted != amount) {
+        bail!(
+            "quoted {quote_amount} {} instead of {amount}",
+            unit.as_str()
+        );
+    }
+    let invoice_amount = bolt11
+        .amount_milli_satoshis()
+        .map(Amount::from_msats)
+        .ok_or_else(|| anyhow!("invoice has no amount"))?;
+    if invoice_amount != amount_msat {
+        bail!("invoice is for {invoice_amount} instead of {amount_msat}");
+    }
+    Ok(())
+}
+
+pub async fn handle_ws(state: AppState, v: Value) -> Result<Value, AppError> {
+    let v = serde_json::from_value::<PayRequest>(v)
+        .map_err(|e| AppError::new(StatusCode::BAD_REQUEST, anyhow!("Invalid request: {}", e)))?;
+    let client = state.get_client(v.federation_id).await?;
+    let pay = _pay(client, v).await?;
+    let pay_json = json!(pay);
+    Ok(pay_json)
+}
+
+#[axum_macros::debug_handler]
+pub async fn handle_rest(
+    State(state): State<AppState>,
+    Json(req): Json<PayRequest>,
+) -> Result<Json<PayResponse>, AppError> {
+    let client = state.get_client(req.federation_id).await?;
+    let pay = _pay(client, req).await?;
+    Ok(Json(pay))
+}
+
+#[cfg(test)]
+mod tests {
+    use bitcoin::secp256k1::{Secp256k1, SecretKey};
+    use bitcoin_hashes::{sha256, Hash};
+    use lightning_invoice::{Currency, InvoiceBuilder, PaymentSecret};
+    use serde_json::json;
+
+    use super::*;
+
+    fn invoice(amount_msat: u64) -> Bolt11Invoice {
+        let key = SecretKey::from_slice(&[1; 32]).unwrap();
+        InvoiceBuilder::new(Currency::Regtest)
+            .description("quote".to_string())
+            .payment_hash(sha256::Hash::hash(&[0; 32]))
+            .payment_secret(PaymentSecret([0; 32]))
+            .current_timestamp()
+            .min_final_cltv_expiry_delta(144)
+            .amount_milli_satoshis(amount_msat)
+            .build_signed(|hash| Secp256k1::new().sign_ecdsa_recoverable(hash, &key))
+            .unwrap()
+    }
+
+    fn quote(fields: Value) -> ForeignMintQuote {
+        let mut quote = json!({ "quote": "q", "request": "lnbcrt1", "state": "UNPAID" });
+        quote
+            .as_object_mut()
+            .unwrap()
+            .extend(fields.as_object().unwrap().clone());
+        serde_json::from_value(quote).unwrap()
+    }
+
+    fn check(quote: &ForeignMintQuote, bolt11: &Bolt11Invoice, amount: u64, unit: Unit) -> bool {
+        let amount_msat = unit.checked_to_amount(amount).unwrap();
+        check_quote(quote, bolt11, amount, unit, amount_msat).is_ok()
+    }
+
+    #[test]
+    fn accepts_quotes_for_the_requested_amount() {
+        let bolt11 = invoice(21_000);
+        assert!(check(&quote(json!({})), &bolt11, 21, Unit::Sat));
+        assert!(check(
+            &quote(json!({ "amount": 21, "unit": "sat" })),
+            &bolt11,
+            21,
+            Unit::Sat
+        ));
+        assert!(check(
+            &quote(json!({ "amount": 21000, "unit": "msat" })),
+            &bolt11,
+            21_000,
+            Unit::Msat
+        ));
+    }
+
+    #[test]
+    fn rejects_quotes_for_other_amounts_or_units() {
+        let bolt11 = invoice(21_000);
+        assert!(!check(&quote(json!({})), &invoice(22_000), 21, Unit::Sat));
+        assert!(!check(&quote(json!({})), &bolt11, 21, Unit::Msat));
+        assert!(!check(
+            &quote(json!({ "amount": 22 })),
+            &bolt11,
+            21,
+            Unit::Sat
+        ));
+        assert!(!check(
+            &quote(json!({ "unit": "usd" })),
+            &bolt11,
+            21,
+            Unit::Sat
+        ));
+        assert!(!check(
+            &quote(json!({ "amount": 21, "unit": "msat" })),
+            &bolt11,
+            21,
+            Unit::Sat
+        ));
+    }
+}
@@ -0,0 +1,242 @@
+use std::str::FromStr;
+
+use anyhow::anyhow;
+use axum::extract::State;
+use axum::http::StatusCode;
+use axum::Json;
+use fedimint_client::ClientArc;
+use fedimint_core::config::FederationId;
+use fedimint_core::core::OperationId;
+use fedimint_core::Amount;
+use fedimint_ln_client::{LightningClientModule, LnReceiveState};
+use futures_util::StreamExt;
+use serde::{Deserialize, Serialize};
+use serde_json::{json, Value};
+use tracing::info;
+
+use super::{ForeignMeltQuote, ForeignMint};
+use crate::error::AppError;
+use crate::router::handlers::cashu::token::{CashuToken, Proof};
+use crate::router::handlers::cashu::Unit;
+use crate::state::AppState;
+
+const REDEEM_DESCRIPTION: &str = "Redeem Cashu token";
+const REDEEM_INVOICE_EXPIRY: u64 = 600;
+
+/// Invoices created before giving up on finding one whose amount plus the
+/// foreign mint's fee reserve is covered by the token
+const MAX_QUOTE_ATTEMPTS: usize = 3;
+
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct RedeemRequest {
+    /// A `cashuA`/`cashuB` token issued by an ordinary Cashu mint
+    pub token: String,
+    /// Overrides the mint url encoded in the token
+    pub mint_url: Option<String>,
+    pub federation_id: Option<FederationId>,
+}
+
+#[derive(Debug, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct RedeemResponse {
+    pub operation_id: OperationId,
+    pub token_amount_msat: Amount,
+    /// Amount credited to our federation wallet
+    pub amount_msat: Amount,
+    /// Lightning fees the foreign mint kept, including its unused fee reserve
+    pub fees_msat: Amount,
+}
+
+async fn _redeem(
+    state: &AppState,
+    client: ClientArc,
+    req: RedeemRequest,
+) -> Result<RedeemResponse, AppError> {
+    let token = CashuToken::from_str(&req.token)
+        .map_err(|e| AppError::new(StatusCode::BAD_REQUEST, anyhow!("Invalid token: {}", e)))?;
+    if let Some(proof) = token.proofs.first() {
+        if state.get_client_by_keyset_id(&proof.id).await.is_ok() {
+            return Err(AppError::new(
+                StatusCode::BAD_REQUEST,
+                anyhow!("Token was issued by a joined federation, reissue it instead"),
+            ));
+        }
+    }
+    let mint_url = req.mint_url.or(token.mint.clone()).ok_or_else(|| {
+        AppError::new(
+            StatusCode::BAD_REQUEST,
+            anyhow!("Token does not name its mint, pass a mint url"),
+        )
+    })?;
+    let mint = ForeignMint::new(&mint_url)?;
+    let unit = token.unit.unwrap_or(Unit::Sat);
+    let (total, token_amount_msat) = token_amount(&token.proofs, unit)?;
+
+    let (operation_id, invoice_amount, quote) = quote_redeem(&client, &mint, unit, total).await?;
+    info!(
+        "Melting {total} {} at {} to pay invoice of operation {operation_id}",
+        unit.as_str(),
+        mint.url()
+    );
+    let melt = mint.melt_bolt11(&quote.quote, &token.proofs).await?;
+    if !melt.is_paid() && !melt.is_pending() {
+        return Err(AppError::new(
+            StatusCode::BAD_GATEWAY,
+            anyhow!("Mint {} did not pay the invoice", mint.url()),
+        ));
+    }
+
+    await_receive(&client, operation_id).await?;
+
+    Ok(RedeemResponse {
+        operation_id,
+        token_amount_msat,
+        amount_msat: invoice_amount,
+        fees_msat: token_amount_msat - invoice_amount,
+    })
+}
+
+/// Total value of the proofs in `unit` and as a fedimint amount
+fn token_amount(proofs: &[Proof], unit: Unit) -> Result<(u64, Amount), AppError> {
+    proofs
+        .iter()
+        .try_fold(0u64, |total, proof| total.checked_add(proof.amount))
+        .and_then(|total| Some((total, unit.checked_to_amount(total)?)))
+        .ok_or_else(|| AppError::new(StatusCode::BAD_REQUEST, anyhow!("Token amount overflows")))
+}
+
+/// Creates an invoice on our federation and a melt quote for it at the
+/// foreign mint, lowering the invoice amount until the token covers the
+/// amount plus the mint's fee reserve
+async fn quote_redeem(
+    client: &ClientArc,
+    mint: &ForeignMint,
+    unit: Unit,
+    total: u64,
+) -> Result<(OperationId, Amount, ForeignMeltQuote), AppError> {
+    let lightning_module = client.get_first_module::<LightningClientModule>();
+    lightning_module.select_active_gateway().await?;
+
+    let mut fee_reserve = 0;
+    for _ in 0..MAX_QUOTE_ATTEMPTS {
+        let amount = total.checked_sub(fee_reserve).filter(|amount| *amount > 0);
+        let Some(amount) = amount else {
+            break;
+        };
+        let amount_msat = unit.to_amount(amount);
+        let (operation_id, invoice) = lightning_module
+            .create_bolt11_invoice(
+                amount_msat,
+                REDEEM_DESCRIPTION.to_string(),
+                Some(REDEEM_INVOICE_EXPIRY),
+                (),
+            )
+            .await?;
+
+        let quote = mint.melt_quote_bolt11(&invoice.to_string(), unit).await?;
+        let required = quote.required().ok_or_else(|| {
+            AppError::new(
+                StatusCode::BAD_GATEWAY,
+                anyhow!(
+                    "Mint {} quoted an amount plus fee reserve that overflows",
+                    mint.url()
+                ),
+            )
+        })?;
+        if required <= total {
+            return Ok((operation_id, amount_msat, quote));
+        }
+        fee_reserve = quote.fee_reserve;
+    }
+
+    Err(AppError::new(
+        StatusCode::BAD_REQUEST,
+        anyhow!(
+            "Token amount ({total} {}) does not cover the mint's fee reserve",
+            unit.as_str()
+        ),
+    ))
+}
+
+async fn await_receive(client: &ClientArc, operation_id: OperationId) -> Result<(), AppError> {
+    let mut updates = client
+        .get_first_module::<LightningClientModule>()
+        .subscribe_ln_receive(operation_id)
+        .await?
+        .into_stream();
+    while let Some(update) = updates.next().await {
+        info!("Update: {update:?}");
+        match update {
+            LnReceiveState::Claimed => return Ok(()),
+            LnReceiveState::Canceled { reason } => {
+                return Err(AppError::new(
+                    StatusCode::INTERNAL_SERVER_ERROR,
+                    anyhow!(reason),
+                ))
+            }
+            _ => {}
+        }
+    }
+
+    Err(AppError::new(
+        StatusCode::INTERNAL_SERVER_ERROR,
+        anyhow!("Unexpected end of stream"),
+    ))
+}
+
+pub async fn handle_ws(state: AppState, v: Value) -> Result<Value, AppError> {
+    let v = serde_json::from_value::<RedeemRequest>(v)
+        .map_err(|e| AppError::new(StatusCode::BAD_REQUEST, anyhow!("Invalid request: {}", e)))?;
+    let client = state.get_client(v.federation_id).await?;
+    let redeem = _redeem(&state, client, v).await?;
+    let redeem_json = json!(redeem);
+    Ok(redeem_json)
+}
+
+#[axum_macros::debug_handler]
+pub async fn handle_rest(
+    State(state): State<AppState>,
+    Json(req): Json<RedeemRequest>,
+) -> Result<Json<RedeemResponse>, AppError> {
+    let client = state.get_client(req.federation_id).await?;
+    let redeem = _redeem(&state, client, req).await?;
+    Ok(Json(redeem))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn proofs(amounts: &[u64]) -> Vec<Proof> {
+        amounts
+            .iter()
+            .map(|amount| Proof {
+                amount: *amount,
+                id: "009a1f293253e41e".to_string(),
+                secret: "secret".to_string(),
+                c: "02bc9097997d81afb2cc7346b5e4345a9346bd2a506eb7958598a72f0cf85163ea".to_string(),
+            })
+            .collect()
+    }
+
+    #[test]
+    fn token_amount_sums_proofs_in_the_unit() {
+        let (total, amount) = token_amount(&proofs(&[1, 2, 8]), Unit::Sat).unwrap();
+        assert_eq!((total, amount), (11, Amount::from_sats(11)));
+        let (total, amount) = token_amount(&proofs(&[1, 2, 8]), Unit::Msat).unwrap();
+        assert_eq!((total, amount), (11, Amount::from_msats(11)));
+    }
+
+    #[test]
+    fn token_amount_rejects_overflowing_tokens() {
+        for (amounts, unit) in [
+            (vec![u64::MAX, 1], Unit::Msat),
+            (vec![u64::MAX / 1000 + 1], Unit::Sat),
+            (vec![u64::MAX / 2, u64::MAX / 2], Unit::Sat),
+        ] {
+            let e = token_amount(&proofs(&amounts), unit).unwrap_err();
+            assert_eq!(e.status, StatusCode::BAD_REQUEST);
+        }
+    }
+}
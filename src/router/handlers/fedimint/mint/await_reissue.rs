@@ -0,0 +1,55 @@
+use anyhow::anyhow;
+use axum::extract::State;
+use axum::http::StatusCode;
+use axum::Json;
+use fedimint_client::ClientArc;
+use fedimint_core::config::FederationId;
+use fedimint_core::core::OperationId;
+use serde::{Deserialize, Serialize};
+use serde_json::{json, Value};
+
+use crate::error::AppError;
+use crate::notes::await_reissue;
+use crate::state::AppState;
+
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct AwaitReissueRequest {
+    pub operation_id: OperationId,
+    pub federation_id: Option<FederationId>,
+}
+
+#[derive(Debug, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct AwaitReissueResponse {
+    pub operation_id: OperationId,
+}
+
+async fn _await_reissue(
+    client: ClientArc,
+    req: AwaitReissueRequest,
+) -> Result<AwaitReissueResponse, AppError> {
+    await_reissue(&client, req.operation_id).await?;
+    Ok(AwaitReissueResponse {
+        operation_id: req.operation_id,
+    })
+}
+
+pub async fn handle_ws(state: AppState, v: Value) -> Result<Value, AppError> {
+    let v = serde_json::from_value::<AwaitReissueRequest>(v)
+        .map_err(|e| AppError::new(StatusCode::BAD_REQUEST, anyhow!("Invalid request: {}", e)))?;
+    let client = state.get_client(v.federation_id).await?;
+    let await_reissue = _await_reissue(client, v).await?;
+    let await_reissue_json = json!(await_reissue);
+    Ok(await_reissue_json)
+}
+
+#[axum_macros::debug_handler]
+pub async fn handle_rest(
+    State(state): State<AppState>,
+    Json(req): Json<AwaitReissueRequest>,
+) -> Result<Json<AwaitReissueResponse>, AppError> {
+    let client = state.get_client(req.federation_id).await?;
+    let await_reissue = _await_reissue(client, req).await?;
+    Ok(Json(await_reissue))
+}
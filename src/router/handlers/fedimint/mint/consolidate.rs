@@ -0,0 +1,301 @@
+use std::collections::BTreeMap;
+use std::pin::pin;
+use std::time::Duration;
+
+use anyhow::{anyhow, ensure};
+use axum::async_trait;
+use axum::extract::State;
+use axum::http::StatusCode;
+use axum::Json;
+use clap::Args;
+use fedimint_client::ClientArc;
+use fedimint_core::config::FederationId;
+use fedimint_core::core::OperationId;
+use fedimint_core::{Amount, TieredMulti, TieredSummary};
+use fedimint_mint_client::NotesSelector;
+use futures_util::{Stream, StreamExt};
+use serde::{Deserialize, Serialize};
+use serde_json::{json, Value};
+use tracing::{info, warn};
+
+use crate::error::AppError;
+use crate::notes::reissue_own_notes;
+use crate::router::handlers::cashu::get_mint_config;
+use crate::router::handlers::fedimint::admin::get_wallet_summary;
+use crate::state::AppState;
+
+/// Notes per denomination the mint client aims for when issuing notes. It is
+/// hardcoded in the client, so consolidated notes always follow it.
+const CLIENT_NOTES_PER_DENOMINATION: u16 = 2;
+
+#[derive(Debug, Clone, Args)]
+pub struct ConsolidateConfig {
+    /// Consolidate a federation's notes in the background once the wallet
+    /// holds more than this many notes
+    #[clap(
+        long = "consolidate-note-threshold",
+        env = "CONSOLIDATE_NOTE_THRESHOLD"
+    )]
+    pub note_threshold: Option<usize>,
+
+    /// Seconds between checks of the note count
+    #[clap(
+        long = "consolidate-interval",
+        env = "CONSOLIDATE_INTERVAL",
+        default_value_t = 3600
+    )]
+    pub interval: u64,
+
+    /// Notes per denomination to keep when consolidating in the background
+    #[clap(
+        long = "consolidate-notes-per-tier",
+        env = "CONSOLIDATE_NOTES_PER_TIER",
+        default_value_t = 2
+    )]
+    pub notes_per_tier: usize,
+
+    /// Skip background consolidations whose fee would exceed this many msat
+    #[clap(long = "consolidate-max-fee", env = "CONSOLIDATE_MAX_FEE")]
+    pub max_fee: Option<u64>,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct ConsolidateRequest {
+    /// Notes to keep of every denomination, defaults to the 2 the client aims
+    /// for
+    pub notes_per_tier: Option<usize>,
+    /// Notes to keep of specific denominations, keyed by msat amount
+    #[serde(default)]
+    pub tiers: BTreeMap<u64, usize>,
+    /// Only report what consolidating would do
+    #[serde(default)]
+    pub dry_run: bool,
+    pub federation_id: Option<FederationId>,
+}
+
+#[derive(Debug, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct ConsolidateResponse {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub operation_id: Option<OperationId>,
+    pub denominations_before: TieredSummary,
+    /// Estimate of the denominations after the reissue. The client picks the
+    /// denominations of the issued notes itself, so they can differ.
+    pub estimated_denominations_after: TieredSummary,
+    pub notes_reissued: usize,
+    pub notes_issued: usize,
+    pub amount_reissued_msat: Amount,
+    pub fee_msat: Amount,
+}
+
+/// Which notes to reissue and an estimate of what the wallet holds afterwards
+struct ConsolidationPlan {
+    before: TieredSummary,
+    selected: TieredSummary,
+    issued: TieredSummary,
+    estimated: TieredSummary,
+    fee: Amount,
+}
+
+impl ConsolidationPlan {
+    fn unchanged(before: TieredSummary) -> Self {
+        Self {
+            estimated: before.clone(),
+            before,
+            selected: TieredSummary::default(),
+            issued: TieredSummary::default(),
+            fee: Amount::ZERO,
+        }
+    }
+}
+
+async fn plan(
+    client: &ClientArc,
+    target: impl Fn(Amount) -> usize,
+) -> Result<ConsolidationPlan, AppError> {
+    let config = get_mint_config(client)?;
+    let before = get_wallet_summary(client).await;
+
+    let mut selected = TieredSummary::default();
+    let mut kept = TieredSummary::default();
+    for (tier, count) in before.iter() {
+        let keep = count.min(target(tier));
+        kept.inc(tier, keep);
+        selected.inc(tier, count - keep);
+    }
+
+    let spend_fee = config.fee_consensus.note_spend_abs * selected.count_items() as u64;
+    let available = selected.total_amount().saturating_sub(spend_fee);
+    let mut issued = represent(available, &kept, client)?;
+    let issuance_fee = config.fee_consensus.note_issuance_abs * issued.count_items() as u64;
+    if issuance_fee != Amount::ZERO {
+        issued = represent(available.saturating_sub(issuance_fee), &kept, client)?;
+    }
+
+    let mut estimated = TieredSummary::default();
+    for (tier, count) in kept.iter().chain(issued.iter()) {
+        estimated.inc(tier, count);
+    }
+
+    Ok(ConsolidationPlan {
+        before,
+        fee: selected.total_amount() - issued.total_amount(),
+        selected,
+        issued,
+        estimated,
+    })
+}
+
+fn represent(
+    amount: Amount,
+    kept: &TieredSummary,
+    client: &ClientArc,
+) -> Result<TieredSummary, AppError> {
+    Ok(TieredSummary::represent_amount(
+        amount,
+        kept,
+        &get_mint_config(client)?.tbs_pks,
+        CLIENT_NOTES_PER_DENOMINATION,
+    ))
+}
+
+/// Reissues the notes exceeding `target` per denomination, unless the fee
+/// would exceed `max_fee`
+async fn consolidate(
+    client: &ClientArc,
+    target: impl Fn(Amount) -> usize,
+    dry_run: bool,
+    max_fee: Option<Amount>,
+) -> Result<ConsolidateResponse, AppError> {
+    let mut plan = plan(client, target).await?;
+    // Reissuing a single note cannot reduce the note count
+    if plan.selected.count_items() <= 1 {
+        plan = ConsolidationPlan::unchanged(plan.before);
+    }
+
+    let too_expensive = max_fee.is_some_and(|max_fee| plan.fee > max_fee);
+    if too_expensive {
+        info!(
+            "Skipping consolidation of federation {}, the fee of {} exceeds the maximum",
+            client.federation_id(),
+            plan.fee
+        );
+    }
+
+    let mut operation_id = None;
+    if !dry_run && !too_expensive && plan.selected.count_items() > 0 {
+        let (reissue_id, _) = reissue_own_notes(
+            client,
+            &SelectNotesByTier(plan.selected.clone()),
+            plan.selected.total_amount(),
+        )
+        .await?;
+        operation_id = Some(reissue_id);
+    }
+
+    Ok(ConsolidateResponse {
+        operation_id,
+        notes_reissued: plan.selected.count_items(),
+        notes_issued: plan.issued.count_items(),
+        amount_reissued_msat: plan.selected.total_amount(),
+        fee_msat: plan.fee,
+        denominations_before: plan.before,
+        estimated_denominations_after: plan.estimated,
+    })
+}
+
+/// Selects the given number of notes of each denomination
+struct SelectNotesByTier(TieredSummary);
+
+#[async_trait]
+impl<Note: Send> NotesSelector<Note> for SelectNotesByTier {
+    async fn select_notes(
+        &self,
+        stream: impl Stream<Item = (Amount, Note)> + Send,
+        _requested_amount: Amount,
+    ) -> anyhow::Result<TieredMulti<Note>> {
+        let mut stream = pin!(stream);
+        let mut missing = self.0.iter().collect::<BTreeMap<_, _>>();
+        let mut selected = Vec::new();
+        while let Some((amount, note)) = stream.next().await {
+            if let Some(count) = missing.get_mut(&amount).filter(|count| **count > 0) {
+                *count -= 1;
+                selected.push((amount, note));
+            }
+        }
+        ensure!(
+            missing.values().all(|count| *count == 0),
+            "The wallet's notes changed while consolidating, retry"
+        );
+        Ok(selected.into_iter().collect())
+    }
+}
+
+/// Consolidates the notes of every joined federation whose wallet holds more
+/// notes than the configured threshold
+pub fn spawn_consolidate_policy(state: AppState, config: ConsolidateConfig) {
+    let Some(threshold) = config.note_threshold else {
+        return;
+    };
+    tokio::spawn(async move {
+        let mut interval = tokio::time::interval(Duration::from_secs(config.interval));
+        loop {
+            interval.tick().await;
+            for client in state.multimint.all().await {
+                let notes = get_wallet_summary(&client).await.count_items();
+                if notes <= threshold {
+                    continue;
+                }
+                info!(
+                    "Wallet of federation {} holds {notes} notes, consolidating",
+                    client.federation_id()
+                );
+                let target = |_| config.notes_per_tier;
+                let max_fee = config.max_fee.map(Amount::from_msats);
+                if let Err(e) = consolidate(&client, target, false, max_fee).await {
+                    warn!(
+                        "Consolidating notes of federation {} failed: {}",
+                        client.federation_id(),
+                        e.error
+                    );
+                }
+            }
+        }
+    });
+}
+
+async fn _consolidate(
+    client: ClientArc,
+    req: ConsolidateRequest,
+) -> Result<ConsolidateResponse, AppError> {
+    let notes_per_tier = req
+        .notes_per_tier
+        .unwrap_or(CLIENT_NOTES_PER_DENOMINATION as usize);
+    let target = |tier: Amount| {
+        req.tiers
+            .get(&tier.msats)
+            .copied()
+            .unwrap_or(notes_per_tier)
+    };
+    consolidate(&client, target, req.dry_run, None).await
+}
+
+pub async fn handle_ws(state: AppState, v: Value) -> Result<Value, AppError> {
+    let v = serde_json::from_value::<ConsolidateRequest>(v)
+        .map_err(|e| AppError::new(StatusCode::BAD_REQUEST, anyhow!("Invalid request: {}", e)))?;
+    let client = state.get_client(v.federation_id).await?;
+    let consolidate = _consolidate(client, v).await?;
+    let consolidate_json = json!(consolidate);
+    Ok(consolidate_json)
+}
+
+#[axum_macros::debug_handler]
+pub async fn handle_rest(
+    State(state): State<AppState>,
+    Json(req): Json<ConsolidateRequest>,
+) -> Result<Json<ConsolidateResponse>, AppError> {
+    let client = state.get_client(req.federation_id).await?;
+    let consolidate = _consolidate(client, req).await?;
+    Ok(Json(consolidate))
+}
@@ -0,0 +1,65 @@
+use anyhow::anyhow;
+use axum::extract::State;
+use axum::http::StatusCode;
+use axum::Json;
+use serde::{Deserialize, Serialize};
+use serde_json::{json, Value};
+
+use crate::error::AppError;
+use crate::router::handlers::cashu::check::record_notes;
+use crate::router::handlers::cashu::token::{Ecash, EcashFormat};
+use crate::router::handlers::cashu::Unit;
+use crate::state::AppState;
+
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct ConvertRequest {
+    pub notes: Ecash,
+    pub format: EcashFormat,
+    pub unit: Option<Unit>,
+    pub mint_url: Option<String>,
+}
+
+#[derive(Debug, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct ConvertResponse {
+    pub notes: String,
+}
+
+async fn _convert(state: AppState, req: ConvertRequest) -> Result<ConvertResponse, AppError> {
+    let notes = req.notes.to_oob_notes(&state).await?;
+    let notes = match req.format {
+        EcashFormat::Fedimint => notes.to_string(),
+        format => {
+            let client = state
+                .get_client_by_prefix(&notes.federation_id_prefix())
+                .await?;
+            record_notes(&client, &notes).await?;
+            format.encode(
+                &client,
+                &notes,
+                req.unit.unwrap_or(Unit::Msat),
+                req.mint_url,
+            )?
+        }
+    };
+
+    Ok(ConvertResponse { notes })
+}
+
+pub async fn handle_ws(state: AppState, v: Value) -> Result<Value, AppError> {
+    let v = serde_json::from_value::<ConvertRequest>(v)
+        .map_err(|e| AppError::new(StatusCode::BAD_REQUEST, anyhow!("Invalid request: {}", e)))?;
+    let convert = _convert(state, v).await?;
+    let convert_json = json!(convert);
+    Ok(convert_json)
+}
+
+#[axum_macros::debug_handler]
+pub async fn handle_rest(
+    State(state): State<AppState>,
+    Json(req): Json<ConvertRequest>,
+) -> Result<Json<ConvertResponse>, AppError> {
+    let convert = _convert(state, req).await?;
+    Ok(Json(convert))
+}
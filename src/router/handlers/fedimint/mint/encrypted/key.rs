@@ -0,0 +1,54 @@
+use anyhow::anyhow;
+use axum::extract::State;
+use axum::http::StatusCode;
+use axum::Json;
+use bitcoin::secp256k1::PublicKey;
+use fedimint_client::ClientArc;
+use fedimint_core::config::FederationId;
+use serde::{Deserialize, Serialize};
+use serde_json::{json, Value};
+
+use super::receiving_pubkey;
+use crate::error::AppError;
+use crate::state::AppState;
+
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct ReceivingKeyRequest {
+    pub federation_id: Option<FederationId>,
+}
+
+#[derive(Debug, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct ReceivingKeyResponse {
+    pub pubkey: PublicKey,
+    /// The key without its parity byte, as used by Nostr
+    pub x_only_pubkey: String,
+}
+
+async fn _receiving_key(client: ClientArc) -> Result<ReceivingKeyResponse, AppError> {
+    let pubkey = receiving_pubkey(&client).await?;
+    Ok(ReceivingKeyResponse {
+        pubkey,
+        x_only_pubkey: pubkey.x_only_public_key().0.to_string(),
+    })
+}
+
+pub async fn handle_ws(state: AppState, v: Value) -> Result<Value, AppError> {
+    let v = serde_json::from_value::<ReceivingKeyRequest>(v)
+        .map_err(|e| AppError::new(StatusCode::BAD_REQUEST, anyhow!("Invalid request: {}", e)))?;
+    let client = state.get_client(v.federation_id).await?;
+    let key = _receiving_key(client).await?;
+    let key_json = json!(key);
+    Ok(key_json)
+}
+
+#[axum_macros::debug_handler]
+pub async fn handle_rest(
+    State(state): State<AppState>,
+    Json(req): Json<ReceivingKeyRequest>,
+) -> Result<Json<ReceivingKeyResponse>, AppError> {
+    let client = state.get_client(req.federation_id).await?;
+    let key = _receiving_key(client).await?;
+    Ok(Json(key))
+}
@@ -0,0 +1,358 @@
+use std::str::FromStr;
+
+use anyhow::anyhow;
+use axum::http::StatusCode;
+use base64::engine::general_purpose::STANDARD;
+use base64::Engine;
+use bitcoin::secp256k1::rand::{thread_rng, RngCore};
+use bitcoin::secp256k1::{ecdh, PublicKey, Secp256k1, SecretKey};
+use bitcoin_hashes::hmac::{Hmac, HmacEngine};
+use bitcoin_hashes::{sha256, Hash, HashEngine};
+use chacha20::cipher::{KeyIvInit, StreamCipher};
+use chacha20::ChaCha20;
+use fedimint_client::derivable_secret::ChildId;
+use fedimint_client::secret::{PlainRootSecretStrategy, RootSecretStrategy};
+use fedimint_client::ClientArc;
+use serde::{Deserialize, Serialize};
+
+use crate::error::AppError;
+
+pub mod key;
+pub mod receive;
+pub mod send;
+
+/// Child of the client's root secret the receiving key is derived from, far
+/// from the ids the client uses for its modules
+const RECEIVING_KEY_CHILD_ID: u64 = 0x6674_7470;
+
+const NIP44_VERSION: u8 = 2;
+const NIP44_SALT: &[u8] = b"nip44-v2";
+const NIP44_NONCE_LEN: usize = 32;
+const NIP44_MAC_LEN: usize = 32;
+const NIP44_MAX_PLAINTEXT_LEN: usize = 65535;
+
+/// Notes encrypted to a recipient public key with NIP-44 v2. The recipient
+/// decrypts `payload` with the conversation key of its secret key and
+/// `senderPubkey`, e.g. with any Nostr library.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct Envelope {
+    /// Receiving key of the server that sealed the envelope
+    pub sender_pubkey: PublicKey,
+    /// Base64 encoded NIP-44 v2 payload of the serialized notes
+    pub payload: String,
+}
+
+/// Returns the key encrypted notes are received with. It is derived from the
+/// client's root secret, so it survives restarts and is recovered along with
+/// the wallet.
+pub async fn receiving_key(client: &ClientArc) -> Result<SecretKey, AppError> {
+    let client_secret = client
+        .root_secret_encoding::<<PlainRootSecretStrategy as RootSecretStrategy>::Encoding>()
+        .await?;
+    let bytes = PlainRootSecretStrategy::to_root_secret(&client_secret)
+        .child_key(ChildId(RECEIVING_KEY_CHILD_ID))
+        .to_random_bytes::<32>();
+    Ok(SecretKey::from_slice(&bytes)?)
+}
+
+pub async fn receiving_pubkey(client: &ClientArc) -> Result<PublicKey, AppError> {
+    Ok(receiving_key(client).await?.public_key(&Secp256k1::new()))
+}
+
+/// Parses a compressed public key or a 32 byte x-only key as used by Nostr
+pub fn parse_pubkey(key: &str) -> Result<PublicKey, AppError> {
+    let key = key.trim();
+    let key = if key.len() == 64 {
+        format!("02{key}")
+    } else {
+        key.to_string()
+    };
+    PublicKey::from_str(&key).map_err(|e| {
+        AppError::new(
+            StatusCode::BAD_REQUEST,
+            anyhow!("Invalid recipient public key: {}", e),
+        )
+    })
+}
+
+pub fn seal(
+    sender_key: &SecretKey,
+    recipient: &PublicKey,
+    plaintext: &str,
+) -> Result<Envelope, AppError> {
+    let mut nonce = [0u8; NIP44_NONCE_LEN];
+    thread_rng().fill_bytes(&mut nonce);
+    let payload = nip44_encrypt(
+        &conversation_key(sender_key, recipient),
+        &nonce,
+        plaintext.as_bytes(),
+    )?;
+    Ok(Envelope {
+        sender_pubkey: sender_key.public_key(&Secp256k1::new()),
+        payload,
+    })
+}
+
+/// Decrypts an envelope, returning `None` if it was not sealed for `key`
+pub fn open(key: &SecretKey, envelope: &Envelope) -> Result<Option<String>, AppError> {
+    let plaintext = match nip44_decrypt(
+        &conversation_key(key, &envelope.sender_pubkey),
+        &envelope.payload,
+    )? {
+        Some(plaintext) => plaintext,
+        None => return Ok(None),
+    };
+    let plaintext = String::from_utf8(plaintext).map_err(|_| {
+        AppError::new(
+            StatusCode::BAD_REQUEST,
+            anyhow!("Envelope does not contain text"),
+        )
+    })?;
+    Ok(Some(plaintext))
+}
+
+fn conversation_key(key: &SecretKey, pubkey: &PublicKey) -> [u8; 32] {
+    let shared_point = ecdh::shared_secret_point(pubkey, key);
+    hmac_sha256(NIP44_SALT, &[&shared_point[..32]])
+}
+
+/// Keys of one message: ChaCha20 key and nonce and the HMAC key, expanded from
+/// the conversation key with the message nonce as HKDF info
+fn message_keys(
+    conversation_key: &[u8; 32],
+    nonce: &[u8; NIP44_NONCE_LEN],
+) -> ([u8; 32], [u8; 12], [u8; 32]) {
+    let mut okm = Vec::with_capacity(96);
+    let mut previous: Vec<u8> = Vec::new();
+    for counter in 1u8..=3 {
+        let block = hmac_sha256(conversation_key, &[previous.as_slice(), nonce, &[counter]]);
+        okm.extend_from_slice(&block);
+        previous = block.to_vec();
+    }
+
+    let mut chacha_key = [0u8; 32];
+    let mut chacha_nonce = [0u8; 12];
+    let mut hmac_key = [0u8; 32];
+    chacha_key.copy_from_slice(&okm[0..32]);
+    chacha_nonce.copy_from_slice(&okm[32..44]);
+    hmac_key.copy_from_slice(&okm[44..76]);
+    (chacha_key, chacha_nonce, hmac_key)
+}
+
+fn nip44_encrypt(
+    conversation_key: &[u8; 32],
+    nonce: &[u8; NIP44_NONCE_LEN],
+    plaintext: &[u8],
+) -> Result<String, AppError> {
+    if plaintext.is_empty() || plaintext.len() > NIP44_MAX_PLAINTEXT_LEN {
+        return Err(AppError::new(
+            StatusCode::BAD_REQUEST,
+            anyhow!(
+                "Notes must serialize to 1 to {} bytes to be encrypted",
+                NIP44_MAX_PLAINTEXT_LEN
+            ),
+        ));
+    }
+
+    let (chacha_key, chacha_nonce, hmac_key) = message_keys(conversation_key, nonce);
+    let mut ciphertext = Vec::with_capacity(2 + padded_len(plaintext.len()));
+    ciphertext.extend_from_slice(&(plaintext.len() as u16).to_be_bytes());
+    ciphertext.extend_from_slice(plaintext);
+    ciphertext.resize(2 + padded_len(plaintext.len()), 0);
+    ChaCha20::new(&chacha_key.into(), &chacha_nonce.into()).apply_keystream(&mut ciphertext);
+    let mac = hmac_sha256(&hmac_key, &[nonce, ciphertext.as_slice()]);
+
+    let mut payload = Vec::with_capacity(1 + NIP44_NONCE_LEN + ciphertext.len() + NIP44_MAC_LEN);
+    payload.push(NIP44_VERSION);
+    payload.extend_from_slice(nonce);
+    payload.extend_from_slice(&ciphertext);
+    payload.extend_from_slice(&mac);
+    Ok(STANDARD.encode(payload))
+}
+
+/// Returns `None` if the payload was not encrypted with `conversation_key`
+fn nip44_decrypt(conversation_key: &[u8; 32], payload: &str) -> Result<Option<Vec<u8>>, AppError> {
+    let invalid = |reason: &str| {
+        AppError::new(
+            StatusCode::BAD_REQUEST,
+            anyhow!("Invalid envelope payload: {}", reason),
+        )
+    };
+
+    let payload = STANDARD
+        .decode(payload.trim())
+        .map_err(|_| invalid("not base64"))?;
+    if payload.first() != Some(&NIP44_VERSION) {
+        return Err(invalid("unsupported version"));
+    }
+    if payload.len() < 1 + NIP44_NONCE_LEN + 2 + 32 + NIP44_MAC_LEN {
+        return Err(invalid("too short"));
+    }
+    let (nonce, rest) = payload[1..].split_at(NIP44_NONCE_LEN);
+    let (ciphertext, mac) = rest.split_at(rest.len() - NIP44_MAC_LEN);
+    let nonce: [u8; NIP44_NONCE_LEN] = nonce.try_into().expect("split at nonce length");
+
+    let (chacha_key, chacha_nonce, hmac_key) = message_keys(conversation_key, &nonce);
+    if !fixed_time_eq(&hmac_sha256(&hmac_key, &[&nonce, ciphertext]), mac) {
+        return Ok(None);
+    }
+
+    let mut padded = ciphertext.to_vec();
+    ChaCha20::new(&chacha_key.into(), &chacha_nonce.into()).apply_keystream(&mut padded);
+    let len = u16::from_be_bytes([padded[0], padded[1]]) as usize;
+    if len == 0 || padded.len() != 2 + padded_len(len) {
+        return Err(invalid("bad padding"));
+    }
+    Ok(Some(padded[2..2 + len].to_vec()))
+}
+
+/// NIP-44 padding: 32 bytes at least, then chunks of an eighth of the next
+/// power of two
+fn padded_len(len: usize) -> usize {
+    if len <= 32 {
+        return 32;
+    }
+    let next_power = 1usize << (usize::BITS - (len - 1).leading_zeros());
+    let chunk = if next_power <= 256 {
+        32
+    } else {
+        next_power / 8
+    };
+    chunk * ((len - 1) / chunk + 1)
+}
+
+fn hmac_sha256(key: &[u8], data: &[&[u8]]) -> [u8; 32] {
+    let mut engine = HmacEngine::<sha256::Hash>::new(key);
+    for chunk in data {
+        engine.input(chunk);
+    }
+    Hmac::<sha256::Hash>::from_engine(engine).into_inner()
+}
+
+fn fixed_time_eq(a: &[u8], b: &[u8]) -> bool {
+    a.len() == b.len() && a.iter().zip(b).fold(0u8, |acc, (x, y)| acc | (x ^ y)) == 0
+}
+
+#[cfg(test)]
+mod tests {
+    use bitcoin_hashes::hex::{FromHex, ToHex};
+
+    use super::*;
+
+    // The vectors are cases of the official `nip44.vectors.json`
+
+    fn bytes<const N: usize>(hex: &str) -> [u8; N] {
+        Vec::<u8>::from_hex(hex).unwrap().try_into().unwrap()
+    }
+
+    #[test]
+    fn conversation_key_matches_nip44_vectors() {
+        let vectors = [
+            (
+                "315e59ff51cb9209768cf7da80791ddcaae56ac9775eb25b6dee1234bc5d2268",
+                "c2f9d9948dc8c7c38321e4b85c8558872eafa0641cd269db76848a6073e69133",
+                "3dfef0ce2a4d80a25e7a328accf73448ef67096f65f79588e358d9a0eb9013f1",
+            ),
+            (
+                "a1e37752c9fdc1273be53f68c5f74be7c8905728e8de75800b94262f9497c86e",
+                "03bb7947065dde12ba991ea045132581d0954f042c84e06d8c00066e23c1a800",
+                "4d14f36e81b8452128da64fe6f1eae873baae2f444b02c950b90e43553f2178b",
+            ),
+        ];
+        for (sec1, pub2, key) in vectors {
+            let sec1 = SecretKey::from_slice(&bytes::<32>(sec1)).unwrap();
+            let pub2 = parse_pubkey(pub2).unwrap();
+            assert_eq!(conversation_key(&sec1, &pub2).to_hex(), key);
+        }
+    }
+
+    #[test]
+    fn message_keys_match_nip44_vectors() {
+        let (chacha_key, chacha_nonce, hmac_key) = message_keys(
+            &bytes("a1a3d60f3470a8612633924e91febf96dc5366ce130f658b1f0fc652c20b3b54"),
+            &bytes("e1e6f880560d6d149ed83dcc7e5861ee62a5ee051f7fde9975fe5d25d2a02d72"),
+        );
+        assert_eq!(
+            chacha_key.to_hex(),
+            "f145f3bed47cb70dbeaac07f3a3fe683e822b3715edb7c4fe310829014ce7d76"
+        );
+        assert_eq!(chacha_nonce.to_hex(), "c4ad129bb01180c0933a160c");
+        assert_eq!(
+            hmac_key.to_hex(),
+            "027c1db445f05e2eee864a0975b0ddef5b7110583c8c192de3732571ca5838c4"
+        );
+    }
+
+    #[test]
+    fn padded_len_matches_nip44_vectors() {
+        let vectors = [
+            (16, 32),
+            (32, 32),
+            (33, 64),
+            (37, 64),
+            (45, 64),
+            (49, 64),
+            (64, 64),
+            (65, 96),
+            (100, 128),
+            (111, 128),
+            (200, 224),
+            (250, 256),
+            (320, 320),
+            (383, 384),
+            (384, 384),
+            (400, 448),
+            (500, 512),
+            (512, 512),
+            (515, 640),
+            (700, 768),
+            (800, 896),
+            (900, 1024),
+            (1020, 1024),
+            (65536, 65536),
+        ];
+        for (len, padded) in vectors {
+            assert_eq!(padded_len(len), padded, "unpadded length {len}");
+        }
+    }
+
+    #[test]
+    fn encrypt_decrypt_matches_nip44_vectors() {
+        let vectors = [
+            (
+                "0000000000000000000000000000000000000000000000000000000000000001",
+                "0000000000000000000000000000000000000000000000000000000000000002",
+                "c41c775356fd92eadc63ff5a0dc1da211b268cbea22316767095b2871ea1412d",
+                "0000000000000000000000000000000000000000000000000000000000000001",
+                "a",
+                "AgAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAABee0G5VSK0/9YypIObAtDKfYEAjD35uVkHyB0F4DwrcNaCXlCWZKaArsGrY6M9wnuTMxWfp1RTN9Xga8no+kF5Vsb",
+            ),
+            (
+                "0000000000000000000000000000000000000000000000000000000000000002",
+                "0000000000000000000000000000000000000000000000000000000000000001",
+                "c41c775356fd92eadc63ff5a0dc1da211b268cbea22316767095b2871ea1412d",
+                "f00000000000000000000000000000f00000000000000000000000000000000f",
+                "🍕🫃",
+                "AvAAAAAAAAAAAAAAAAAAAPAAAAAAAAAAAAAAAAAAAAAPSKSK6is9ngkX2+cSq85Th16oRTISAOfhStnixqZziKMDvB0QQzgFZdjLTPicCJaV8nDITO+QfaQ61+KbWQIOO2Yj",
+            ),
+        ];
+        let secp = Secp256k1::new();
+        for (sec1, sec2, key, nonce, plaintext, payload) in vectors {
+            let sec1 = SecretKey::from_slice(&bytes::<32>(sec1)).unwrap();
+            let sec2 = SecretKey::from_slice(&bytes::<32>(sec2)).unwrap();
+            let expected_key = key;
+            let key = conversation_key(&sec1, &sec2.public_key(&secp));
+            assert_eq!(key.to_hex(), expected_key);
+            assert_eq!(conversation_key(&sec2, &sec1.public_key(&secp)), key);
+            assert_eq!(
+                nip44_encrypt(&key, &bytes(nonce), plaintext.as_bytes()).unwrap(),
+                payload
+            );
+            assert_eq!(
+                nip44_decrypt(&key, payload).unwrap().unwrap(),
+                plaintext.as_bytes()
+            );
+        }
+    }
+}
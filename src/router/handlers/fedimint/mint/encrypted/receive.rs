@@ -0,0 +1,91 @@
+use std::str::FromStr;
+
+use anyhow::anyhow;
+use axum::extract::State;
+use axum::http::StatusCode;
+use axum::Json;
+use serde::Deserialize;
+use serde_json::{json, Value};
+use tracing::info;
+
+use super::{open, receiving_key, Envelope};
+use crate::error::AppError;
+use crate::router::handlers::cashu::swap::{reissue_notes, reissue_notes_in_background};
+use crate::router::handlers::cashu::token::Ecash;
+use crate::router::handlers::fedimint::mint::reissue::ReissueResponse;
+use crate::state::AppState;
+
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct ReceiveEncryptedRequest {
+    pub envelope: Envelope,
+    /// Return as soon as the reissue is submitted, await it with
+    /// `await-reissue`
+    #[serde(default)]
+    pub no_wait: bool,
+}
+
+/// Opens the envelope with the receiving key of whichever joined federation
+/// it was sealed for
+async fn open_envelope(state: &AppState, envelope: &Envelope) -> Result<String, AppError> {
+    for client in state.multimint.all().await {
+        if let Some(plaintext) = open(&receiving_key(&client).await?, envelope)? {
+            return Ok(plaintext);
+        }
+    }
+
+    Err(AppError::new(
+        StatusCode::BAD_REQUEST,
+        anyhow!("Envelope was not encrypted to any of our receiving keys"),
+    ))
+}
+
+async fn _receive_encrypted(
+    state: &AppState,
+    req: ReceiveEncryptedRequest,
+) -> Result<ReissueResponse, AppError> {
+    let plaintext = open_envelope(state, &req.envelope).await?;
+    let ecash = Ecash::from_str(&plaintext).map_err(|e| {
+        AppError::new(
+            StatusCode::BAD_REQUEST,
+            anyhow!("Envelope does not contain e-cash: {}", e),
+        )
+    })?;
+    let notes = ecash.to_oob_notes(state).await?;
+    let client = state
+        .get_client_by_prefix(&notes.federation_id_prefix())
+        .await?;
+
+    let amount_msat = notes.total_amount();
+    let operation_id = if req.no_wait {
+        reissue_notes_in_background(&client, notes).await?
+    } else {
+        reissue_notes(&client, notes).await?
+    };
+    info!(
+        "Reissuing {} received from {}: {}",
+        amount_msat, req.envelope.sender_pubkey, operation_id
+    );
+
+    Ok(ReissueResponse {
+        operation_id,
+        amount_msat,
+    })
+}
+
+pub async fn handle_ws(state: AppState, v: Value) -> Result<Value, AppError> {
+    let v = serde_json::from_value::<ReceiveEncryptedRequest>(v)
+        .map_err(|e| AppError::new(StatusCode::BAD_REQUEST, anyhow!("Invalid request: {}", e)))?;
+    let reissue = _receive_encrypted(&state, v).await?;
+    let reissue_json = json!(reissue);
+    Ok(reissue_json)
+}
+
+#[axum_macros::debug_handler]
+pub async fn handle_rest(
+    State(state): State<AppState>,
+    Json(req): Json<ReceiveEncryptedRequest>,
+) -> Result<Json<ReissueResponse>, AppError> {
+    let reissue = _receive_encrypted(&state, req).await?;
+    Ok(Json(reissue))
+}
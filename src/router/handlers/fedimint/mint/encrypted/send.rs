@@ -0,0 +1,85 @@
+use anyhow::anyhow;
+use axum::extract::State;
+use axum::http::StatusCode;
+use axum::Json;
+use fedimint_client::ClientArc;
+use fedimint_core::core::OperationId;
+use fedimint_core::Amount;
+use fedimint_mint_client::MintClientModule;
+use serde::{Deserialize, Serialize};
+use serde_json::{json, Value};
+use tracing::info;
+
+use super::{parse_pubkey, receiving_key, seal, Envelope};
+use crate::error::AppError;
+use crate::router::handlers::fedimint::mint::spend::{spend, SpendRequest};
+use crate::state::AppState;
+
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct SendEncryptedRequest {
+    /// Compressed or x-only secp256k1 public key of the recipient
+    pub recipient_pubkey: String,
+    #[serde(flatten)]
+    pub spend: SpendRequest,
+}
+
+#[derive(Debug, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct SendEncryptedResponse {
+    pub operation: OperationId,
+    pub envelope: Envelope,
+    pub amount_msat: Amount,
+    pub change_msat: Amount,
+}
+
+async fn _send_encrypted(
+    client: ClientArc,
+    req: SendEncryptedRequest,
+) -> Result<SendEncryptedResponse, AppError> {
+    let recipient = parse_pubkey(&req.recipient_pubkey)?;
+    let sender_key = receiving_key(&client).await?;
+
+    let spend = spend(client.clone(), req.spend).await?;
+    let envelope = match seal(&sender_key, &recipient, &spend.notes.to_string()) {
+        Ok(envelope) => envelope,
+        Err(e) => {
+            // Nobody has seen the notes yet, take them back right away
+            client
+                .get_first_module::<MintClientModule>()
+                .try_cancel_spend_notes(spend.operation)
+                .await;
+            return Err(e);
+        }
+    };
+    info!(
+        "Sealed spend {} of {} for {}",
+        spend.operation, spend.amount_msat, recipient
+    );
+
+    Ok(SendEncryptedResponse {
+        operation: spend.operation,
+        envelope,
+        amount_msat: spend.amount_msat,
+        change_msat: spend.change_msat,
+    })
+}
+
+pub async fn handle_ws(state: AppState, v: Value) -> Result<Value, AppError> {
+    let v = serde_json::from_value::<SendEncryptedRequest>(v)
+        .map_err(|e| AppError::new(StatusCode::BAD_REQUEST, anyhow!("Invalid request: {}", e)))?;
+    let client = state.get_client(v.spend.federation_id).await?;
+    let sent = _send_encrypted(client, v).await?;
+    let sent_json = json!(sent);
+    Ok(sent_json)
+}
+
+#[axum_macros::debug_handler]
+pub async fn handle_rest(
+    State(state): State<AppState>,
+    Json(req): Json<SendEncryptedRequest>,
+) -> Result<Json<SendEncryptedResponse>, AppError> {
+    let client = state.get_client(req.spend.federation_id).await?;
+    let sent = _send_encrypted(client, req).await?;
+    Ok(Json(sent))
+}
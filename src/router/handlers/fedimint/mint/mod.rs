@@ -1,5 +1,14 @@
+pub mod await_reissue;
 pub mod combine;
+pub mod consolidate;
+pub mod convert;
+pub mod encrypted;
+pub mod reclaim;
 pub mod reissue;
+pub mod reissue_batch;
 pub mod spend;
+pub mod spends;
 pub mod split;
 pub mod validate;
+pub mod validate_offline;
+pub mod vouchers;
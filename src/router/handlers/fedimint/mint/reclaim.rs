@@ -0,0 +1,107 @@
+use anyhow::anyhow;
+use axum::extract::State;
+use axum::http::StatusCode;
+use axum::Json;
+use fedimint_client::ClientArc;
+use fedimint_core::config::FederationId;
+use fedimint_core::core::OperationId;
+use fedimint_core::Amount;
+use fedimint_mint_client::{MintClientModule, SpendOOBState};
+use futures_util::StreamExt;
+use serde::{Deserialize, Serialize};
+use serde_json::{json, Value};
+use tracing::info;
+
+use super::spends::{is_outstanding, spend_state, spent_notes};
+use crate::error::AppError;
+use crate::state::AppState;
+
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct ReclaimRequest {
+    pub operation_id: OperationId,
+    pub federation_id: Option<FederationId>,
+}
+
+#[derive(Debug, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct ReclaimResponse {
+    pub operation_id: OperationId,
+    pub state: SpendOOBState,
+    /// Amount returned to the wallet, zero if the recipient redeemed the
+    /// notes first
+    pub amount_msat: Amount,
+}
+
+async fn _reclaim(client: ClientArc, req: ReclaimRequest) -> Result<ReclaimResponse, AppError> {
+    reclaim_spend(&client, req.operation_id).await
+}
+
+/// Cancels an outstanding out-of-band spend and waits for the outcome, or
+/// returns the outcome of a completed spend
+pub async fn reclaim_spend(
+    client: &ClientArc,
+    operation_id: OperationId,
+) -> Result<ReclaimResponse, AppError> {
+    let not_a_spend = || {
+        AppError::new(
+            StatusCode::BAD_REQUEST,
+            anyhow!("Operation {} is not an out-of-band spend", operation_id),
+        )
+    };
+    let entry = client
+        .operation_log()
+        .get_operation(operation_id)
+        .await
+        .ok_or_else(not_a_spend)?;
+    let notes = spent_notes(&entry).ok_or_else(not_a_spend)?;
+
+    let mut state = spend_state(client, operation_id, &entry);
+    if is_outstanding(&state) {
+        let mint_module = client.get_first_module::<MintClientModule>();
+        if state == SpendOOBState::Created {
+            info!("Reclaiming out-of-band spend {operation_id}");
+            mint_module.try_cancel_spend_notes(operation_id).await;
+        }
+        let mut updates = mint_module
+            .subscribe_spend_notes(operation_id)
+            .await?
+            .into_stream();
+        while let Some(update) = updates.next().await {
+            info!("Update: {update:?}");
+            state = update;
+            if !is_outstanding(&state) {
+                break;
+            }
+        }
+    }
+
+    let amount_msat = match state {
+        SpendOOBState::UserCanceledSuccess | SpendOOBState::Refunded => notes.total_amount(),
+        _ => Amount::ZERO,
+    };
+    Ok(ReclaimResponse {
+        operation_id,
+        state,
+        amount_msat,
+    })
+}
+
+pub async fn handle_ws(state: AppState, v: Value) -> Result<Value, AppError> {
+    let v = serde_json::from_value::<ReclaimRequest>(v)
+        .map_err(|e| AppError::new(StatusCode::BAD_REQUEST, anyhow!("Invalid request: {}", e)))?;
+    let client = state.get_client(v.federation_id).await?;
+    let reclaim = _reclaim(client, v).await?;
+    let reclaim_json = json!(reclaim);
+    Ok(reclaim_json)
+}
+
+#[axum_macros::debug_handler]
+pub async fn handle_rest(
+    State(state): State<AppState>,
+    Json(req): Json<ReclaimRequest>,
+) -> Result<Json<ReclaimResponse>, AppError> {
+    let client = state.get_client(req.federation_id).await?;
+    let reclaim = _reclaim(client, req).await?;
+    Ok(Json(reclaim))
+}
@@ -3,59 +3,61 @@ use axum::extract::State;
 use axum::http::StatusCode;
 use axum::Json;
 use fedimint_client::ClientArc;
+use fedimint_core::core::OperationId;
 use fedimint_core::Amount;
-use fedimint_mint_client::{MintClientModule, OOBNotes};
-use futures_util::StreamExt;
+use fedimint_mint_client::OOBNotes;
 use serde::{Deserialize, Serialize};
 use serde_json::{json, Value};
-use tracing::info;
 
 use crate::error::AppError;
+use crate::router::handlers::cashu::swap::{reissue_notes, reissue_notes_in_background};
+use crate::router::handlers::cashu::token::Ecash;
 use crate::state::AppState;
 
 #[derive(Debug, Deserialize)]
 #[serde(rename_all = "camelCase")]
 pub struct ReissueRequest {
-    pub notes: OOBNotes,
+    pub notes: Ecash,
+    /// Return as soon as the reissue is submitted, await it with
+    /// `await-reissue`
+    #[serde(default)]
+    pub no_wait: bool,
 }
 
 #[derive(Debug, Serialize)]
 #[serde(rename_all = "camelCase")]
 pub struct ReissueResponse {
+    pub operation_id: OperationId,
     pub amount_msat: Amount,
 }
 
-async fn _reissue(client: ClientArc, req: ReissueRequest) -> Result<ReissueResponse, AppError> {
-    let amount_msat = req.notes.total_amount();
+async fn _reissue(
+    client: ClientArc,
+    notes: OOBNotes,
+    no_wait: bool,
+) -> Result<ReissueResponse, AppError> {
+    let amount_msat = notes.total_amount();
 
-    let mint = client.get_first_module::<MintClientModule>();
+    let operation_id = if no_wait {
+        reissue_notes_in_background(&client, notes).await?
+    } else {
+        reissue_notes(&client, notes).await?
+    };
 
-    let operation_id = mint.reissue_external_notes(req.notes, ()).await?;
-    let mut updates = mint
-        .subscribe_reissue_external_notes(operation_id)
-        .await
-        .unwrap()
-        .into_stream();
-
-    while let Some(update) = updates.next().await {
-        let update_clone = update.clone();
-        if let fedimint_mint_client::ReissueExternalNotesState::Failed(e) = update {
-            Err(AppError::new(StatusCode::INTERNAL_SERVER_ERROR, anyhow!(e)))?;
-        }
-
-        info!("Update: {update_clone:?}");
-    }
-
-    Ok(ReissueResponse { amount_msat })
+    Ok(ReissueResponse {
+        operation_id,
+        amount_msat,
+    })
 }
 
 pub async fn handle_ws(state: AppState, v: Value) -> Result<Value, AppError> {
     let v = serde_json::from_value::<ReissueRequest>(v)
         .map_err(|e| AppError::new(StatusCode::BAD_REQUEST, anyhow!("Invalid request: {}", e)))?;
+    let notes = v.notes.to_oob_notes(&state).await?;
     let client = state
-        .get_client_by_prefix(&v.notes.federation_id_prefix())
+        .get_client_by_prefix(&notes.federation_id_prefix())
         .await?;
-    let reissue = _reissue(client, v).await?;
+    let reissue = _reissue(client, notes, v.no_wait).await?;
     let reissue_json = json!(reissue);
     Ok(reissue_json)
 }
@@ -65,9 +67,10 @@ pub async fn handle_rest(
     State(state): State<AppState>,
     Json(req): Json<ReissueRequest>,
 ) -> Result<Json<ReissueResponse>, AppError> {
+    let notes = req.notes.to_oob_notes(&state).await?;
     let client = state
-        .get_client_by_prefix(&req.notes.federation_id_prefix())
+        .get_client_by_prefix(&notes.federation_id_prefix())
         .await?;
-    let reissue = _reissue(client, req).await?;
+    let reissue = _reissue(client, notes, req.no_wait).await?;
     Ok(Json(reissue))
 }
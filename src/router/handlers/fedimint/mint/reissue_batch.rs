@@ -0,0 +1,158 @@
+use std::collections::HashSet;
+
+use anyhow::anyhow;
+use axum::extract::State;
+use axum::http::StatusCode;
+use axum::Json;
+use fedimint_core::core::OperationId;
+use fedimint_core::Amount;
+use fedimint_mint_client::OOBNotes;
+use futures_util::future::join_all;
+use serde::{Deserialize, Serialize};
+use serde_json::{json, Value};
+
+use crate::error::AppError;
+use crate::router::handlers::cashu::check::notes_ys;
+use crate::router::handlers::cashu::swap::{reissue_notes, reissue_notes_in_background};
+use crate::router::handlers::cashu::token::Ecash;
+use crate::state::AppState;
+
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct ReissueBatchRequest {
+    /// Bundles of notes, each may belong to a different joined federation
+    pub notes: Vec<Ecash>,
+    /// Return as soon as the reissues are submitted, await each with
+    /// `await-reissue`
+    #[serde(default)]
+    pub no_wait: bool,
+}
+
+#[derive(Debug, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct ReissueBatchResult {
+    pub success: bool,
+    pub amount_msat: Amount,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub operation_id: Option<OperationId>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub error: Option<String>,
+}
+
+#[derive(Debug, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct ReissueBatchResponse {
+    /// One result per bundle, in the order of the request
+    pub results: Vec<ReissueBatchResult>,
+    pub amount_msat: Amount,
+}
+
+async fn _reissue_batch(
+    state: &AppState,
+    req: ReissueBatchRequest,
+) -> Result<ReissueBatchResponse, AppError> {
+    // Resolve every bundle and reject the ones repeating notes, of their own or
+    // of an earlier bundle, before anything is submitted
+    let mut seen = HashSet::new();
+    let mut bundles = Vec::with_capacity(req.notes.len());
+    for ecash in &req.notes {
+        let notes = match ecash.to_oob_notes(state).await {
+            Ok(notes) => notes,
+            Err(e) => {
+                bundles.push(Err((Amount::ZERO, e.error.to_string())));
+                continue;
+            }
+        };
+        let ys = match notes_ys(&notes) {
+            Ok(ys) => ys,
+            Err(e) => {
+                bundles.push(Err((notes.total_amount(), e.error.to_string())));
+                continue;
+            }
+        };
+        let unique = ys.iter().collect::<HashSet<_>>();
+        let error = if unique.len() != ys.len() {
+            Some("Bundle repeats one of its notes")
+        } else if unique.iter().any(|y| seen.contains(*y)) {
+            Some("Bundle repeats notes of an earlier bundle")
+        } else {
+            None
+        };
+        if let Some(error) = error {
+            bundles.push(Err((notes.total_amount(), error.to_string())));
+            continue;
+        }
+        seen.extend(ys);
+        bundles.push(Ok(notes));
+    }
+
+    let results = join_all(bundles.into_iter().map(|bundle| async {
+        match bundle {
+            Ok(notes) => reissue_bundle(state, notes, req.no_wait).await,
+            Err((amount_msat, error)) => ReissueBatchResult {
+                success: false,
+                amount_msat,
+                operation_id: None,
+                error: Some(error),
+            },
+        }
+    }))
+    .await;
+    let amount_msat = results
+        .iter()
+        .filter(|result| result.success)
+        .map(|result| result.amount_msat)
+        .sum();
+
+    Ok(ReissueBatchResponse {
+        results,
+        amount_msat,
+    })
+}
+
+async fn reissue_bundle(state: &AppState, notes: OOBNotes, no_wait: bool) -> ReissueBatchResult {
+    let amount_msat = notes.total_amount();
+    let res = async {
+        let client = state
+            .get_client_by_prefix(&notes.federation_id_prefix())
+            .await?;
+        if no_wait {
+            reissue_notes_in_background(&client, notes).await
+        } else {
+            reissue_notes(&client, notes).await
+        }
+    }
+    .await;
+
+    match res {
+        Ok(operation_id) => ReissueBatchResult {
+            success: true,
+            amount_msat,
+            operation_id: Some(operation_id),
+            error: None,
+        },
+        Err(e) => ReissueBatchResult {
+            success: false,
+            amount_msat,
+            operation_id: None,
+            error: Some(e.error.to_string()),
+        },
+    }
+}
+
+pub async fn handle_ws(state: AppState, v: Value) -> Result<Value, AppError> {
+    let v = serde_json::from_value::<ReissueBatchRequest>(v)
+        .map_err(|e| AppError::new(StatusCode::BAD_REQUEST, anyhow!("Invalid request: {}", e)))?;
+    let reissue_batch = _reissue_batch(&state, v).await?;
+    let reissue_batch_json = json!(reissue_batch);
+    Ok(reissue_batch_json)
+}
+
+#[axum_macros::debug_handler]
+pub async fn handle_rest(
+    State(state): State<AppState>,
+    Json(req): Json<ReissueBatchRequest>,
+) -> Result<Json<ReissueBatchResponse>, AppError> {
+    let reissue_batch = _reissue_batch(&state, req).await?;
+    Ok(Json(reissue_batch))
+}
@@ -14,12 +14,15 @@ use serde_json::{json, Value};
 use tracing::{info, warn};
 
 use crate::error::AppError;
+use crate::notes::{spend_exact_with_change, SpendMeta};
 use crate::state::AppState;
 
 #[derive(Debug, Deserialize)]
 #[serde(rename_all = "camelCase")]
 pub struct SpendRequest {
     pub amount_msat: Amount,
+    /// Hand out the smallest notes covering the amount instead of making
+    /// change to spend exactly `amountMsat`
     pub allow_overpay: bool,
     pub timeout: u64,
     pub federation_id: Option<FederationId>,
@@ -30,33 +33,53 @@ pub struct SpendRequest {
 pub struct SpendResponse {
     pub operation: OperationId,
     pub notes: OOBNotes,
+    /// Amount actually handed out, more than requested when overpaying
+    pub amount_msat: Amount,
+    /// Amount of the notes reissued to make change that stayed in the wallet
+    pub change_msat: Amount,
 }
 
-async fn _spend(client: ClientArc, req: SpendRequest) -> Result<SpendResponse, AppError> {
+/// Spends the requested amount out of band, returning the notes to hand to
+/// the recipient
+pub async fn spend(client: ClientArc, req: SpendRequest) -> Result<SpendResponse, AppError> {
     warn!("The client will try to double-spend these notes after the duration specified by the --timeout option to recover any unclaimed e-cash.");
 
-    let mint_module = client.get_first_module::<MintClientModule>();
     let timeout = Duration::from_secs(req.timeout);
-    let (operation, notes) = mint_module
-        .spend_notes_with_selector(&SelectNotesWithAtleastAmount, req.amount_msat, timeout, ())
-        .await?;
-
-    let overspend_amount = notes.total_amount() - req.amount_msat;
-    if overspend_amount != Amount::ZERO {
-        warn!(
-            "Selected notes {} worth more than requested",
-            overspend_amount
-        );
-    }
+    let (operation, notes, change_msat) = if req.allow_overpay {
+        let (operation, notes) = client
+            .get_first_module::<MintClientModule>()
+            .spend_notes_with_selector(
+                &SelectNotesWithAtleastAmount,
+                req.amount_msat,
+                timeout,
+                SpendMeta::new(timeout),
+            )
+            .await?;
+        let overspend_amount = notes.total_amount() - req.amount_msat;
+        if overspend_amount != Amount::ZERO {
+            warn!(
+                "Selected notes {} worth more than requested",
+                overspend_amount
+            );
+        }
+        (operation, notes, Amount::ZERO)
+    } else {
+        spend_exact_with_change(&client, req.amount_msat, timeout).await?
+    };
     info!("Spend e-cash operation: {operation}");
-    Ok(SpendResponse { operation, notes })
+    Ok(SpendResponse {
+        operation,
+        amount_msat: notes.total_amount(),
+        notes,
+        change_msat,
+    })
 }
 
 pub async fn handle_ws(state: AppState, v: Value) -> Result<Value, AppError> {
     let v = serde_json::from_value::<SpendRequest>(v)
         .map_err(|e| AppError::new(StatusCode::BAD_REQUEST, anyhow!("Invalid request: {}", e)))?;
     let client = state.get_client(v.federation_id).await?;
-    let spend = _spend(client, v).await?;
+    let spend = spend(client, v).await?;
     let spend_json = json!(spend);
     Ok(spend_json)
 }
@@ -67,6 +90,6 @@ pub async fn handle_rest(
     Json(req): Json<SpendRequest>,
 ) -> Result<Json<SpendResponse>, AppError> {
     let client = state.get_client(req.federation_id).await?;
-    let spend = _spend(client, req).await?;
+    let spend = spend(client, req).await?;
     Ok(Json(spend))
 }
@@ -0,0 +1,215 @@
+use anyhow::anyhow;
+use axum::extract::State;
+use axum::http::StatusCode;
+use axum::Json;
+use fedimint_client::oplog::OperationLogEntry;
+use fedimint_client::ClientArc;
+use fedimint_core::config::FederationId;
+use fedimint_core::core::OperationId;
+use fedimint_core::Amount;
+use fedimint_mint_client::{
+    MintClientModule, MintOperationMeta, MintOperationMetaVariant, OOBNotes, SpendOOBState, KIND,
+};
+use futures_util::StreamExt;
+use serde::{Deserialize, Serialize};
+use serde_json::{json, Value};
+
+use crate::error::AppError;
+use crate::notes::SpendMeta;
+use crate::router::handlers::cashu::check::{federation_notes, spent_nonces};
+use crate::state::AppState;
+use crate::utils::{spawn_follower, system_time_to_u64};
+
+/// Operations fetched from the operation log at once
+const OPERATION_LOG_PAGE_SIZE: usize = 100;
+
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct ListSpendsRequest {
+    /// Also list spends that were redeemed, reclaimed or refunded
+    #[serde(default)]
+    pub include_completed: bool,
+    pub federation_id: Option<FederationId>,
+}
+
+#[derive(Debug, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct Spend {
+    pub operation_id: OperationId,
+    pub amount_msat: Amount,
+    /// Unix timestamp of the spend
+    pub created_at: u64,
+    /// Unix timestamp after which unredeemed notes are reclaimed, unknown for
+    /// spends made by other clients of the wallet
+    pub deadline: Option<u64>,
+    pub state: SpendOOBState,
+    /// Whether the recipient redeemed the notes, outstanding spends are
+    /// checked against the federation
+    pub redeemed: bool,
+    pub notes: OOBNotes,
+}
+
+#[derive(Debug, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct ListSpendsResponse {
+    pub spends: Vec<Spend>,
+}
+
+async fn _spends(
+    client: ClientArc,
+    req: ListSpendsRequest,
+) -> Result<ListSpendsResponse, AppError> {
+    let mut spends = Vec::new();
+    let mut start_after = None;
+    loop {
+        let page = client
+            .operation_log()
+            .list_operations(OPERATION_LOG_PAGE_SIZE, start_after)
+            .await;
+        let Some((last, _)) = page.last() else {
+            break;
+        };
+        start_after = Some(*last);
+
+        for (key, entry) in page {
+            let Some(notes) = spent_notes(&entry) else {
+                continue;
+            };
+            let state = spend_state(&client, key.operation_id, &entry);
+            if !req.include_completed && !is_outstanding(&state) {
+                continue;
+            }
+            spends.push((key, SpendMeta::of(&entry), state, notes));
+        }
+    }
+
+    let redeemed = redemptions(
+        &client,
+        spends.iter().map(|(_, _, state, notes)| (state, notes)),
+    )
+    .await?;
+    let spends = spends
+        .into_iter()
+        .zip(redeemed)
+        .map(|((key, meta, state, notes), redeemed)| {
+            Ok(Spend {
+                operation_id: key.operation_id,
+                amount_msat: notes.total_amount(),
+                created_at: system_time_to_u64(key.creation_time)?,
+                deadline: meta.map(|meta| meta.deadline),
+                state,
+                redeemed,
+                notes,
+            })
+        })
+        .collect::<Result<_, AppError>>()?;
+
+    Ok(ListSpendsResponse { spends })
+}
+
+/// Returns the notes of an out-of-band spend operation. Spends of notes the
+/// server reissued into its own wallet are left out, they never reached a
+/// recipient.
+pub fn spent_notes(entry: &OperationLogEntry) -> Option<OOBNotes> {
+    if entry.operation_module_kind() != KIND.as_str()
+        || SpendMeta::of(entry).is_some_and(|meta| meta.internal)
+    {
+        return None;
+    }
+    match entry.meta::<MintOperationMeta>().variant {
+        MintOperationMetaVariant::SpendOOB { oob_notes, .. } => Some(oob_notes),
+        MintOperationMetaVariant::Reissuance { .. } => None,
+    }
+}
+
+/// Current state of an out-of-band spend, as cached in the operation log once
+/// the spend completed. Outstanding spends are followed in the background
+/// until they complete, and are reported as created until then.
+pub fn spend_state(
+    client: &ClientArc,
+    operation_id: OperationId,
+    entry: &OperationLogEntry,
+) -> SpendOOBState {
+    if let Some(state) = entry.outcome::<SpendOOBState>() {
+        return state;
+    }
+    follow_spend(client, operation_id);
+    SpendOOBState::Created
+}
+
+/// Drains the updates of a spend in the background, which caches its outcome
+/// in the operation log
+fn follow_spend(client: &ClientArc, operation_id: OperationId) {
+    let client = client.clone();
+    spawn_follower(operation_id, async move {
+        let mut updates = client
+            .get_first_module::<MintClientModule>()
+            .subscribe_spend_notes(operation_id)
+            .await?
+            .into_stream();
+        while updates.next().await.is_some() {}
+        Ok(())
+    });
+}
+
+pub fn is_outstanding(state: &SpendOOBState) -> bool {
+    matches!(
+        state,
+        SpendOOBState::Created | SpendOOBState::UserCanceledProcessing
+    )
+}
+
+/// Whether the recipients redeemed the notes of each spend. The notes of all
+/// outstanding spends are checked against the federation's spent notes at
+/// once.
+pub async fn redemptions<'a>(
+    client: &ClientArc,
+    spends: impl Iterator<Item = (&'a SpendOOBState, &'a OOBNotes)>,
+) -> Result<Vec<bool>, AppError> {
+    let spends = spends.collect::<Vec<_>>();
+    let outstanding = spends
+        .iter()
+        .filter(|(state, _)| completed_redemption(state).is_none())
+        .flat_map(|(_, notes)| federation_notes(notes))
+        .collect();
+    let spent = spent_nonces(client, outstanding).await?;
+    Ok(spends
+        .into_iter()
+        .map(|(state, notes)| {
+            completed_redemption(state).unwrap_or_else(|| {
+                federation_notes(notes)
+                    .iter()
+                    .any(|(_, note)| spent.contains(&note.nonce))
+            })
+        })
+        .collect())
+}
+
+/// Whether the recipient redeemed the notes of a completed spend, `None`
+/// while the spend is outstanding
+fn completed_redemption(state: &SpendOOBState) -> Option<bool> {
+    match state {
+        SpendOOBState::Success | SpendOOBState::UserCanceledFailure => Some(true),
+        SpendOOBState::Refunded | SpendOOBState::UserCanceledSuccess => Some(false),
+        _ => None,
+    }
+}
+
+pub async fn handle_ws(state: AppState, v: Value) -> Result<Value, AppError> {
+    let v = serde_json::from_value::<ListSpendsRequest>(v)
+        .map_err(|e| AppError::new(StatusCode::BAD_REQUEST, anyhow!("Invalid request: {}", e)))?;
+    let client = state.get_client(v.federation_id).await?;
+    let spends = _spends(client, v).await?;
+    let spends_json = json!(spends);
+    Ok(spends_json)
+}
+
+#[axum_macros::debug_handler]
+pub async fn handle_rest(
+    State(state): State<AppState>,
+    Json(req): Json<ListSpendsRequest>,
+) -> Result<Json<ListSpendsResponse>, AppError> {
+    let client = state.get_client(req.federation_id).await?;
+    let spends = _spends(client, req).await?;
+    Ok(Json(spends))
+}
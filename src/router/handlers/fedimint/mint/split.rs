@@ -1,8 +1,11 @@
 use std::collections::BTreeMap;
 
+use anyhow::anyhow;
+use axum::http::StatusCode;
 use axum::Json;
+use fedimint_core::config::FederationIdPrefix;
 use fedimint_core::{Amount, TieredMulti};
-use fedimint_mint_client::OOBNotes;
+use fedimint_mint_client::{OOBNotes, SpendableNote};
 use serde::{Deserialize, Serialize};
 use serde_json::{json, Value};
 
@@ -12,12 +15,22 @@ use crate::error::AppError;
 #[serde(rename_all = "camelCase")]
 pub struct SplitRequest {
     pub notes: OOBNotes,
+    /// Amounts to package the notes into, in msat
+    #[serde(default)]
+    pub amounts: Vec<Amount>,
 }
 
 #[derive(Debug, Serialize)]
 #[serde(rename_all = "camelCase")]
 pub struct SplitResponse {
-    pub notes: BTreeMap<Amount, OOBNotes>,
+    /// Every note as its own `OOBNotes`, grouped by denomination
+    pub notes: BTreeMap<Amount, Vec<OOBNotes>>,
+    /// One bundle per requested amount, in the order of the request
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub bundles: Vec<OOBNotes>,
+    /// Notes left over after packaging the requested amounts
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub remainder: Option<OOBNotes>,
 }
 
 async fn _split(req: SplitRequest) -> Result<SplitResponse, AppError> {
@@ -29,23 +42,84 @@ async fn _split(req: SplitRequest) -> Result<SplitResponse, AppError> {
         .map(|(amount, notes)| {
             let notes = notes
                 .iter()
-                .map(|note| {
-                    OOBNotes::new(
-                        federation,
-                        TieredMulti::new(vec![(*amount, vec![*note])].into_iter().collect()),
-                    )
-                })
+                .map(|note| bundle(federation, vec![(*amount, *note)]))
                 .collect::<Vec<_>>();
-            (*amount, notes[0].clone()) // clone the amount and return a single
-                                        // OOBNotes
+            (*amount, notes)
         })
         .collect::<BTreeMap<_, _>>();
 
-    Ok(SplitResponse { notes })
+    let mut bundles = Vec::new();
+    let mut remainder = None;
+    if !req.amounts.is_empty() {
+        let mut pool = req
+            .notes
+            .notes()
+            .iter_items()
+            .map(|(amount, note)| (amount, *note))
+            .collect();
+        // Package the largest amounts first, they need the fewest options
+        let mut order = (0..req.amounts.len()).collect::<Vec<_>>();
+        order.sort_by_key(|index| std::cmp::Reverse(req.amounts[*index]));
+        let mut packaged = vec![None; req.amounts.len()];
+        for index in order {
+            let amount = req.amounts[index];
+            let selected = take_exact(&mut pool, amount).ok_or_else(|| {
+                AppError::new(
+                    StatusCode::BAD_REQUEST,
+                    anyhow!("The denominations of the notes cannot make up {}", amount),
+                )
+            })?;
+            packaged[index] = Some(bundle(federation, selected));
+        }
+        bundles = packaged.into_iter().flatten().collect();
+        if !pool.is_empty() {
+            remainder = Some(bundle(federation, pool));
+        }
+    }
+
+    Ok(SplitResponse {
+        notes,
+        bundles,
+        remainder,
+    })
+}
+
+/// Removes notes adding up to exactly `amount` from the pool, taking the
+/// largest fitting denominations first. Leaves the pool untouched if the
+/// amount cannot be made up.
+fn take_exact(
+    pool: &mut Vec<(Amount, SpendableNote)>,
+    amount: Amount,
+) -> Option<Vec<(Amount, SpendableNote)>> {
+    pool.sort_by(|(a, _), (b, _)| b.cmp(a));
+    let mut pending = amount;
+    let mut selected = Vec::new();
+    for (index, (note_amount, _)) in pool.iter().enumerate() {
+        if *note_amount <= pending {
+            pending -= *note_amount;
+            selected.push(index);
+        }
+    }
+    if pending != Amount::ZERO || amount == Amount::ZERO {
+        return None;
+    }
+
+    Some(
+        selected
+            .into_iter()
+            .rev()
+            .map(|index| pool.remove(index))
+            .collect(),
+    )
+}
+
+fn bundle(federation: FederationIdPrefix, notes: Vec<(Amount, SpendableNote)>) -> OOBNotes {
+    OOBNotes::new(federation, notes.into_iter().collect::<TieredMulti<_>>())
 }
 
 pub async fn handle_ws(v: Value) -> Result<Value, AppError> {
-    let v = serde_json::from_value(v).unwrap();
+    let v = serde_json::from_value::<SplitRequest>(v)
+        .map_err(|e| AppError::new(StatusCode::BAD_REQUEST, anyhow!("Invalid request: {}", e)))?;
     let split = _split(v).await?;
     let split_json = json!(split);
     Ok(split_json)
@@ -9,27 +9,48 @@ use serde::{Deserialize, Serialize};
 use serde_json::{json, Value};
 
 use crate::error::AppError;
+use crate::router::handlers::cashu::check::{
+    notes_ys, proof_states, record_notes, ProofStateResponse,
+};
 use crate::state::AppState;
 
 #[derive(Debug, Deserialize)]
 #[serde(rename_all = "camelCase")]
 pub struct ValidateRequest {
     pub notes: OOBNotes,
+    /// Also report whether each note has been spent
+    #[serde(default)]
+    pub check_spent: bool,
 }
 
 #[derive(Debug, Serialize)]
 #[serde(rename_all = "camelCase")]
 pub struct ValidateResponse {
     pub amount_msat: Amount,
+    pub states: Option<Vec<ProofStateResponse>>,
 }
 
-async fn _validate(client: ClientArc, req: ValidateRequest) -> Result<ValidateResponse, AppError> {
+async fn _validate(
+    state: &AppState,
+    client: ClientArc,
+    req: ValidateRequest,
+) -> Result<ValidateResponse, AppError> {
     let amount_msat = client
         .get_first_module::<MintClientModule>()
-        .validate_notes(req.notes)
+        .validate_notes(req.notes.clone())
         .await?;
 
-    Ok(ValidateResponse { amount_msat })
+    let states = if req.check_spent {
+        record_notes(&client, &req.notes).await?;
+        Some(proof_states(&state.multimint.all().await, &notes_ys(&req.notes)?).await?)
+    } else {
+        None
+    };
+
+    Ok(ValidateResponse {
+        amount_msat,
+        states,
+    })
 }
 
 pub async fn handle_ws(state: AppState, v: Value) -> Result<Value, AppError> {
@@ -38,7 +59,7 @@ pub async fn handle_ws(state: AppState, v: Value) -> Result<Value, AppError> {
     let client = state
         .get_client_by_prefix(&v.notes.federation_id_prefix())
         .await?;
-    let validate = _validate(client, v).await?;
+    let validate = _validate(&state, client, v).await?;
     let validate_json = json!(validate);
     Ok(validate_json)
 }
@@ -51,6 +72,6 @@ pub async fn handle_rest(
     let client = state
         .get_client_by_prefix(&req.notes.federation_id_prefix())
         .await?;
-    let validate = _validate(client, req).await?;
+    let validate = _validate(&state, client, req).await?;
     Ok(Json(validate))
 }
@@ -0,0 +1,250 @@
+use std::collections::HashMap;
+use std::sync::Arc;
+use std::time::Duration;
+
+use anyhow::anyhow;
+use axum::extract::State;
+use axum::http::StatusCode;
+use axum::Json;
+use fedimint_core::api::{GlobalFederationApi, InviteCode, WsFederationApi};
+use fedimint_core::config::{ClientConfig, FederationId};
+use fedimint_core::module::registry::ModuleDecoderRegistry;
+use fedimint_core::module::CommonModuleInit;
+use fedimint_core::util::SafeUrl;
+use fedimint_core::{Amount, PeerId};
+use fedimint_mint_client::common::config::MintClientConfig;
+use fedimint_mint_client::common::MintCommonInit;
+use fedimint_mint_client::OOBNotes;
+use serde::{Deserialize, Serialize};
+use serde_json::{json, Value};
+use tokio::sync::RwLock;
+use tracing::info;
+
+use crate::error::AppError;
+use crate::router::handlers::cashu::check::federation_note;
+use crate::state::AppState;
+
+/// How long to wait for the guardians to hand out the federation config
+const CONFIG_DOWNLOAD_TIMEOUT: Duration = Duration::from_secs(30);
+
+#[derive(Debug, Clone, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct Guardian {
+    pub peer_id: PeerId,
+    pub name: String,
+    pub url: SafeUrl,
+}
+
+/// The parts of a federation's config needed to verify its notes
+#[derive(Debug)]
+pub struct ForeignFederation {
+    pub federation_id: FederationId,
+    pub name: Option<String>,
+    pub guardians: Vec<Guardian>,
+    pub mint_config: MintClientConfig,
+}
+
+impl ForeignFederation {
+    fn from_config(federation_id: FederationId, config: &ClientConfig) -> Result<Self, AppError> {
+        let (_, mint_config) = config
+            .get_first_module_by_kind::<MintClientConfig>(fedimint_mint_client::KIND)
+            .map_err(|e| {
+                AppError::new(
+                    StatusCode::BAD_REQUEST,
+                    anyhow!("Federation has no usable mint module: {}", e),
+                )
+            })?;
+        Ok(Self {
+            federation_id,
+            name: config.global.federation_name().map(str::to_string),
+            guardians: config
+                .global
+                .api_endpoints
+                .iter()
+                .map(|(peer_id, peer)| Guardian {
+                    peer_id: *peer_id,
+                    name: peer.name.clone(),
+                    url: peer.url.clone(),
+                })
+                .collect(),
+            mint_config: mint_config.clone(),
+        })
+    }
+}
+
+/// Configs of federations fetched to verify their notes, kept in memory only
+#[derive(Debug, Clone, Default)]
+pub struct ForeignFederations(Arc<RwLock<HashMap<FederationId, Arc<ForeignFederation>>>>);
+
+impl ForeignFederations {
+    async fn get(&self, notes: &OOBNotes) -> Option<Arc<ForeignFederation>> {
+        self.0
+            .read()
+            .await
+            .values()
+            .find(|federation| federation.federation_id.to_prefix() == notes.federation_id_prefix())
+            .cloned()
+    }
+
+    async fn fetch(&self, invite_code: &InviteCode) -> Result<Arc<ForeignFederation>, AppError> {
+        let federation_id = invite_code.federation_id();
+        if let Some(federation) = self.0.read().await.get(&federation_id) {
+            return Ok(federation.clone());
+        }
+
+        let api = WsFederationApi::from_invite_code(std::slice::from_ref(invite_code));
+        let config = tokio::time::timeout(
+            CONFIG_DOWNLOAD_TIMEOUT,
+            api.download_client_config(invite_code),
+        )
+        .await
+        .map_err(|_| {
+            AppError::new(
+                StatusCode::GATEWAY_TIMEOUT,
+                anyhow!(
+                    "Timed out fetching the config of federation {}",
+                    federation_id
+                ),
+            )
+        })?
+        .map_err(|e| {
+            AppError::new(
+                StatusCode::BAD_GATEWAY,
+                anyhow!(
+                    "Failed to fetch the config of federation {}: {}",
+                    federation_id,
+                    e
+                ),
+            )
+        })?;
+        let decoders = mint_decoders(&config);
+        let config = config.redecode_raw(&decoders)?;
+        let federation = Arc::new(ForeignFederation::from_config(federation_id, &config)?);
+        info!("Cached the mint keys of federation {federation_id}");
+
+        self.0
+            .write()
+            .await
+            .insert(federation_id, federation.clone());
+        Ok(federation)
+    }
+}
+
+/// Decoders for the mint modules of a downloaded config, which arrives with
+/// its module configs undecoded
+fn mint_decoders(config: &ClientConfig) -> ModuleDecoderRegistry {
+    ModuleDecoderRegistry::new(
+        config
+            .modules
+            .iter()
+            .filter(|(_, module)| module.kind == fedimint_mint_client::KIND)
+            .map(|(instance_id, _)| {
+                (
+                    *instance_id,
+                    fedimint_mint_client::KIND,
+                    MintCommonInit::decoder(),
+                )
+            }),
+    )
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct ValidateOfflineRequest {
+    pub notes: OOBNotes,
+    /// Invite code of the notes' federation, only needed the first time
+    /// notes of a federation we have not joined are verified
+    pub invite_code: Option<InviteCode>,
+}
+
+#[derive(Debug, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct ValidateOfflineResponse {
+    pub federation_id: FederationId,
+    pub federation_name: Option<String>,
+    pub guardians: Vec<Guardian>,
+    /// Whether every note carries a valid federation signature
+    pub valid: bool,
+    pub amount_msat: Amount,
+    /// Why notes failed verification, by their position in the notes
+    pub errors: Vec<String>,
+}
+
+async fn resolve_federation(
+    state: &AppState,
+    req: &ValidateOfflineRequest,
+) -> Result<Arc<ForeignFederation>, AppError> {
+    if let Some(invite_code) = &req.invite_code {
+        if invite_code.federation_id().to_prefix() != req.notes.federation_id_prefix() {
+            return Err(AppError::new(
+                StatusCode::BAD_REQUEST,
+                anyhow!("Notes were not issued by the invite code's federation"),
+            ));
+        }
+        return state.foreign_federations.fetch(invite_code).await;
+    }
+
+    if let Some(federation) = state.foreign_federations.get(&req.notes).await {
+        return Ok(federation);
+    }
+    // Joined federations' configs are already at hand
+    if let Ok(client) = state
+        .get_client_by_prefix(&req.notes.federation_id_prefix())
+        .await
+    {
+        return Ok(Arc::new(ForeignFederation::from_config(
+            client.federation_id(),
+            client.get_config(),
+        )?));
+    }
+
+    Err(AppError::new(
+        StatusCode::BAD_REQUEST,
+        anyhow!(
+            "Unknown federation {:?}, an invite code is needed to verify its notes",
+            req.notes.federation_id_prefix()
+        ),
+    ))
+}
+
+async fn _validate_offline(
+    state: &AppState,
+    req: ValidateOfflineRequest,
+) -> Result<ValidateOfflineResponse, AppError> {
+    let federation = resolve_federation(state, &req).await?;
+
+    let mut errors = Vec::new();
+    for (idx, (amount, note)) in req.notes.notes().iter_items().enumerate() {
+        match federation.mint_config.tbs_pks.get(amount) {
+            Some(pk) if federation_note(note).verify(*pk) => {}
+            Some(_) => errors.push(format!("Note {idx} has an invalid signature")),
+            None => errors.push(format!("Note {idx} uses an invalid amount tier {amount}")),
+        }
+    }
+
+    Ok(ValidateOfflineResponse {
+        federation_id: federation.federation_id,
+        federation_name: federation.name.clone(),
+        guardians: federation.guardians.clone(),
+        valid: errors.is_empty(),
+        amount_msat: req.notes.total_amount(),
+        errors,
+    })
+}
+
+pub async fn handle_ws(state: AppState, v: Value) -> Result<Value, AppError> {
+    let v = serde_json::from_value::<ValidateOfflineRequest>(v)
+        .map_err(|e| AppError::new(StatusCode::BAD_REQUEST, anyhow!("Invalid request: {}", e)))?;
+    let validate = _validate_offline(&state, v).await?;
+    let validate_json = json!(validate);
+    Ok(validate_json)
+}
+
+#[axum_macros::debug_handler]
+pub async fn handle_rest(
+    State(state): State<AppState>,
+    Json(req): Json<ValidateOfflineRequest>,
+) -> Result<Json<ValidateOfflineResponse>, AppError> {
+    let validate = _validate_offline(&state, req).await?;
+    Ok(Json(validate))
+}
@@ -0,0 +1,201 @@
+use std::time::{Duration, SystemTime};
+
+use anyhow::anyhow;
+use axum::extract::State;
+use axum::http::StatusCode;
+use axum::Json;
+use fedimint_client::ClientArc;
+use fedimint_core::config::FederationId;
+use fedimint_core::core::OperationId;
+use fedimint_core::db::IDatabaseTransactionOpsCoreTyped;
+use fedimint_core::Amount;
+use fedimint_mint_client::{MintClientModule, OOBNotes};
+use qrcode::render::svg;
+use qrcode::QrCode;
+use serde::{Deserialize, Serialize};
+use serde_json::{json, Value};
+use tracing::info;
+
+use crate::db::{VoucherBatch, VoucherBatchKey};
+use crate::error::AppError;
+use crate::notes::{spend_exact_with_change, SpendMeta};
+use crate::state::AppState;
+use crate::utils::system_time_to_u64;
+
+/// Vouchers that can be created in one batch
+const MAX_VOUCHERS_PER_BATCH: usize = 1000;
+
+const QR_CODE_MIN_SIZE: u32 = 256;
+
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct CreateVouchersRequest {
+    pub count: usize,
+    /// Value of each voucher
+    pub amount_msat: Amount,
+    /// Seconds after which unredeemed vouchers can be reclaimed
+    pub timeout: u64,
+    pub description: Option<String>,
+    /// Render every voucher as an SVG QR code
+    #[serde(default)]
+    pub qr: bool,
+    pub federation_id: Option<FederationId>,
+}
+
+#[derive(Debug, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct Voucher {
+    pub operation_id: OperationId,
+    pub notes: OOBNotes,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub qr_svg: Option<String>,
+}
+
+#[derive(Debug, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct CreateVouchersResponse {
+    pub batch_id: OperationId,
+    pub deadline: u64,
+    pub total_msat: Amount,
+    pub vouchers: Vec<Voucher>,
+}
+
+async fn _create_vouchers(
+    client: ClientArc,
+    req: CreateVouchersRequest,
+) -> Result<CreateVouchersResponse, AppError> {
+    if req.count == 0 || req.count > MAX_VOUCHERS_PER_BATCH {
+        return Err(AppError::new(
+            StatusCode::BAD_REQUEST,
+            anyhow!("A batch holds 1 to {} vouchers", MAX_VOUCHERS_PER_BATCH),
+        ));
+    }
+    if req.amount_msat == Amount::ZERO {
+        return Err(AppError::new(
+            StatusCode::BAD_REQUEST,
+            anyhow!("Vouchers need a value"),
+        ));
+    }
+
+    // The batch is persisted first and every voucher is added as soon as its
+    // spend exists, so no voucher goes untracked if creating the batch fails
+    let timeout = Duration::from_secs(req.timeout);
+    let batch_id = OperationId::new_random();
+    let batch = VoucherBatch {
+        amount: req.amount_msat,
+        description: req.description,
+        created_at: system_time_to_u64(SystemTime::now())?,
+        deadline: SpendMeta::new(timeout).deadline,
+        vouchers: Vec::with_capacity(req.count),
+    };
+    let mut dbtx = client.db().begin_transaction().await;
+    dbtx.insert_entry(&VoucherBatchKey(batch_id), &batch).await;
+    dbtx.commit_tx_result().await?;
+
+    let mut vouchers = Vec::with_capacity(req.count);
+    for _ in 0..req.count {
+        match create_voucher(&client, batch_id, req.amount_msat, timeout, req.qr).await {
+            Ok(voucher) => vouchers.push(voucher),
+            Err(e) => {
+                // Hand back the vouchers of the incomplete batch, they stay
+                // listed in it as reclaimed
+                let mint_module = client.get_first_module::<MintClientModule>();
+                for voucher in &vouchers {
+                    mint_module
+                        .try_cancel_spend_notes(voucher.operation_id)
+                        .await;
+                }
+                return Err(e);
+            }
+        }
+    }
+    info!(
+        "Created voucher batch {batch_id} of {} x {}",
+        req.count, req.amount_msat
+    );
+
+    Ok(CreateVouchersResponse {
+        batch_id,
+        deadline: batch.deadline,
+        total_msat: req.amount_msat * req.count as u64,
+        vouchers,
+    })
+}
+
+/// Spends the notes of a voucher and adds the spend to the batch
+async fn create_voucher(
+    client: &ClientArc,
+    batch_id: OperationId,
+    amount: Amount,
+    timeout: Duration,
+    qr: bool,
+) -> Result<Voucher, AppError> {
+    let (operation_id, notes, _) = spend_exact_with_change(client, amount, timeout).await?;
+    let res = async {
+        add_voucher(client, batch_id, operation_id).await?;
+        qr.then(|| render_qr(&notes)).transpose()
+    }
+    .await;
+    match res {
+        Ok(qr_svg) => Ok(Voucher {
+            operation_id,
+            notes,
+            qr_svg,
+        }),
+        Err(e) => {
+            client
+                .get_first_module::<MintClientModule>()
+                .try_cancel_spend_notes(operation_id)
+                .await;
+            Err(e)
+        }
+    }
+}
+
+async fn add_voucher(
+    client: &ClientArc,
+    batch_id: OperationId,
+    operation_id: OperationId,
+) -> Result<(), AppError> {
+    let mut dbtx = client.db().begin_transaction().await;
+    let mut batch = dbtx
+        .get_value(&VoucherBatchKey(batch_id))
+        .await
+        .ok_or_else(|| anyhow!("Voucher batch {} is missing", batch_id))?;
+    batch.vouchers.push(operation_id);
+    dbtx.insert_entry(&VoucherBatchKey(batch_id), &batch).await;
+    dbtx.commit_tx_result().await?;
+    Ok(())
+}
+
+fn render_qr(notes: &OOBNotes) -> Result<String, AppError> {
+    let code = QrCode::new(notes.to_string()).map_err(|e| {
+        AppError::new(
+            StatusCode::BAD_REQUEST,
+            anyhow!("Voucher does not fit in a QR code: {}", e),
+        )
+    })?;
+    Ok(code
+        .render::<svg::Color>()
+        .min_dimensions(QR_CODE_MIN_SIZE, QR_CODE_MIN_SIZE)
+        .build())
+}
+
+pub async fn handle_ws(state: AppState, v: Value) -> Result<Value, AppError> {
+    let v = serde_json::from_value::<CreateVouchersRequest>(v)
+        .map_err(|e| AppError::new(StatusCode::BAD_REQUEST, anyhow!("Invalid request: {}", e)))?;
+    let client = state.get_client(v.federation_id).await?;
+    let vouchers = _create_vouchers(client, v).await?;
+    let vouchers_json = json!(vouchers);
+    Ok(vouchers_json)
+}
+
+#[axum_macros::debug_handler]
+pub async fn handle_rest(
+    State(state): State<AppState>,
+    Json(req): Json<CreateVouchersRequest>,
+) -> Result<Json<CreateVouchersResponse>, AppError> {
+    let client = state.get_client(req.federation_id).await?;
+    let vouchers = _create_vouchers(client, req).await?;
+    Ok(Json(vouchers))
+}
@@ -0,0 +1,113 @@
+use anyhow::anyhow;
+use axum::http::StatusCode;
+use fedimint_client::ClientArc;
+use fedimint_core::core::OperationId;
+use fedimint_core::db::IDatabaseTransactionOpsCoreTyped;
+use fedimint_core::Amount;
+use fedimint_mint_client::SpendOOBState;
+use serde::Serialize;
+
+use super::spends::{is_outstanding, redemptions, spend_state, spent_notes};
+use crate::db::{VoucherBatch, VoucherBatchKey};
+use crate::error::AppError;
+
+pub mod create;
+pub mod reclaim;
+pub mod status;
+
+#[derive(Debug, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct VoucherStatus {
+    pub operation_id: OperationId,
+    pub state: SpendOOBState,
+    /// Whether the voucher was redeemed
+    pub redeemed: bool,
+}
+
+#[derive(Debug, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct VoucherTotals {
+    pub count: usize,
+    pub amount_msat: Amount,
+    pub redeemed: usize,
+    pub redeemed_msat: Amount,
+    pub outstanding: usize,
+    pub outstanding_msat: Amount,
+    pub reclaimed: usize,
+    pub reclaimed_msat: Amount,
+}
+
+impl VoucherTotals {
+    pub fn new(batch: &VoucherBatch, vouchers: &[VoucherStatus]) -> Self {
+        let (mut redeemed, mut outstanding, mut reclaimed) = (0, 0, 0);
+        for voucher in vouchers {
+            if voucher.redeemed {
+                redeemed += 1;
+            } else if is_outstanding(&voucher.state) {
+                outstanding += 1;
+            } else {
+                reclaimed += 1;
+            }
+        }
+        let value_of = |count: usize| batch.amount * count as u64;
+        Self {
+            count: vouchers.len(),
+            amount_msat: value_of(vouchers.len()),
+            redeemed,
+            redeemed_msat: value_of(redeemed),
+            outstanding,
+            outstanding_msat: value_of(outstanding),
+            reclaimed,
+            reclaimed_msat: value_of(reclaimed),
+        }
+    }
+}
+
+pub async fn get_batch(
+    client: &ClientArc,
+    batch_id: OperationId,
+) -> Result<VoucherBatch, AppError> {
+    client
+        .db()
+        .begin_transaction_nc()
+        .await
+        .get_value(&VoucherBatchKey(batch_id))
+        .await
+        .ok_or_else(|| {
+            AppError::new(
+                StatusCode::NOT_FOUND,
+                anyhow!("Voucher batch {} not found", batch_id),
+            )
+        })
+}
+
+/// Redemption status of each voucher, derived from the outcome of its spend
+/// or, while it is outstanding, from the federation's spent notes
+pub async fn voucher_statuses(
+    client: &ClientArc,
+    operation_ids: &[OperationId],
+) -> Result<Vec<VoucherStatus>, AppError> {
+    let mut spends = Vec::with_capacity(operation_ids.len());
+    for operation_id in operation_ids {
+        let entry = client
+            .operation_log()
+            .get_operation(*operation_id)
+            .await
+            .ok_or_else(|| anyhow!("Voucher operation {} is missing", operation_id))?;
+        let notes = spent_notes(&entry)
+            .ok_or_else(|| anyhow!("Voucher operation {} is not a spend", operation_id))?;
+        spends.push((spend_state(client, *operation_id, &entry), notes));
+    }
+
+    let redeemed = redemptions(client, spends.iter().map(|(state, notes)| (state, notes))).await?;
+    Ok(operation_ids
+        .iter()
+        .zip(spends)
+        .zip(redeemed)
+        .map(|((operation_id, (state, _)), redeemed)| VoucherStatus {
+            operation_id: *operation_id,
+            state,
+            redeemed,
+        })
+        .collect())
+}
@@ -0,0 +1,94 @@
+use std::time::SystemTime;
+
+use anyhow::anyhow;
+use axum::extract::State;
+use axum::http::StatusCode;
+use axum::Json;
+use fedimint_client::ClientArc;
+use fedimint_core::config::FederationId;
+use fedimint_core::core::OperationId;
+use fedimint_core::Amount;
+use futures_util::future::join_all;
+use serde::{Deserialize, Serialize};
+use serde_json::{json, Value};
+
+use super::{get_batch, voucher_statuses, VoucherTotals};
+use crate::error::AppError;
+use crate::router::handlers::fedimint::mint::reclaim::{reclaim_spend, ReclaimResponse};
+use crate::router::handlers::fedimint::mint::spends::is_outstanding;
+use crate::state::AppState;
+use crate::utils::system_time_to_u64;
+
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct ReclaimVouchersRequest {
+    pub batch_id: OperationId,
+    /// Reclaim before the batch's deadline
+    #[serde(default)]
+    pub force: bool,
+    pub federation_id: Option<FederationId>,
+}
+
+#[derive(Debug, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct ReclaimVouchersResponse {
+    pub batch_id: OperationId,
+    /// Outcome of every voucher that was still outstanding
+    pub reclaims: Vec<ReclaimResponse>,
+    pub reclaimed_msat: Amount,
+    pub totals: VoucherTotals,
+}
+
+async fn _reclaim_vouchers(
+    client: ClientArc,
+    req: ReclaimVouchersRequest,
+) -> Result<ReclaimVouchersResponse, AppError> {
+    let batch = get_batch(&client, req.batch_id).await?;
+    if !req.force && system_time_to_u64(SystemTime::now())? < batch.deadline {
+        return Err(AppError::new(
+            StatusCode::BAD_REQUEST,
+            anyhow!(
+                "Vouchers of batch {} can be reclaimed after {}, pass force to reclaim them now",
+                req.batch_id,
+                batch.deadline
+            ),
+        ));
+    }
+
+    let outstanding = voucher_statuses(&client, &batch.vouchers)
+        .await?
+        .into_iter()
+        .filter(|status| is_outstanding(&status.state) && !status.redeemed);
+    let reclaims = join_all(outstanding.map(|status| reclaim_spend(&client, status.operation_id)))
+        .await
+        .into_iter()
+        .collect::<Result<Vec<_>, _>>()?;
+
+    let vouchers = voucher_statuses(&client, &batch.vouchers).await?;
+
+    Ok(ReclaimVouchersResponse {
+        batch_id: req.batch_id,
+        reclaimed_msat: reclaims.iter().map(|reclaim| reclaim.amount_msat).sum(),
+        reclaims,
+        totals: VoucherTotals::new(&batch, &vouchers),
+    })
+}
+
+pub async fn handle_ws(state: AppState, v: Value) -> Result<Value, AppError> {
+    let v = serde_json::from_value::<ReclaimVouchersRequest>(v)
+        .map_err(|e| AppError::new(StatusCode::BAD_REQUEST, anyhow!("Invalid request: {}", e)))?;
+    let client = state.get_client(v.federation_id).await?;
+    let reclaim = _reclaim_vouchers(client, v).await?;
+    let reclaim_json = json!(reclaim);
+    Ok(reclaim_json)
+}
+
+#[axum_macros::debug_handler]
+pub async fn handle_rest(
+    State(state): State<AppState>,
+    Json(req): Json<ReclaimVouchersRequest>,
+) -> Result<Json<ReclaimVouchersResponse>, AppError> {
+    let client = state.get_client(req.federation_id).await?;
+    let reclaim = _reclaim_vouchers(client, req).await?;
+    Ok(Json(reclaim))
+}
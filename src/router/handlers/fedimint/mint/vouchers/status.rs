@@ -0,0 +1,107 @@
+use anyhow::anyhow;
+use axum::extract::State;
+use axum::http::StatusCode;
+use axum::Json;
+use fedimint_client::ClientArc;
+use fedimint_core::config::FederationId;
+use fedimint_core::core::OperationId;
+use fedimint_core::db::IDatabaseTransactionOpsCoreTyped;
+use fedimint_core::Amount;
+use futures_util::StreamExt;
+use serde::{Deserialize, Serialize};
+use serde_json::{json, Value};
+
+use super::{get_batch, voucher_statuses, VoucherStatus, VoucherTotals};
+use crate::db::{VoucherBatch, VoucherBatchKeyPrefix};
+use crate::error::AppError;
+use crate::state::AppState;
+
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct VoucherStatusRequest {
+    /// Batch to report on, all batches if unset
+    pub batch_id: Option<OperationId>,
+    pub federation_id: Option<FederationId>,
+}
+
+#[derive(Debug, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct BatchStatus {
+    pub batch_id: OperationId,
+    pub amount_msat: Amount,
+    pub description: Option<String>,
+    pub created_at: u64,
+    pub deadline: u64,
+    pub totals: VoucherTotals,
+    pub vouchers: Vec<VoucherStatus>,
+}
+
+#[derive(Debug, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct VoucherStatusResponse {
+    pub batches: Vec<BatchStatus>,
+}
+
+async fn _voucher_status(
+    client: ClientArc,
+    req: VoucherStatusRequest,
+) -> Result<VoucherStatusResponse, AppError> {
+    let batches = match req.batch_id {
+        Some(batch_id) => vec![(batch_id, get_batch(&client, batch_id).await?)],
+        None => {
+            client
+                .db()
+                .begin_transaction_nc()
+                .await
+                .find_by_prefix(&VoucherBatchKeyPrefix)
+                .await
+                .map(|(key, batch)| (key.0, batch))
+                .collect::<Vec<_>>()
+                .await
+        }
+    };
+
+    let mut statuses = Vec::with_capacity(batches.len());
+    for (batch_id, batch) in batches {
+        statuses.push(batch_status(&client, batch_id, batch).await?);
+    }
+    statuses.sort_by_key(|status| status.created_at);
+
+    Ok(VoucherStatusResponse { batches: statuses })
+}
+
+async fn batch_status(
+    client: &ClientArc,
+    batch_id: OperationId,
+    batch: VoucherBatch,
+) -> Result<BatchStatus, AppError> {
+    let vouchers = voucher_statuses(client, &batch.vouchers).await?;
+    Ok(BatchStatus {
+        batch_id,
+        totals: VoucherTotals::new(&batch, &vouchers),
+        amount_msat: batch.amount,
+        description: batch.description,
+        created_at: batch.created_at,
+        deadline: batch.deadline,
+        vouchers,
+    })
+}
+
+pub async fn handle_ws(state: AppState, v: Value) -> Result<Value, AppError> {
+    let v = serde_json::from_value::<VoucherStatusRequest>(v)
+        .map_err(|e| AppError::new(StatusCode::BAD_REQUEST, anyhow!("Invalid request: {}", e)))?;
+    let client = state.get_client(v.federation_id).await?;
+    let status = _voucher_status(client, v).await?;
+    let status_json = json!(status);
+    Ok(status_json)
+}
+
+#[axum_macros::debug_handler]
+pub async fn handle_rest(
+    State(state): State<AppState>,
+    Json(req): Json<VoucherStatusRequest>,
+) -> Result<Json<VoucherStatusResponse>, AppError> {
+    let client = state.get_client(req.federation_id).await?;
+    let status = _voucher_status(client, req).await?;
+    Ok(Json(status))
+}
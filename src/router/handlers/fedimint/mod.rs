@@ -1,4 +1,5 @@
 pub mod admin;
+pub mod bridge;
 pub mod ln;
 pub mod mint;
 pub mod wallet;
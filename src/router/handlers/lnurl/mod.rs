@@ -0,0 +1,189 @@
+use std::str::FromStr;
+
+use anyhow::anyhow;
+use axum::body::{Body, Bytes};
+use axum::extract::{FromRequest, Request};
+use axum::http::StatusCode;
+use axum::middleware::Next;
+use axum::response::{IntoResponse, Response};
+use axum::Json;
+use clap::Args;
+use fedimint_client::ClientArc;
+use serde::Serialize;
+use url::Url;
+
+use crate::error::{AppError, ErrorSource};
+use crate::state::AppState;
+
+pub mod pay;
+pub mod withdraw;
+
+/// Status of LUD-06 error responses
+const ERROR_STATUS: &str = "ERROR";
+
+/// A username that can be paid through `/.well-known/lnurlp/{username}`,
+/// configured as `username` or `username:federation`
+#[derive(Debug, Clone)]
+pub struct LnurlUser {
+    pub username: String,
+    /// Id or id prefix of the federation receiving the payments, the default
+    /// federation when unset
+    pub federation: Option<String>,
+}
+
+impl FromStr for LnurlUser {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let (username, federation) = match s.split_once(':') {
+            Some((username, federation)) => (username, Some(federation.trim().to_string())),
+            None => (s, None),
+        };
+        let username = username.trim().to_lowercase();
+        if username.is_empty()
+            || !username
+                .chars()
+                .all(|c| c.is_ascii_alphanumeric() || matches!(c, '-' | '_' | '.'))
+        {
+            return Err(anyhow!("Invalid LNURL username: {}", username));
+        }
+        Ok(LnurlUser {
+            username,
+            federation,
+        })
+    }
+}
+
+/// Lightning Address and LNURL endpoints served without authentication
+#[derive(Debug, Clone, Args)]
+pub struct LnurlConfig {
+    /// Comma separated usernames that can receive payments, each optionally
+    /// followed by `:` and the federation id or id prefix receiving them
+    #[clap(long = "lnurl-users", env = "LNURL_USERS", value_delimiter = ',')]
+    pub users: Vec<LnurlUser>,
+
+    /// Public url of this server, the callbacks are served under it and the
+    /// Lightning Addresses use its domain
+    #[clap(long = "lnurl-public-url", env = "LNURL_PUBLIC_URL")]
+    pub public_url: Option<Url>,
+
+    /// Smallest amount in msat a Lightning Address accepts
+    #[clap(
+        long = "lnurl-min-sendable",
+        env = "LNURL_MIN_SENDABLE",
+        default_value_t = 1000
+    )]
+    pub min_sendable: u64,
+
+    /// Largest amount in msat a Lightning Address accepts
+    #[clap(
+        long = "lnurl-max-sendable",
+        env = "LNURL_MAX_SENDABLE",
+        default_value_t = 100_000_000_000
+    )]
+    pub max_sendable: u64,
+
+    /// Longest comment payers can attach to a payment, comments are disabled
+    /// when 0
+    #[clap(
+        long = "lnurl-comment-allowed",
+        env = "LNURL_COMMENT_ALLOWED",
+        default_value_t = 255
+    )]
+    pub comment_allowed: usize,
+}
+
+impl LnurlConfig {
+    pub fn user(&self, username: &str) -> Result<&LnurlUser, AppError> {
+        let username = username.to_lowercase();
+        self.users
+            .iter()
+            .find(|user| user.username == username)
+            .ok_or_else(|| {
+                AppError::new(StatusCode::NOT_FOUND, anyhow!("Unknown user {}", username))
+            })
+    }
+
+    /// Returns the public url of `path`, LNURL endpoints can only be served
+    /// once the public url is configured
+    pub fn url(&self, path: &str) -> Result<Url, AppError> {
+        let public_url = self.public_url.as_ref().ok_or_else(|| {
+            AppError::new(
+                StatusCode::SERVICE_UNAVAILABLE,
+                anyhow!("LNURL_PUBLIC_URL is not configured"),
+            )
+        })?;
+        let url = format!(
+            "{}/{}",
+            public_url.as_str().trim_end_matches('/'),
+            path.trim_start_matches('/')
+        );
+        Url::parse(&url).map_err(|e| AppError::new(StatusCode::INTERNAL_SERVER_ERROR, e))
+    }
+
+    /// Domain of the Lightning Addresses
+    pub fn domain(&self) -> Result<String, AppError> {
+        self.url("/")?
+            .host_str()
+            .map(str::to_string)
+            .ok_or_else(|| {
+                AppError::new(
+                    StatusCode::INTERNAL_SERVER_ERROR,
+                    anyhow!("LNURL_PUBLIC_URL has no host"),
+                )
+            })
+    }
+}
+
+/// Returns the client of the federation receiving `user`'s payments
+pub async fn user_client(state: &AppState, user: &LnurlUser) -> Result<ClientArc, AppError> {
+    let federation_id = match &user.federation {
+        Some(federation) => Some(state.find_federation(federation).await.ok_or_else(|| {
+            AppError::new(
+                StatusCode::SERVICE_UNAVAILABLE,
+                anyhow!("Not joined to federation {}", federation),
+            )
+        })?),
+        None => None,
+    };
+    state.get_client(federation_id).await
+}
+
+#[derive(Debug, Serialize)]
+pub struct ErrorResponse {
+    pub status: String,
+    pub reason: String,
+}
+
+/// Renders error responses of the LNURL endpoints as LUD-06
+/// `{"status": "ERROR", "reason": ...}` objects
+pub async fn lnurl_errors(req: Request, next: Next) -> Response {
+    let response = next.run(req).await;
+    let status = response.status();
+    if !status.is_client_error() && !status.is_server_error() {
+        return response;
+    }
+
+    let (parts, body) = response.into_parts();
+    let reason = match parts.extensions.get::<ErrorSource>() {
+        Some(ErrorSource(error)) => error.to_string(),
+        None => error_body(body).await.unwrap_or_else(|| {
+            status
+                .canonical_reason()
+                .unwrap_or("Unknown error")
+                .to_string()
+        }),
+    };
+    let error = ErrorResponse {
+        status: ERROR_STATUS.to_string(),
+        reason,
+    };
+    (status, Json(error)).into_response()
+}
+
+async fn error_body(body: Body) -> Option<String> {
+    match Bytes::from_request(Request::new(body), &()).await {
+        Ok(bytes) if !bytes.is_empty() => Some(String::from_utf8_lossy(&bytes).into_owned()),
+        _ => None,
+    }
+}
@@ -0,0 +1,266 @@
+use anyhow::anyhow;
+use axum::extract::{Path, Query, State};
+use axum::http::StatusCode;
+use axum::Json;
+use bitcoin::secp256k1::rand::thread_rng;
+use bitcoin::secp256k1::{PublicKey, Secp256k1, SecretKey};
+use bitcoin_hashes::{sha256, Hash};
+use fedimint_core::Amount;
+use fedimint_ln_client::LightningClientModule;
+use lightning_invoice::{Bolt11Invoice, InvoiceBuilder};
+use serde::{Deserialize, Serialize};
+use tracing::info;
+
+use super::{user_client, LnurlConfig, LnurlUser};
+use crate::error::AppError;
+use crate::state::AppState;
+
+const PAY_REQUEST_TAG: &str = "payRequest";
+
+/// Extra meta of the receive operations of LNURL payments, listed with the
+/// operation in `list-operations`
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct LnurlPayMeta {
+    pub username: String,
+    pub comment: Option<String>,
+}
+
+#[derive(Debug, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct PayRequestResponse {
+    pub tag: String,
+    pub callback: String,
+    pub min_sendable: u64,
+    pub max_sendable: u64,
+    pub metadata: String,
+    /// LUD-12 comment length, left out when comments are disabled
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub comment_allowed: Option<usize>,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct CallbackParams {
+    /// Amount in msat
+    pub amount: u64,
+    pub comment: Option<String>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct CallbackResponse {
+    pub pr: String,
+    pub routes: Vec<String>,
+}
+
+/// Text describing payments to `user`
+fn description(config: &LnurlConfig, user: &LnurlUser) -> Result<String, AppError> {
+    Ok(format!("Payment to {}@{}", user.username, config.domain()?))
+}
+
+/// LUD-06 metadata of `user`'s pay request
+fn metadata(config: &LnurlConfig, user: &LnurlUser) -> Result<String, AppError> {
+    let text = description(config, user)?;
+    let identifier = format!("{}@{}", user.username, config.domain()?);
+    let metadata = [
+        ["text/plain", text.as_str()],
+        ["text/identifier", identifier.as_str()],
+    ];
+    Ok(serde_json::to_string(&metadata)?)
+}
+
+fn callback_path(user: &LnurlUser) -> String {
+    format!("/lnurlp/{}/callback", user.username)
+}
+
+fn _pay_request(config: &LnurlConfig, username: &str) -> Result<PayRequestResponse, AppError> {
+    let user = config.user(username)?;
+    Ok(PayRequestResponse {
+        tag: PAY_REQUEST_TAG.to_string(),
+        callback: config.url(&callback_path(user))?.to_string(),
+        min_sendable: config.min_sendable,
+        max_sendable: config.max_sendable,
+        metadata: metadata(config, user)?,
+        comment_allowed: (config.comment_allowed > 0).then_some(config.comment_allowed),
+    })
+}
+
+async fn _callback(
+    state: &AppState,
+    username: &str,
+    params: CallbackParams,
+) -> Result<CallbackResponse, AppError> {
+    let config = &state.lnurl;
+    let user = config.user(username)?;
+    if params.amount < config.min_sendable || params.amount > config.max_sendable {
+        return Err(AppError::new(
+            StatusCode::BAD_REQUEST,
+            anyhow!(
+                "Amount must be between {} and {} msat",
+                config.min_sendable,
+                config.max_sendable
+            ),
+        ));
+    }
+    let comment = params.comment.filter(|comment| !comment.is_empty());
+    if comment
+        .as_ref()
+        .is_some_and(|comment| comment.chars().count() > config.comment_allowed)
+    {
+        return Err(AppError::new(
+            StatusCode::BAD_REQUEST,
+            anyhow!(
+                "Comment is longer than {} characters",
+                config.comment_allowed
+            ),
+        ));
+    }
+
+    let client = user_client(state, user).await?;
+    let lightning_module = client.get_first_module::<LightningClientModule>();
+    lightning_module.select_active_gateway().await?;
+    let (operation_id, invoice) = lightning_module
+        .create_bolt11_invoice(
+            Amount::from_msats(params.amount),
+            description(config, user)?,
+            None,
+            LnurlPayMeta {
+                username: user.username.clone(),
+                comment,
+            },
+        )
+        .await?;
+    let description_hash = sha256::Hash::hash(metadata(config, user)?.as_bytes());
+    let invoice = with_description_hash(&invoice, description_hash)?;
+    info!(
+        "Created invoice for {} msat to {}: {}",
+        params.amount, user.username, operation_id
+    );
+
+    Ok(CallbackResponse {
+        pr: invoice.to_string(),
+        routes: vec![],
+    })
+}
+
+/// Re-signs `invoice` with `description_hash` in place of its description, as
+/// LUD-06 requires. Fedimint 0.2 clients only create invoices with a plain
+/// description, but sign them with a throwaway node key and have the gateway
+/// intercept the payment by the route hint's channel id, so signing with
+/// another throwaway key does not change how the invoice is paid.
+fn with_description_hash(
+    invoice: &Bolt11Invoice,
+    description_hash: sha256::Hash,
+) -> Result<Bolt11Invoice, AppError> {
+    let secp = Secp256k1::new();
+    let node_key = SecretKey::new(&mut thread_rng());
+    let mut builder = InvoiceBuilder::new(invoice.currency())
+        .description_hash(description_hash)
+        .payment_hash(*invoice.payment_hash())
+        .payment_secret(*invoice.payment_secret())
+        .duration_since_epoch(invoice.duration_since_epoch())
+        .min_final_cltv_expiry_delta(invoice.min_final_cltv_expiry_delta())
+        .payee_pub_key(PublicKey::from_secret_key(&secp, &node_key))
+        .expiry_time(invoice.expiry_time());
+    if let Some(amount_msat) = invoice.amount_milli_satoshis() {
+        builder = builder.amount_milli_satoshis(amount_msat);
+    }
+    for route_hint in invoice.route_hints() {
+        builder = builder.private_route(route_hint);
+    }
+    builder
+        .build_signed(|hash| secp.sign_ecdsa_recoverable(hash, &node_key))
+        .map_err(|e| {
+            AppError::new(
+                StatusCode::INTERNAL_SERVER_ERROR,
+                anyhow!("Failed to sign invoice: {}", e),
+            )
+        })
+}
+
+#[axum_macros::debug_handler]
+pub async fn handle_pay_request(
+    State(state): State<AppState>,
+    Path(username): Path<String>,
+) -> Result<Json<PayRequestResponse>, AppError> {
+    let pay_request = _pay_request(&state.lnurl, &username)?;
+    Ok(Json(pay_request))
+}
+
+#[axum_macros::debug_handler]
+pub async fn handle_callback(
+    State(state): State<AppState>,
+    Path(username): Path<String>,
+    Query(params): Query<CallbackParams>,
+) -> Result<Json<CallbackResponse>, AppError> {
+    let callback = _callback(&state, &username, params).await?;
+    Ok(Json(callback))
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use lightning_invoice::{
+        Bolt11InvoiceDescription, Currency, PaymentSecret, RouteHint, RouteHintHop, RoutingFees,
+    };
+
+    use super::*;
+
+    fn invoice() -> Bolt11Invoice {
+        let secp = Secp256k1::new();
+        let node_key = SecretKey::from_slice(&[1; 32]).unwrap();
+        let gateway = PublicKey::from_secret_key(&secp, &SecretKey::from_slice(&[2; 32]).unwrap());
+        InvoiceBuilder::new(Currency::Regtest)
+            .amount_milli_satoshis(21_000)
+            .description("Payment to alice@example.com".to_string())
+            .payment_hash(sha256::Hash::hash(&[3; 32]))
+            .payment_secret(PaymentSecret([4; 32]))
+            .duration_since_epoch(Duration::from_secs(1_700_000_000))
+            .min_final_cltv_expiry_delta(18)
+            .payee_pub_key(PublicKey::from_secret_key(&secp, &node_key))
+            .expiry_time(Duration::from_secs(3600))
+            .private_route(RouteHint(vec![RouteHintHop {
+                src_node_id: gateway,
+                short_channel_id: 42,
+                fees: RoutingFees {
+                    base_msat: 0,
+                    proportional_millionths: 0,
+                },
+                cltv_expiry_delta: 30,
+                htlc_minimum_msat: None,
+                htlc_maximum_msat: None,
+            }]))
+            .build_signed(|hash| secp.sign_ecdsa_recoverable(hash, &node_key))
+            .unwrap()
+    }
+
+    #[test]
+    fn re_signed_invoice_commits_to_the_metadata() {
+        let invoice = invoice();
+        let hash = sha256::Hash::hash(br#"[["text/plain","Payment to alice@example.com"]]"#);
+
+        let re_signed = with_description_hash(&invoice, hash).unwrap();
+
+        assert!(re_signed.check_signature().is_ok());
+        assert!(matches!(
+            re_signed.description(),
+            Bolt11InvoiceDescription::Hash(description_hash) if description_hash.0 == hash
+        ));
+        assert_eq!(re_signed.payment_hash(), invoice.payment_hash());
+        assert_eq!(re_signed.payment_secret(), invoice.payment_secret());
+        assert_eq!(
+            re_signed.amount_milli_satoshis(),
+            invoice.amount_milli_satoshis()
+        );
+        assert_eq!(
+            re_signed.duration_since_epoch(),
+            invoice.duration_since_epoch()
+        );
+        assert_eq!(re_signed.expiry_time(), invoice.expiry_time());
+        assert_eq!(
+            re_signed.min_final_cltv_expiry_delta(),
+            invoice.min_final_cltv_expiry_delta()
+        );
+        assert_eq!(re_signed.route_hints(), invoice.route_hints());
+    }
+}
@@ -0,0 +1,114 @@
+use std::time::SystemTime;
+
+use anyhow::anyhow;
+use axum::extract::State;
+use axum::http::StatusCode;
+use axum::Json;
+use fedimint_client::ClientArc;
+use fedimint_core::config::FederationId;
+use fedimint_core::core::OperationId;
+use fedimint_core::db::IDatabaseTransactionOpsCoreTyped;
+use fedimint_core::Amount;
+use serde::Deserialize;
+use serde_json::{json, Value};
+use tracing::info;
+
+use super::WithdrawLinkResponse;
+use crate::db::{WithdrawLink, WithdrawLinkKey};
+use crate::error::AppError;
+use crate::state::AppState;
+use crate::utils::system_time_to_u64;
+
+const DEFAULT_DESCRIPTION: &str = "Withdraw from Fedimint";
+
+fn default_uses() -> u64 {
+    1
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct CreateWithdrawLinkRequest {
+    pub min_withdrawable_msat: Amount,
+    pub max_withdrawable_msat: Amount,
+    pub description: Option<String>,
+    /// Seconds the link can be used for
+    pub expiry_time: u64,
+    /// Withdrawals the link allows, 1 for a single use link
+    #[serde(default = "default_uses")]
+    pub uses: u64,
+    pub federation_id: Option<FederationId>,
+}
+
+async fn _create_withdraw_link(
+    state: &AppState,
+    client: ClientArc,
+    req: CreateWithdrawLinkRequest,
+) -> Result<WithdrawLinkResponse, AppError> {
+    if req.min_withdrawable_msat == Amount::ZERO
+        || req.min_withdrawable_msat > req.max_withdrawable_msat
+    {
+        return Err(AppError::new(
+            StatusCode::BAD_REQUEST,
+            anyhow!("Withdrawable amounts must be positive, with the minimum below the maximum"),
+        ));
+    }
+    if req.uses == 0 {
+        return Err(AppError::new(
+            StatusCode::BAD_REQUEST,
+            anyhow!("A link has to allow at least one withdrawal"),
+        ));
+    }
+    // Fail before anything is stored if the link could not be handed out
+    state.lnurl.url("/")?;
+
+    let k1 = OperationId::new_random();
+    let created_at = system_time_to_u64(SystemTime::now())?;
+    let expiry = created_at.checked_add(req.expiry_time).ok_or_else(|| {
+        AppError::new(
+            StatusCode::BAD_REQUEST,
+            anyhow!("Expiry time of {} seconds is too long", req.expiry_time),
+        )
+    })?;
+    let link = WithdrawLink {
+        min_withdrawable: req.min_withdrawable_msat,
+        max_withdrawable: req.max_withdrawable_msat,
+        description: req
+            .description
+            .unwrap_or_else(|| DEFAULT_DESCRIPTION.to_string()),
+        created_at,
+        expiry,
+        uses: req.uses,
+        used: 0,
+        payments: vec![],
+        pending: vec![],
+        revoked: false,
+    };
+    let mut dbtx = client.db().begin_transaction().await;
+    dbtx.insert_entry(&WithdrawLinkKey(k1), &link).await;
+    dbtx.commit_tx_result().await?;
+    info!(
+        "Created withdraw link {k1} for {} to {}, {} uses",
+        link.min_withdrawable, link.max_withdrawable, link.uses
+    );
+
+    WithdrawLinkResponse::new(&state.lnurl, k1, link)
+}
+
+pub async fn handle_ws(state: AppState, v: Value) -> Result<Value, AppError> {
+    let v = serde_json::from_value::<CreateWithdrawLinkRequest>(v)
+        .map_err(|e| AppError::new(StatusCode::BAD_REQUEST, anyhow!("Invalid request: {}", e)))?;
+    let client = state.get_client(v.federation_id).await?;
+    let link = _create_withdraw_link(&state, client, v).await?;
+    let link_json = json!(link);
+    Ok(link_json)
+}
+
+#[axum_macros::debug_handler]
+pub async fn handle_rest(
+    State(state): State<AppState>,
+    Json(req): Json<CreateWithdrawLinkRequest>,
+) -> Result<Json<WithdrawLinkResponse>, AppError> {
+    let client = state.get_client(req.federation_id).await?;
+    let link = _create_withdraw_link(&state, client, req).await?;
+    Ok(Json(link))
+}
@@ -0,0 +1,81 @@
+use std::time::SystemTime;
+
+use anyhow::anyhow;
+use axum::extract::State;
+use axum::http::StatusCode;
+use axum::Json;
+use fedimint_core::config::FederationId;
+use fedimint_core::db::IDatabaseTransactionOpsCoreTyped;
+use futures_util::StreamExt;
+use serde::{Deserialize, Serialize};
+use serde_json::{json, Value};
+
+use super::WithdrawLinkResponse;
+use crate::db::WithdrawLinkKeyPrefix;
+use crate::error::AppError;
+use crate::state::AppState;
+use crate::utils::system_time_to_u64;
+
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct ListWithdrawLinksRequest {
+    /// Also list used up, expired and revoked links
+    #[serde(default)]
+    pub include_inactive: bool,
+    /// Only list the links of this federation
+    pub federation_id: Option<FederationId>,
+}
+
+#[derive(Debug, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct ListWithdrawLinksResponse {
+    pub links: Vec<WithdrawLinkResponse>,
+}
+
+async fn _list_withdraw_links(
+    state: &AppState,
+    req: ListWithdrawLinksRequest,
+) -> Result<ListWithdrawLinksResponse, AppError> {
+    let clients = match req.federation_id {
+        Some(federation_id) => vec![state.get_client(Some(federation_id)).await?],
+        None => state.multimint.all().await,
+    };
+
+    let now = system_time_to_u64(SystemTime::now())?;
+    let mut links = Vec::new();
+    for client in clients {
+        let federation_links = client
+            .db()
+            .begin_transaction_nc()
+            .await
+            .find_by_prefix(&WithdrawLinkKeyPrefix)
+            .await
+            .collect::<Vec<_>>()
+            .await;
+        for (key, link) in federation_links {
+            if req.include_inactive || link.is_active(now) {
+                links.push(WithdrawLinkResponse::new(&state.lnurl, key.0, link)?);
+            }
+        }
+    }
+    links.sort_by_key(|link| link.created_at);
+
+    Ok(ListWithdrawLinksResponse { links })
+}
+
+pub async fn handle_ws(state: AppState, v: Value) -> Result<Value, AppError> {
+    let v = serde_json::from_value::<ListWithdrawLinksRequest>(v)
+        .map_err(|e| AppError::new(StatusCode::BAD_REQUEST, anyhow!("Invalid request: {}", e)))?;
+    let links = _list_withdraw_links(&state, v).await?;
+    let links_json = json!(links);
+    Ok(links_json)
+}
+
+#[axum_macros::debug_handler]
+pub async fn handle_rest(
+    State(state): State<AppState>,
+    Json(req): Json<ListWithdrawLinksRequest>,
+) -> Result<Json<ListWithdrawLinksResponse>, AppError> {
+    let links = _list_withdraw_links(&state, req).await?;
+    Ok(Json(links))
+}
@@ -0,0 +1,391 @@
+use std::str::FromStr;
+use std::time::SystemTime;
+
+use anyhow::anyhow;
+use axum::extract::{Path, Query, State};
+use axum::http::StatusCode;
+use axum::Json;
+use fedimint_client::ClientArc;
+use fedimint_core::core::OperationId;
+use fedimint_core::db::IDatabaseTransactionOpsCoreTyped;
+use fedimint_core::Amount;
+use fedimint_ln_client::{
+    InternalPayState, LightningClientModule, LnPayState, OutgoingLightningPayment, PayType,
+};
+use futures_util::StreamExt;
+use lightning_invoice::Bolt11Invoice;
+use serde::{Deserialize, Serialize};
+use tracing::{info, warn};
+
+use super::LnurlConfig;
+use crate::db::{WithdrawLink, WithdrawLinkKey, WithdrawLinkKeyPrefix};
+use crate::error::AppError;
+use crate::state::AppState;
+use crate::utils::{spawn_follower, system_time_to_u64};
+
+pub mod create;
+pub mod list;
+pub mod revoke;
+
+const WITHDRAW_REQUEST_TAG: &str = "withdrawRequest";
+const OK_STATUS: &str = "OK";
+const CALLBACK_PATH: &str = "/lnurlw/callback";
+
+/// Extra meta of the payments made for withdraw links
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct LnurlWithdrawMeta {
+    pub k1: OperationId,
+}
+
+/// A withdraw link as listed through the admin API
+#[derive(Debug, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct WithdrawLinkResponse {
+    pub k1: OperationId,
+    /// Bech32 encoded LNURL of the link
+    pub lnurl: String,
+    pub url: String,
+    pub min_withdrawable_msat: Amount,
+    pub max_withdrawable_msat: Amount,
+    pub description: String,
+    pub created_at: u64,
+    pub expiry: u64,
+    pub uses: u64,
+    pub used: u64,
+    pub payments: Vec<OperationId>,
+    pub pending_payments: Vec<OperationId>,
+    pub revoked: bool,
+    pub active: bool,
+}
+
+impl WithdrawLinkResponse {
+    pub fn new(
+        config: &LnurlConfig,
+        k1: OperationId,
+        link: WithdrawLink,
+    ) -> Result<Self, AppError> {
+        let url = config.url(&format!("/lnurlw/{k1}"))?.to_string();
+        Ok(Self {
+            k1,
+            lnurl: lnurl::lnurl::LnUrl::from_url(url.clone()).encode(),
+            url,
+            active: link.is_active(system_time_to_u64(SystemTime::now())?),
+            min_withdrawable_msat: link.min_withdrawable,
+            max_withdrawable_msat: link.max_withdrawable,
+            description: link.description,
+            created_at: link.created_at,
+            expiry: link.expiry,
+            uses: link.uses,
+            used: link.used,
+            payments: link.payments,
+            pending_payments: link
+                .pending
+                .iter()
+                .map(|payment| payment.operation_id())
+                .collect(),
+            revoked: link.revoked,
+        })
+    }
+}
+
+fn parse_k1(k1: &str) -> Result<OperationId, AppError> {
+    k1.parse()
+        .map_err(|e| AppError::new(StatusCode::BAD_REQUEST, anyhow!("Invalid k1 {}: {}", k1, e)))
+}
+
+/// Finds the client of the federation a withdraw link pays out from
+pub async fn find_link(
+    state: &AppState,
+    k1: OperationId,
+) -> Result<(ClientArc, WithdrawLink), AppError> {
+    for client in state.multimint.all().await {
+        let link = client
+            .db()
+            .begin_transaction_nc()
+            .await
+            .get_value(&WithdrawLinkKey(k1))
+            .await;
+        if let Some(link) = link {
+            return Ok((client, link));
+        }
+    }
+
+    Err(AppError::new(
+        StatusCode::NOT_FOUND,
+        anyhow!("Withdraw link {} not found", k1),
+    ))
+}
+
+fn ensure_active(link: &WithdrawLink) -> Result<(), AppError> {
+    if link.is_active(system_time_to_u64(SystemTime::now())?) {
+        return Ok(());
+    }
+    let reason = if link.revoked {
+        "has been revoked"
+    } else if link.used >= link.uses {
+        "has already been used"
+    } else {
+        "has expired"
+    };
+    Err(AppError::new(
+        StatusCode::BAD_REQUEST,
+        anyhow!("Withdraw link {}", reason),
+    ))
+}
+
+/// Counts a withdrawal against the link. Runs in one transaction, so
+/// concurrent withdrawals cannot use a link more often than allowed.
+async fn start_withdrawal(client: &ClientArc, k1: OperationId) -> Result<(), AppError> {
+    let mut dbtx = client.db().begin_transaction().await;
+    let mut link = dbtx.get_value(&WithdrawLinkKey(k1)).await.ok_or_else(|| {
+        AppError::new(
+            StatusCode::NOT_FOUND,
+            anyhow!("Withdraw link {} not found", k1),
+        )
+    })?;
+    ensure_active(&link)?;
+    link.used += 1;
+    dbtx.insert_entry(&WithdrawLinkKey(k1), &link).await;
+    dbtx.commit_tx_result().await.map_err(|_| {
+        AppError::new(
+            StatusCode::CONFLICT,
+            anyhow!("Withdraw link is being used concurrently"),
+        )
+    })?;
+    Ok(())
+}
+
+/// Records a payment that went out for a withdrawal as pending until it is
+/// known whether it succeeded
+async fn start_payment(
+    client: &ClientArc,
+    k1: OperationId,
+    payment: PayType,
+) -> Result<(), AppError> {
+    let mut dbtx = client.db().begin_transaction().await;
+    if let Some(mut link) = dbtx.get_value(&WithdrawLinkKey(k1)).await {
+        link.pending.push(payment);
+        dbtx.insert_entry(&WithdrawLinkKey(k1), &link).await;
+    }
+    dbtx.commit_tx_result().await?;
+    Ok(())
+}
+
+/// Records the payment of a withdrawal, or gives the use back if the invoice
+/// could not be paid. `attempt` is the settled payment, which is no longer
+/// pending either way; settling it twice has no effect.
+async fn finish_withdrawal(
+    client: &ClientArc,
+    k1: OperationId,
+    attempt: Option<OperationId>,
+    payment: Option<OperationId>,
+) -> Result<(), AppError> {
+    let mut dbtx = client.db().begin_transaction().await;
+    let link = dbtx.get_value(&WithdrawLinkKey(k1)).await;
+    let unsettled = |link: &WithdrawLink| {
+        attempt.is_none()
+            || link
+                .pending
+                .iter()
+                .any(|payment| Some(payment.operation_id()) == attempt)
+    };
+    if let Some(mut link) = link.filter(unsettled) {
+        link.pending
+            .retain(|payment| Some(payment.operation_id()) != attempt);
+        match payment {
+            Some(operation_id) => link.payments.push(operation_id),
+            None => link.used = link.used.saturating_sub(1),
+        }
+        dbtx.insert_entry(&WithdrawLinkKey(k1), &link).await;
+    }
+    dbtx.commit_tx_result().await?;
+    Ok(())
+}
+
+/// Follows a withdrawal's payment in the background and settles the
+/// withdrawal once the payment succeeded or failed
+fn follow_withdrawal(client: &ClientArc, k1: OperationId, payment: PayType) {
+    let client = client.clone();
+    let operation_id = payment.operation_id();
+    spawn_follower(operation_id, async move {
+        let lightning_module = client.get_first_module::<LightningClientModule>();
+        let mut outcomes = match payment {
+            PayType::Internal(operation_id) => lightning_module
+                .subscribe_internal_pay(operation_id)
+                .await?
+                .into_stream()
+                .map(|update| match update {
+                    InternalPayState::Preimage(_) => Some(true),
+                    InternalPayState::Funding => None,
+                    _ => Some(false),
+                })
+                .boxed(),
+            PayType::Lightning(operation_id) => lightning_module
+                .subscribe_ln_pay(operation_id)
+                .await?
+                .into_stream()
+                .map(|update| match update {
+                    LnPayState::Success { .. } => Some(true),
+                    LnPayState::Canceled
+                    | LnPayState::Refunded { .. }
+                    | LnPayState::UnexpectedError { .. } => Some(false),
+                    _ => None,
+                })
+                .boxed(),
+        };
+
+        while let Some(outcome) = outcomes.next().await {
+            let Some(paid) = outcome else {
+                continue;
+            };
+            let payment = paid.then_some(operation_id);
+            finish_withdrawal(&client, k1, Some(operation_id), payment)
+                .await
+                .map_err(|e| e.error)?;
+            if paid {
+                info!("Withdrawal {operation_id} through link {k1} succeeded");
+            } else {
+                warn!("Withdrawal {operation_id} through link {k1} failed, use given back");
+            }
+            break;
+        }
+        Ok(())
+    });
+}
+
+/// Follows the payments of withdrawals that were still in flight when the
+/// server stopped
+pub async fn resume_withdrawals(state: &AppState) {
+    for client in state.multimint.all().await {
+        let links = client
+            .db()
+            .begin_transaction_nc()
+            .await
+            .find_by_prefix(&WithdrawLinkKeyPrefix)
+            .await
+            .collect::<Vec<_>>()
+            .await;
+        for (key, link) in links {
+            for payment in link.pending {
+                follow_withdrawal(&client, key.0, payment);
+            }
+        }
+    }
+}
+
+#[derive(Debug, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct WithdrawRequestResponse {
+    pub tag: String,
+    pub callback: String,
+    pub k1: OperationId,
+    pub default_description: String,
+    pub min_withdrawable: u64,
+    pub max_withdrawable: u64,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct CallbackParams {
+    pub k1: String,
+    pub pr: String,
+}
+
+#[derive(Debug, Serialize)]
+pub struct CallbackResponse {
+    pub status: String,
+}
+
+async fn _withdraw_request(
+    state: &AppState,
+    k1: &str,
+) -> Result<WithdrawRequestResponse, AppError> {
+    let k1 = parse_k1(k1)?;
+    let (_, link) = find_link(state, k1).await?;
+    ensure_active(&link)?;
+    Ok(WithdrawRequestResponse {
+        tag: WITHDRAW_REQUEST_TAG.to_string(),
+        callback: state.lnurl.url(CALLBACK_PATH)?.to_string(),
+        k1,
+        default_description: link.description,
+        min_withdrawable: link.min_withdrawable.msats,
+        max_withdrawable: link.max_withdrawable.msats,
+    })
+}
+
+async fn _callback(state: &AppState, params: CallbackParams) -> Result<CallbackResponse, AppError> {
+    let k1 = parse_k1(&params.k1)?;
+    let (client, link) = find_link(state, k1).await?;
+    let invoice = Bolt11Invoice::from_str(params.pr.trim())
+        .map_err(|e| AppError::new(StatusCode::BAD_REQUEST, anyhow!("Invalid invoice: {}", e)))?;
+    let amount = invoice
+        .amount_milli_satoshis()
+        .map(Amount::from_msats)
+        .ok_or_else(|| {
+            AppError::new(
+                StatusCode::BAD_REQUEST,
+                anyhow!("Invoices without an amount are not supported"),
+            )
+        })?;
+    if amount < link.min_withdrawable || amount > link.max_withdrawable {
+        return Err(AppError::new(
+            StatusCode::BAD_REQUEST,
+            anyhow!(
+                "Amount must be between {} and {}",
+                link.min_withdrawable,
+                link.max_withdrawable
+            ),
+        ));
+    }
+
+    start_withdrawal(&client, k1).await?;
+    let lightning_module = client.get_first_module::<LightningClientModule>();
+    let payment = match lightning_module.select_active_gateway().await {
+        Ok(_) => {
+            lightning_module
+                .pay_bolt11_invoice(invoice, LnurlWithdrawMeta { k1 })
+                .await
+        }
+        Err(e) => Err(e),
+    };
+    let OutgoingLightningPayment { payment_type, .. } = match payment {
+        Ok(payment) => payment,
+        Err(e) => {
+            if let Err(refund) = finish_withdrawal(&client, k1, None, None).await {
+                warn!(
+                    "Failed to give back the use of withdraw link {k1}: {}",
+                    refund.error
+                );
+            }
+            return Err(e.into());
+        }
+    };
+    start_payment(&client, k1, payment_type).await?;
+    follow_withdrawal(&client, k1, payment_type);
+    info!(
+        "Withdrawing {amount} through link {k1}: {}",
+        payment_type.operation_id()
+    );
+
+    Ok(CallbackResponse {
+        status: OK_STATUS.to_string(),
+    })
+}
+
+#[axum_macros::debug_handler]
+pub async fn handle_withdraw_request(
+    State(state): State<AppState>,
+    Path(k1): Path<String>,
+) -> Result<Json<WithdrawRequestResponse>, AppError> {
+    let withdraw_request = _withdraw_request(&state, &k1).await?;
+    Ok(Json(withdraw_request))
+}
+
+#[axum_macros::debug_handler]
+pub async fn handle_callback(
+    State(state): State<AppState>,
+    Query(params): Query<CallbackParams>,
+) -> Result<Json<CallbackResponse>, AppError> {
+    let callback = _callback(&state, params).await?;
+    Ok(Json(callback))
+}
@@ -0,0 +1,56 @@
+use anyhow::anyhow;
+use axum::extract::State;
+use axum::http::StatusCode;
+use axum::Json;
+use fedimint_core::core::OperationId;
+use fedimint_core::db::IDatabaseTransactionOpsCoreTyped;
+use serde::Deserialize;
+use serde_json::{json, Value};
+use tracing::info;
+
+use super::{find_link, WithdrawLinkResponse};
+use crate::db::WithdrawLinkKey;
+use crate::error::AppError;
+use crate::state::AppState;
+
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct RevokeWithdrawLinkRequest {
+    pub k1: OperationId,
+}
+
+async fn _revoke_withdraw_link(
+    state: &AppState,
+    req: RevokeWithdrawLinkRequest,
+) -> Result<WithdrawLinkResponse, AppError> {
+    let (client, _) = find_link(state, req.k1).await?;
+
+    let mut dbtx = client.db().begin_transaction().await;
+    let mut link = dbtx
+        .get_value(&WithdrawLinkKey(req.k1))
+        .await
+        .ok_or_else(|| anyhow!("Withdraw link {} disappeared", req.k1))?;
+    link.revoked = true;
+    dbtx.insert_entry(&WithdrawLinkKey(req.k1), &link).await;
+    dbtx.commit_tx_result().await?;
+    info!("Revoked withdraw link {}", req.k1);
+
+    WithdrawLinkResponse::new(&state.lnurl, req.k1, link)
+}
+
+pub async fn handle_ws(state: AppState, v: Value) -> Result<Value, AppError> {
+    let v = serde_json::from_value::<RevokeWithdrawLinkRequest>(v)
+        .map_err(|e| AppError::new(StatusCode::BAD_REQUEST, anyhow!("Invalid request: {}", e)))?;
+    let link = _revoke_withdraw_link(&state, v).await?;
+    let link_json = json!(link);
+    Ok(link_json)
+}
+
+#[axum_macros::debug_handler]
+pub async fn handle_rest(
+    State(state): State<AppState>,
+    Json(req): Json<RevokeWithdrawLinkRequest>,
+) -> Result<Json<WithdrawLinkResponse>, AppError> {
+    let link = _revoke_withdraw_link(&state, req).await?;
+    Ok(Json(link))
+}
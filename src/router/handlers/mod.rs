@@ -1,2 +1,3 @@
 pub mod cashu;
 pub mod fedimint;
+pub mod lnurl;
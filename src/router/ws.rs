@@ -54,11 +54,29 @@ pub enum JsonRpcMethod {
     AdminModule,
     AdminRestore,
     AdminListOperations,
+    BridgeRedeem,
+    BridgePay,
+    LnurlWithdrawLink,
+    LnurlWithdrawLinks,
+    LnurlRevokeWithdrawLink,
     MintReissue,
+    MintReissueBatch,
+    MintAwaitReissue,
     MintSpend,
+    MintSpends,
+    MintReclaim,
     MintValidate,
+    MintValidateOffline,
     MintSplit,
     MintCombine,
+    MintConvert,
+    MintConsolidate,
+    MintVouchers,
+    MintVoucherStatus,
+    MintReclaimVouchers,
+    MintSendEncrypted,
+    MintReceiveEncrypted,
+    MintReceivingKey,
     LnInvoice,
     LnAwaitInvoice,
     LnPay,
@@ -168,19 +186,73 @@ async fn match_method(req: JsonRpcRequest, state: AppState) -> Result<Value, App
         JsonRpcMethod::AdminListOperations => {
             handlers::fedimint::admin::list_operations::handle_ws(state.clone(), req.params).await
         }
+        JsonRpcMethod::BridgeRedeem => {
+            handlers::fedimint::bridge::redeem::handle_ws(state.clone(), req.params).await
+        }
+        JsonRpcMethod::BridgePay => {
+            handlers::fedimint::bridge::pay::handle_ws(state.clone(), req.params).await
+        }
+        JsonRpcMethod::LnurlWithdrawLink => {
+            handlers::lnurl::withdraw::create::handle_ws(state.clone(), req.params).await
+        }
+        JsonRpcMethod::LnurlWithdrawLinks => {
+            handlers::lnurl::withdraw::list::handle_ws(state.clone(), req.params).await
+        }
+        JsonRpcMethod::LnurlRevokeWithdrawLink => {
+            handlers::lnurl::withdraw::revoke::handle_ws(state.clone(), req.params).await
+        }
         JsonRpcMethod::MintReissue => {
             handlers::fedimint::mint::reissue::handle_ws(state.clone(), req.params).await
         }
+        JsonRpcMethod::MintReissueBatch => {
+            handlers::fedimint::mint::reissue_batch::handle_ws(state.clone(), req.params).await
+        }
+        JsonRpcMethod::MintAwaitReissue => {
+            handlers::fedimint::mint::await_reissue::handle_ws(state.clone(), req.params).await
+        }
         JsonRpcMethod::MintSpend => {
             handlers::fedimint::mint::spend::handle_ws(state.clone(), req.params).await
         }
+        JsonRpcMethod::MintSpends => {
+            handlers::fedimint::mint::spends::handle_ws(state.clone(), req.params).await
+        }
+        JsonRpcMethod::MintReclaim => {
+            handlers::fedimint::mint::reclaim::handle_ws(state.clone(), req.params).await
+        }
         JsonRpcMethod::MintValidate => {
             handlers::fedimint::mint::validate::handle_ws(state.clone(), req.params).await
         }
+        JsonRpcMethod::MintValidateOffline => {
+            handlers::fedimint::mint::validate_offline::handle_ws(state.clone(), req.params).await
+        }
         JsonRpcMethod::MintSplit => handlers::fedimint::mint::split::handle_ws(req.params).await,
         JsonRpcMethod::MintCombine => {
             handlers::fedimint::mint::combine::handle_ws(req.params).await
         }
+        JsonRpcMethod::MintConvert => {
+            handlers::fedimint::mint::convert::handle_ws(state.clone(), req.params).await
+        }
+        JsonRpcMethod::MintConsolidate => {
+            handlers::fedimint::mint::consolidate::handle_ws(state.clone(), req.params).await
+        }
+        JsonRpcMethod::MintVouchers => {
+            handlers::fedimint::mint::vouchers::create::handle_ws(state.clone(), req.params).await
+        }
+        JsonRpcMethod::MintVoucherStatus => {
+            handlers::fedimint::mint::vouchers::status::handle_ws(state.clone(), req.params).await
+        }
+        JsonRpcMethod::MintReclaimVouchers => {
+            handlers::fedimint::mint::vouchers::reclaim::handle_ws(state.clone(), req.params).await
+        }
+        JsonRpcMethod::MintSendEncrypted => {
+            handlers::fedimint::mint::encrypted::send::handle_ws(state.clone(), req.params).await
+        }
+        JsonRpcMethod::MintReceiveEncrypted => {
+            handlers::fedimint::mint::encrypted::receive::handle_ws(state.clone(), req.params).await
+        }
+        JsonRpcMethod::MintReceivingKey => {
+            handlers::fedimint::mint::encrypted::key::handle_ws(state.clone(), req.params).await
+        }
         JsonRpcMethod::LnInvoice => {
             handlers::fedimint::ln::invoice::handle_ws(state.clone(), req.params).await
         }
@@ -1,4 +1,5 @@
 use std::path::PathBuf;
+use std::sync::Arc;
 
 use anyhow::{anyhow, Result};
 use axum::http::StatusCode;
@@ -7,15 +8,27 @@ use fedimint_core::config::{FederationId, FederationIdPrefix};
 use multimint::MultiMint;
 
 use crate::error::AppError;
+use crate::router::handlers::cashu::error::CashuError;
+use crate::router::handlers::cashu::{keyset_id, CashuConfig, Unit};
+use crate::router::handlers::fedimint::mint::validate_offline::ForeignFederations;
+use crate::router::handlers::lnurl::LnurlConfig;
 #[derive(Debug, Clone)]
 pub struct AppState {
     pub multimint: MultiMint,
+    pub cashu: Arc<CashuConfig>,
+    pub lnurl: Arc<LnurlConfig>,
+    pub foreign_federations: ForeignFederations,
 }
 
 impl AppState {
-    pub async fn new(fm_db_path: PathBuf) -> Result<Self> {
+    pub async fn new(fm_db_path: PathBuf, cashu: CashuConfig, lnurl: LnurlConfig) -> Result<Self> {
         let clients = MultiMint::new(fm_db_path).await?;
-        Ok(Self { multimint: clients })
+        Ok(Self {
+            multimint: clients,
+            cashu: Arc::new(cashu),
+            lnurl: Arc::new(lnurl),
+            foreign_federations: ForeignFederations::default(),
+        })
     }
 
     // Helper function to get a specific client from the state or default
@@ -51,4 +64,34 @@ impl AppState {
             )),
         }
     }
+
+    /// Finds a joined federation by its hex encoded id or id prefix
+    pub async fn find_federation(&self, id_or_prefix: &str) -> Option<FederationId> {
+        let id_or_prefix = id_or_prefix.to_ascii_lowercase();
+        self.multimint
+            .ids()
+            .await
+            .into_iter()
+            .find(|federation_id| federation_id.to_string().starts_with(&id_or_prefix))
+    }
+
+    /// Resolves a Cashu keyset id back to the client of the federation it was
+    /// derived from, together with the unit it denominates
+    pub async fn get_client_by_keyset_id(
+        &self,
+        keyset_id_str: &str,
+    ) -> Result<(ClientArc, Unit), AppError> {
+        for client in self.multimint.all().await {
+            for unit in Unit::ALL {
+                if keyset_id(&client, unit)? == keyset_id_str {
+                    return Ok((client, unit));
+                }
+            }
+        }
+
+        Err(AppError::new(
+            StatusCode::NOT_FOUND,
+            CashuError::UnknownKeyset(keyset_id_str.to_string()),
+        ))
+    }
 }
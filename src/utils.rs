@@ -1,6 +1,17 @@
+use std::collections::HashSet;
+use std::future::Future;
+use std::sync::Mutex;
 use std::time::{SystemTime, UNIX_EPOCH};
 
 use anyhow::{anyhow, Result};
+use fedimint_core::core::OperationId;
+use lazy_static::lazy_static;
+use tracing::warn;
+
+lazy_static! {
+    /// Operations whose update streams are drained by a background task
+    static ref FOLLOWED_OPERATIONS: Mutex<HashSet<OperationId>> = Mutex::new(HashSet::new());
+}
 
 // Helper function to convert SystemTime to u64
 pub fn system_time_to_u64(time: SystemTime) -> Result<u64> {
@@ -9,3 +20,28 @@ pub fn system_time_to_u64(time: SystemTime) -> Result<u64> {
         Err(_) => Err(anyhow!("some error")),
     }
 }
+
+/// Runs `follow` in the background unless the operation is already being
+/// followed. Followers drain an operation's update stream and record each
+/// state in the database, so readers never wait on the stream.
+pub fn spawn_follower<F>(operation_id: OperationId, follow: F)
+where
+    F: Future<Output = Result<()>> + Send + 'static,
+{
+    if !FOLLOWED_OPERATIONS
+        .lock()
+        .expect("not poisoned")
+        .insert(operation_id)
+    {
+        return;
+    }
+    tokio::spawn(async move {
+        if let Err(e) = follow.await {
+            warn!("Following operation {operation_id} failed: {e}");
+        }
+        FOLLOWED_OPERATIONS
+            .lock()
+            .expect("not poisoned")
+            .remove(&operation_id);
+    });
+}